@@ -16,30 +16,164 @@
 
 //! Rust Code wrapping Jaeger-Agent HTTP API
 
-use crate::{cli::App, primitives::RpcResponse};
+use crate::{
+	cli::App,
+	primitives::{check_errors, RpcResponse, TagFilter, Timestamp, TraceObject, REDACTED},
+};
 use anyhow::Error;
-use serde::Deserialize;
-use std::fmt;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use serde::{
+	de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor},
+	Deserialize, Deserializer,
+};
+use serde_json::value::RawValue;
+use std::{
+	cell::Cell,
+	collections::HashSet,
+	fmt,
+	io::{self, BufReader, Read},
+	str::FromStr,
+	sync::Arc,
+	time::{Duration, Instant},
+};
 
 /// Endpoints:
 ///
 /// `/api/traces`
 /// Params:
 ///     limit: specify how many to return
-///     service: Where did the trace originate
+///     service: Where did the trace originate, required by every search
+///     operation: only traces with spans of this operation of `service`
+///     tags: JSON object of tags the spans must have, like `{"candidate-stage":"3"}`
+///     minDuration, maxDuration: bounds of the duration of a span, like `100ms`. maxDuration must exceed minDuration
+///     start, end: time range of the traces, as Unix timestamps in microseconds
 ///     prettyPrint: Make JSON nice
-/// `/search` <-- have not gotten this to work `/api/traces/{TraceId}`
+///     The `/search` page of the Jaeger UI queries this endpoint with the same params.
+/// `/api/traces/{TraceId}`
 ///     return spans for this TraceId
 /// `/api/services`
 ///     returns services reporting to the jaeger agent
+/// `/api/services/{service}/operations`
+///     returns operations of a service
+/// `/api/dependencies`
+/// Params:
+///     endTs: Unix timestamp in milliseconds of the end of the window
+///     lookback: length of the window in milliseconds
+///     returns how often services called each other
 pub const TRACES: &str = "/api/traces";
 
 /// Returns list of services on this Jaeger agent
 pub const SERVICES: &str = "/api/services";
 
+/// Returns calls between services
+pub const DEPENDENCIES: &str = "/api/dependencies";
+
+/// Window of calls counted by the dependencies endpoint if no `--lookback` is given.
+pub const DEFAULT_DEPENDENCIES_LOOKBACK: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Largest response accepted from Jaeger if no other maximum is given, in bytes.
+pub const DEFAULT_MAX_RESPONSE_BYTES: usize = 10 * 1024 * 1024;
+
+/// Times a failed request to Jaeger is retried if no other number of retries is given.
+pub const DEFAULT_MAX_RETRIES: usize = 3;
+
+/// Time to wait before the first retry. Every further retry waits twice as long as the previous one.
+pub const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Time to wait for Jaeger to accept a connection, and then for each read of the response,
+/// if no other timeout is given.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Environment variable holding the bearer token, if no `--token` is given.
+pub const TOKEN_ENV: &str = "DOT_JAEGER_TOKEN";
+
+/// Most pages [`JaegerApi::all_traces`] fetches of each service, in case the `total` Jaeger reports is unreliable.
+pub const MAX_OFFSET_PAGES: usize = 100;
+
+/// Time between logs of the progress of reading a response, with `--progress`.
+pub const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Ways in which querying Jaeger fails.
+#[derive(Debug, thiserror::Error)]
+pub enum JaegerError {
+	/// Jaeger responded with an error status code.
+	#[error("Request to {url} failed after {elapsed:?}: status code {status}")]
+	Http { url: String, status: u16, elapsed: Duration },
+	/// Jaeger could not be reached, or the connection to it failed.
+	#[error("Request to {url} failed after {elapsed:?}: {message}")]
+	Network { url: String, message: String, elapsed: Duration },
+	/// Jaeger did not respond within the timeout.
+	#[error("Request to {url} timed out after {elapsed:?}: {message}")]
+	Timeout { url: String, message: String, elapsed: Duration },
+	/// The URL of Jaeger is malformed.
+	#[error("Invalid URL {url}: {message}")]
+	InvalidUrl { url: String, message: String },
+	/// The proxy URL is not printed, as it may hold credentials.
+	#[error("Invalid proxy: {0}")]
+	InvalidProxy(String),
+	#[error("Invalid client certificate: {0}")]
+	ClientCert(String),
+	/// The HTTP client could not be set up.
+	#[error("Failed to set up the HTTP client: {0}")]
+	Client(String),
+	/// The file traces are read from instead of Jaeger could not be read.
+	#[error("Failed to read traces from {path}: {source}")]
+	File { path: String, source: io::Error },
+	/// The response could not be read.
+	#[error("Failed to read the response of Jaeger: {0}")]
+	Read(#[from] io::Error),
+	/// The response is larger than the maximum response size.
+	#[error("Jaeger response exceeds the maximum response size of {max} bytes")]
+	TooLarge { max: usize },
+	/// The response is not what Jaeger is expected to respond.
+	#[error("Failed to deserialize the response of Jaeger: {0}")]
+	Deserialize(#[from] serde_json::Error),
+	/// Jaeger responded without any trace of the requested ID.
+	#[error("no trace found for id {0}")]
+	EmptyResponse(String),
+	/// Jaeger responded with errors, like failures to query its storage.
+	#[error("Jaeger responded with errors: {0}")]
+	Errors(serde_json::Value),
+	/// A `--lookback` not in the format Jaeger accepts.
+	#[error("invalid lookback `{0}`. Expected a number followed by one of `s`, `m`, `h`, `d`")]
+	InvalidLookback(String),
+}
+
+/// Bearer token authenticating requests to Jaeger. Never printed, not even in debug output.
+#[derive(Clone, PartialEq)]
+pub struct Token(String);
+
+impl Token {
+	/// The token in the [`TOKEN_ENV`] environment variable, if it is set.
+	pub fn from_env() -> Option<Self> {
+		std::env::var(TOKEN_ENV).ok().map(Token)
+	}
+
+	/// Value of the `Authorization` header of requests authenticated with this token.
+	pub(crate) fn authorization(&self) -> String {
+		format!("Bearer {}", self.0)
+	}
+}
+
+impl fmt::Debug for Token {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "Token({})", REDACTED)
+	}
+}
+
+impl FromStr for Token {
+	type Err = Error;
+	fn from_str(s: &str) -> Result<Self, Error> {
+		Ok(Token(s.to_string()))
+	}
+}
+
 pub enum Endpoint {
 	Traces,
 	Services,
+	/// Operations of a service.
+	Operations(String),
+	Dependencies,
 }
 
 impl fmt::Display for Endpoint {
@@ -47,6 +181,8 @@ impl fmt::Display for Endpoint {
 		match self {
 			Endpoint::Traces => write!(f, "{}", TRACES),
 			Endpoint::Services => write!(f, "{}", SERVICES),
+			Endpoint::Operations(service) => write!(f, "{}/{}/operations", SERVICES, service),
+			Endpoint::Dependencies => write!(f, "{}", DEPENDENCIES),
 		}
 	}
 }
@@ -57,71 +193,812 @@ pub struct JaegerApi<'a> {
 	/// # Example
 	/// http://localhost:16686
 	url: &'a str,
+	/// Largest response accepted from Jaeger, in bytes.
+	max_response_bytes: usize,
+	/// Times a request failing with a retryable error is retried.
+	retries: usize,
+	/// Time to wait before the first retry.
+	retry_backoff: Duration,
+	/// Time to wait for a connection, and for each read.
+	timeout: Duration,
+	/// Shared by all requests, configured with the timeout.
+	agent: ureq::Agent,
+	/// Sent as a bearer token with every request, if any.
+	token: Option<Token>,
+	/// If set, traces are read from this file instead of being queried from Jaeger.
+	file: Option<&'a str>,
+	/// Every request is routed through this proxy, if any.
+	proxy: Option<ureq::Proxy>,
+	/// TLS configuration presenting a client certificate, if any.
+	tls: Option<Arc<rustls::ClientConfig>>,
+	/// Log the progress of reading and deserializing responses.
+	progress: bool,
 }
 
 impl<'a> JaegerApi<'a> {
-	/// Instantiate a new API Object
-	pub fn new(url: &'a str) -> Self {
-		Self { url }
+	/// Instantiate a new API Object.
+	/// Fails if `url` is not an http(s) URL with a host. Trailing slashes are trimmed.
+	pub fn new(url: &'a str) -> Result<Self, JaegerError> {
+		Ok(Self {
+			url: normalize_url(url)?,
+			max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+			retries: DEFAULT_MAX_RETRIES,
+			retry_backoff: DEFAULT_RETRY_BACKOFF,
+			timeout: DEFAULT_TIMEOUT,
+			agent: agent(DEFAULT_TIMEOUT, None, None),
+			token: None,
+			file: None,
+			proxy: None,
+			tls: None,
+			progress: false,
+		})
 	}
 
-	/// Get many traces belonging to one service from this Jaeger Agent.
-	pub fn traces(&self, app: &App) -> Result<String, Error> {
-		let req = ureq::get(&endpoint(self.url, Endpoint::Traces));
-		let req = build_parameters(req, app);
-		let response = req.call()?.into_string()?;
-		Ok(response)
+	/// Read traces from the response of Jaeger saved in `file`, instead of querying Jaeger.
+	/// Every query of traces returns all of the traces in the file.
+	pub fn file(mut self, file: Option<&'a str>) -> Self {
+		self.file = file;
+		self
+	}
+
+	/// Log the bytes received and traces deserialized so far while reading responses, every [`PROGRESS_INTERVAL`].
+	pub fn progress(mut self, progress: bool) -> Self {
+		self.progress = progress;
+		self
+	}
+
+	/// Authenticate every request with the bearer `token`.
+	pub fn token(mut self, token: Option<Token>) -> Self {
+		self.token = token;
+		self
+	}
+
+	/// Give up on requests to Jaeger that take longer than `timeout` to connect, or to read from.
+	pub fn timeout(mut self, timeout: Duration) -> Self {
+		self.timeout = timeout;
+		self.agent = agent(timeout, self.proxy.clone(), self.tls.clone());
+		self
+	}
+
+	/// Route every request through the HTTP or SOCKS5 `proxy`, like `http://proxy:3128` or `socks5://proxy:1080`.
+	pub fn proxy(mut self, proxy: Option<&str>) -> Result<Self, JaegerError> {
+		self.proxy = proxy.map(ureq::Proxy::new).transpose().map_err(|e| JaegerError::InvalidProxy(e.to_string()))?;
+		self.agent = agent(self.timeout, self.proxy.clone(), self.tls.clone());
+		Ok(self)
+	}
+
+	/// Authenticate to Jaeger with the PEM encoded certificate chain in `cert` and private key in `key`,
+	/// for mutual TLS. Either both or none of them must be given.
+	pub fn client_cert(mut self, cert: Option<&str>, key: Option<&str>) -> Result<Self, JaegerError> {
+		self.tls = match (cert, key) {
+			(Some(cert), Some(key)) => Some(Arc::new(client_tls_config(cert, key)?)),
+			(None, None) => None,
+			_ => return Err(JaegerError::ClientCert("a certificate and a private key must be given together".into())),
+		};
+		self.agent = agent(self.timeout, self.proxy.clone(), self.tls.clone());
+		Ok(self)
+	}
+
+	/// Retry requests failing with a network error or a server error up to `retries` times.
+	pub fn retries(mut self, retries: usize) -> Self {
+		self.retries = retries;
+		self
+	}
+
+	/// Wait `backoff` before the first retry, doubling the wait for every further retry.
+	pub fn retry_backoff(mut self, backoff: Duration) -> Self {
+		self.retry_backoff = backoff;
+		self
+	}
+
+	/// Reject responses from Jaeger larger than `max` bytes.
+	pub fn max_response_bytes(mut self, max: usize) -> Self {
+		self.max_response_bytes = max;
+		self
+	}
+
+	/// Send a GET request with the query parameters added by `params`,
+	/// retrying retryable failures with exponential backoff.
+	fn call<F>(&self, url: &str, params: F) -> Result<ureq::Response, JaegerError>
+	where
+		F: Fn(ureq::Request) -> ureq::Request,
+	{
+		// when the last attempt started
+		let started = Cell::new(Instant::now());
+		let call = || {
+			started.set(Instant::now());
+			// ureq errors are large, so they are boxed while being passed around between retries
+			params(self.get(url)).call().map_err(Box::new)
+		};
+		retry(self.retries, self.retry_backoff, |e| is_retryable(e), std::thread::sleep, call).map_err(|e| {
+			let (url, elapsed) = (url.to_string(), started.get().elapsed());
+			match *e {
+				ureq::Error::Status(status, _) => JaegerError::Http { url, status, elapsed },
+				ref e if matches!(e.kind(), ureq::ErrorKind::InvalidUrl | ureq::ErrorKind::UnknownScheme) => {
+					JaegerError::InvalidUrl { url, message: e.to_string() }
+				}
+				ref e if elapsed >= self.timeout => JaegerError::Timeout { url, message: e.to_string(), elapsed },
+				ref e => JaegerError::Network { url, message: e.to_string(), elapsed },
+			}
+		})
+	}
+
+	/// A GET request to `url` accepting compressed responses, authenticated if there is a token.
+	fn get(&self, url: &str) -> ureq::Request {
+		let req = self.agent.get(url).set("Accept-Encoding", "gzip, deflate");
+		match &self.token {
+			Some(token) => req.set("Authorization", &token.authorization()),
+			None => req,
+		}
+	}
+
+	/// Read the body of a response, decompressing it if it is compressed,
+	/// and failing if it is larger than the maximum response size.
+	fn read(&self, response: ureq::Response) -> Result<String, JaegerError> {
+		let (reader, content_length) = decode(response);
+		let body = read_limited(self.with_progress(reader), content_length, self.max_response_bytes)?;
+		if self.progress {
+			log::info!("Received {} of traces, deserializing", mebibytes(body.len()));
+		}
+		Ok(body)
+	}
+
+	/// `reader`, logging how much of it has been read if `--progress` is set.
+	fn with_progress(&self, reader: Box<dyn Read + Send>) -> Box<dyn Read + Send> {
+		if self.progress {
+			Box::new(Progress::new(reader, PROGRESS_INTERVAL))
+		} else {
+			reader
+		}
+	}
+
+	/// Deserialize the traces of a response one at a time while it is read, handing each trace to `f`.
+	/// Fails as soon as more than the maximum response size has been read, or with the error of `f`.
+	fn stream<F>(&self, response: ureq::Response, mut f: F) -> Result<Page<()>, Error>
+	where
+		F: FnMut(TraceObject<'_>) -> Result<(), Error>,
+	{
+		let (reader, content_length) = decode(response);
+		check_length(content_length, self.max_response_bytes)?;
+		let reader = BufReader::new(Limited { inner: self.with_progress(reader), remaining: self.max_response_bytes });
+		let (mut traces, mut logged) = (0, Instant::now());
+		stream_traces(reader, |trace| {
+			traces += 1;
+			if self.progress && logged.elapsed() >= PROGRESS_INTERVAL {
+				log::info!("Deserialized {} traces so far", traces);
+				logged = Instant::now();
+			}
+			f(trace)
+		})
+	}
+
+	/// Get many traces from this Jaeger Agent, one response for each `--service`.
+	/// Jaeger can only query the traces of one service at a time, so every service is queried on its own.
+	pub fn traces(&self, app: &App) -> Result<Vec<String>, JaegerError> {
+		if let Some(path) = self.file {
+			return Ok(vec![read_file(path)?]);
+		}
+		let url = endpoint(self.url, Endpoint::Traces);
+		queried_services(app)
+			.into_iter()
+			.map(|service| self.read(self.call(&url, |req| build_parameters(req, app, service))?))
+			.collect()
+	}
+
+	/// Get traces page by page, `--limit` traces at a time, until `total_limit` traces have been fetched
+	/// or Jaeger has no older traces. Each page ends just before the earliest trace of the previous page.
+	/// Without a `--limit` there is no page size, and a single page is fetched.
+	/// With several services, the pages of each service are fetched in turn, up to `total_limit` traces each.
+	pub fn traces_paginated(&self, app: &App, total_limit: usize) -> Result<Vec<String>, JaegerError> {
+		let page_size = match app.limit {
+			Some(limit) if self.file.is_none() => limit,
+			_ => return self.traces(app),
+		};
+		let url = endpoint(self.url, Endpoint::Traces);
+		let mut pages = Vec::new();
+		for service in queried_services(app) {
+			pages.extend(paginate::<_, JaegerError, _>(page_size, total_limit, |limit, end| {
+				let params = |req| page_parameters(req, app, service, limit, end);
+				let body = self.read(self.call(&url, params)?)?;
+				let (traces, earliest) = {
+					let traces = self.to_json::<TraceObject>(&body)?;
+					(traces.len(), traces.iter().filter_map(TraceObject::start_time).min())
+				};
+				Ok(Page { body, traces, earliest })
+			})?);
+		}
+		Ok(pages)
+	}
+
+	/// Get every trace Jaeger has, one page after the other, skipping the traces of the previous pages with `offset`,
+	/// until as many traces as the `total` of the responses have been fetched.
+	/// The HTTP API of stock Jaeger ignores `offset` and reports a `total` of the traces it returned, so this fetches
+	/// a single page there. A page without traces that were not fetched before ends the query, in case a backend
+	/// reports a larger `total` but ignores `offset` as well.
+	/// At most [`MAX_OFFSET_PAGES`] pages are fetched of each service.
+	pub fn all_traces(&self, app: &App) -> Result<Vec<String>, JaegerError> {
+		if self.file.is_some() {
+			return self.traces(app);
+		}
+		let url = endpoint(self.url, Endpoint::Traces);
+		let mut pages = Vec::new();
+		for service in queried_services(app) {
+			let mut offset = 0;
+			let mut seen = HashSet::new();
+			for page in 1..=MAX_OFFSET_PAGES {
+				let params = |req| parameters(app, service).offset(Some(offset)).build(req);
+				let body = self.read(self.call(&url, params)?)?;
+				let response: RpcResponse<TraceId> = serde_json::from_str(&body)?;
+				let total = response.total();
+				let traces = response.try_consume()?;
+				let returned = traces.len();
+				let unseen = traces.iter().filter(|t| seen.insert(t.trace_id.to_string())).count();
+				// a page without new traces ends the query even if Jaeger claims to have more
+				if unseen == 0 {
+					if returned > 0 {
+						log::debug!("Stopped at offset {}, which returned only traces of previous pages", offset);
+					}
+					break;
+				}
+				offset += returned;
+				pages.push(body);
+				if offset >= total {
+					break;
+				}
+				if page == MAX_OFFSET_PAGES {
+					log::warn!("Stopped after {} pages of {} of {} traces", MAX_OFFSET_PAGES, offset, total);
+				}
+			}
+		}
+		Ok(pages)
+	}
+
+	/// Get traces, fetching them page by page if a `total_limit` is given.
+	pub fn traces_pages(&self, app: &App, total_limit: Option<usize>) -> Result<Vec<String>, JaegerError> {
+		match total_limit {
+			Some(total_limit) => self.traces_paginated(app, total_limit),
+			None => self.traces(app),
+		}
+	}
+
+	/// Get traces like [`traces_pages`](Self::traces_pages), but hand each trace to `f` as soon as it has been
+	/// deserialized instead of buffering whole responses, so only one trace is held in memory at a time.
+	/// Returns the number of traces. Fails with a [`JaegerError`], or with the first error of `f`.
+	pub fn traces_streaming<F>(&self, app: &App, total_limit: Option<usize>, mut f: F) -> Result<usize, Error>
+	where
+		F: FnMut(TraceObject<'_>) -> Result<(), Error>,
+	{
+		if let Some(path) = self.file {
+			let file = std::fs::File::open(path).map_err(|source| file_error(path, source))?;
+			return Ok(stream_traces(BufReader::new(file), f)?.traces);
+		}
+		let url = endpoint(self.url, Endpoint::Traces);
+		let mut traces = 0;
+		for service in queried_services(app) {
+			match (app.limit, total_limit) {
+				(Some(page_size), Some(total_limit)) => {
+					paginate::<_, Error, _>(page_size, total_limit, |limit, end| {
+						let params = |req| page_parameters(req, app, service, limit, end);
+						let page = self.stream(self.call(&url, params)?, &mut f)?;
+						traces += page.traces;
+						Ok(page)
+					})?;
+				}
+				_ => traces += self.stream(self.call(&url, |req| build_parameters(req, app, service))?, &mut f)?.traces,
+			}
+		}
+		Ok(traces)
+	}
+
+	/// Search the traces matching every parameter of `params`.
+	pub fn search(&self, params: ParamBuilder<'_>) -> Result<String, JaegerError> {
+		if let Some(path) = self.file {
+			return read_file(path);
+		}
+		let url = endpoint(self.url, Endpoint::Traces);
+		let pairs = params.pairs();
+		self.read(self.call(&url, |req| pairs.iter().fold(req, |req, (key, value)| req.query(key, value)))?)
 	}
 
 	/// Get a single trace from the Jaeger Agent
-	pub fn trace(&self, app: &App, id: &str) -> Result<String, Error> {
+	pub fn trace(&self, app: &App, id: &str) -> Result<String, JaegerError> {
+		if let Some(path) = self.file {
+			let response = select_trace(&read_file(path)?, id)?;
+			check_found(&response, id)?;
+			return Ok(response);
+		}
 		// /api/traces/{trace_id}
-		let req = ureq::get(&format!("{}/{}", &endpoint(self.url, Endpoint::Traces), id.to_string()));
-		let req = build_parameters(req, app);
-		let response = req.call()?.into_string()?;
+		let url = format!("{}/{}", &endpoint(self.url, Endpoint::Traces), id);
+		let response = self.read(self.call(&url, |req| build_parameters(req, app, None))?)?;
+		check_found(&response, id)?;
 		Ok(response)
 	}
 
 	/// Query the services that reporting to this Jaeger Agent
-	pub fn services(&self, app: &App) -> Result<Vec<String>, Error> {
-		let req = ureq::get(&endpoint(&self.url, Endpoint::Services));
-		let req = build_parameters(req, app);
-		let response: RpcResponse<String> = req.call()?.into_json()?;
-		Ok(response.consume())
+	pub fn services(&self, app: &App) -> Result<Vec<String>, JaegerError> {
+		let url = endpoint(self.url, Endpoint::Services);
+		let response: RpcResponse<String> =
+			serde_json::from_str(&self.read(self.call(&url, |req| build_parameters(req, app, None))?)?)?;
+		response.try_consume()
+	}
+
+	/// Query the operations of `service`
+	pub fn operations(&self, service: &str) -> Result<Vec<String>, JaegerError> {
+		let url = endpoint(self.url, Endpoint::Operations(service.to_string()));
+		let response: RpcResponse<String> = serde_json::from_str(&self.read(self.call(&url, |req| req)?)?)?;
+		response.try_consume()
+	}
+
+	/// Get the calls between services within the `--lookback` window ending now,
+	/// or between `--start` and `--end` if they are given.
+	pub fn dependencies(&self, app: &App) -> Result<String, JaegerError> {
+		// a clock set before 1970 ends the window at the epoch
+		let end = match app.end {
+			Some(end) => Duration::from_micros(end.micros() as u64),
+			None => std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default(),
+		};
+		let lookback = match app.start {
+			Some(start) => end.saturating_sub(Duration::from_micros(start.micros() as u64)),
+			None => app.lookback.as_deref().map(parse_lookback).transpose()?.unwrap_or(DEFAULT_DEPENDENCIES_LOOKBACK),
+		};
+		let url = endpoint(self.url, Endpoint::Dependencies);
+		let params = |req: ureq::Request| {
+			req.query("endTs", &end.as_millis().to_string()).query("lookback", &lookback.as_millis().to_string())
+		};
+		self.read(self.call(&url, params)?)
 	}
 
-	pub fn to_json<'b, T>(&self, response: &'b str) -> Result<Vec<T>, Error>
+	/// Deserialize the data of a response, warning if Jaeger has more results than it returned.
+	pub fn to_json<'b, T>(&self, response: &'b str) -> Result<Vec<T>, JaegerError>
 	where
 		T: Deserialize<'b>,
 	{
-		let response: RpcResponse<T> = serde_json::from_str(&response)?;
-		Ok(response.consume())
+		let response: RpcResponse<T> = serde_json::from_str(response)?;
+		if response.is_truncated() {
+			log::warn!(
+				"Jaeger has {} results, but returned only the {} its limit allows, from offset {}. The others are missing",
+				response.total(),
+				response.limit(),
+				response.offset()
+			);
+		}
+		response.try_consume()
+	}
+
+	/// Deserialize the data of every page into one list.
+	pub fn to_json_pages<'b, T>(&self, pages: &'b [String]) -> Result<Vec<T>, JaegerError>
+	where
+		T: Deserialize<'b>,
+	{
+		let mut data = Vec::new();
+		for page in pages.iter() {
+			data.extend(self.to_json::<T>(page)?);
+		}
+		Ok(data)
+	}
+}
+
+/// Read a response of Jaeger saved in the file at `path`.
+fn read_file(path: &str) -> Result<String, JaegerError> {
+	std::fs::read_to_string(path).map_err(|source| file_error(path, source))
+}
+
+fn file_error(path: &str, source: io::Error) -> JaegerError {
+	JaegerError::File { path: path.to_string(), source }
+}
+
+/// Only the ID of a trace of a response.
+#[derive(Deserialize)]
+struct TraceId<'a> {
+	#[serde(rename = "traceID")]
+	trace_id: &'a str,
+}
+
+/// A response holding only the traces of a response of many traces whose ID is `id`.
+fn select_trace(response: &str, id: &str) -> Result<String, JaegerError> {
+	let response: RpcResponse<Box<RawValue>> = serde_json::from_str(response)?;
+	let mut found = Vec::new();
+	for trace in response.try_consume()? {
+		if serde_json::from_str::<TraceId>(trace.get())?.trace_id == id {
+			found.push(trace);
+		}
+	}
+	let data = found.iter().map(|t| t.get()).collect::<Vec<_>>().join(", ");
+	Ok(format!(r#"{{"data": [{}], "total": {}, "limit": 0, "offset": 0, "errors": null}}"#, data, found.len()))
+}
+
+/// Fail if a response to the query of the trace `id` holds no trace, as it does for unknown IDs.
+fn check_found(response: &str, id: &str) -> Result<(), JaegerError> {
+	let response: RpcResponse<IgnoredAny> = serde_json::from_str(response)?;
+	if response.try_consume()?.is_empty() {
+		return Err(JaegerError::EmptyResponse(id.to_string()));
+	}
+	Ok(())
+}
+
+/// Read a response body of at most `max` bytes.
+/// Responses announcing a larger `content_length` are rejected before anything is read,
+/// responses without a length are rejected as soon as more than `max` bytes have been read.
+fn read_limited(reader: impl Read, content_length: Option<usize>, max: usize) -> Result<String, JaegerError> {
+	check_length(content_length, max)?;
+	let mut body = String::new();
+	reader.take(max as u64 + 1).read_to_string(&mut body)?;
+	if body.len() > max {
+		return Err(JaegerError::TooLarge { max });
+	}
+	Ok(body)
+}
+
+/// Reject responses announcing a `content_length` larger than `max` bytes.
+pub(crate) fn check_length(content_length: Option<usize>, max: usize) -> Result<(), JaegerError> {
+	if content_length.filter(|l| *l > max).is_some() {
+		return Err(JaegerError::TooLarge { max });
+	}
+	Ok(())
+}
+
+/// The body of a response, decompressed if it is compressed, and its length if it is known.
+fn decode(response: ureq::Response) -> (Box<dyn Read + Send>, Option<usize>) {
+	let content_length = response.header("Content-Length").and_then(|l| l.parse().ok());
+	let encoding = response.header("Content-Encoding").map(|e| e.trim().to_lowercase());
+	let reader = response.into_reader();
+	// the length of a compressed response says nothing about the size of the decompressed body
+	match encoding.as_deref() {
+		Some("gzip") => (Box::new(GzDecoder::new(reader)), None),
+		Some("deflate") => (Box::new(ZlibDecoder::new(reader)), None),
+		_ => (Box::new(reader), content_length),
+	}
+}
+
+/// Logs how many bytes have been read from `inner`, at most once per `interval`.
+struct Progress<R> {
+	inner: R,
+	read: usize,
+	interval: Duration,
+	logged: Instant,
+}
+
+impl<R> Progress<R> {
+	fn new(inner: R, interval: Duration) -> Self {
+		Self { inner, read: 0, interval, logged: Instant::now() }
+	}
+}
+
+impl<R: Read> Read for Progress<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let read = self.inner.read(buf)?;
+		self.read += read;
+		if self.logged.elapsed() >= self.interval {
+			log::info!("Received {} from Jaeger so far", mebibytes(self.read));
+			self.logged = Instant::now();
+		}
+		Ok(read)
+	}
+}
+
+/// `bytes` in mebibytes, for logs.
+fn mebibytes(bytes: usize) -> String {
+	format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+/// Reads at most `remaining` more bytes, failing rather than ending early if there are more.
+struct Limited<R> {
+	inner: R,
+	remaining: usize,
+}
+
+impl<R: Read> Read for Limited<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		// read one byte past the limit to tell a body of exactly the limit from a larger one
+		let len = std::cmp::min(buf.len(), self.remaining.saturating_add(1));
+		let read = self.inner.read(&mut buf[..len])?;
+		if read > self.remaining {
+			return Err(io::Error::other("Jaeger response exceeds the maximum response size"));
+		}
+		self.remaining -= read;
+		Ok(read)
+	}
+}
+
+/// Deserialize a response of traces from `reader`, handing each trace to `f` as soon as it has been read.
+/// Fails with the `errors` of the response, if there are any. Every other field of the response is skipped.
+fn stream_traces<F>(reader: impl Read, mut f: F) -> Result<Page<()>, Error>
+where
+	F: FnMut(TraceObject<'_>) -> Result<(), Error>,
+{
+	let mut page = Page { body: (), traces: 0, earliest: None };
+	let mut fold = |raw: &RawValue| {
+		// traces borrow from the JSON they are deserialized from, so each trace is buffered on its own
+		let trace: TraceObject<'_> = serde_json::from_str(raw.get())?;
+		page.traces += 1;
+		page.earliest = page.earliest.into_iter().chain(trace.start_time()).min();
+		f(trace)
+	};
+	let mut deserializer = serde_json::Deserializer::from_reader(reader);
+	let errors = deserializer.deserialize_map(ResponseVisitor(&mut fold))?;
+	deserializer.end()?;
+	check_errors(errors)?;
+	Ok(page)
+}
+
+/// Visits the fields of an [`RpcResponse`], handing each element of its `data` to a callback.
+/// Results in the `errors` of the response.
+struct ResponseVisitor<'f, F>(&'f mut F);
+
+impl<'de, 'f, F> Visitor<'de> for ResponseVisitor<'f, F>
+where
+	F: FnMut(&RawValue) -> Result<(), Error>,
+{
+	type Value = Option<serde_json::Value>;
+
+	fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("a response of traces")
+	}
+
+	fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+		let mut errors = None;
+		while let Some(key) = map.next_key::<String>()? {
+			match key.as_str() {
+				"data" => map.next_value_seed(DataVisitor(&mut *self.0))?,
+				"errors" => errors = map.next_value()?,
+				_ => {
+					map.next_value::<IgnoredAny>()?;
+				}
+			}
+		}
+		Ok(errors)
+	}
+}
+
+/// Visits the `data` of an [`RpcResponse`] element by element.
+struct DataVisitor<'f, F>(&'f mut F);
+
+impl<'de, 'f, F> Visitor<'de> for DataVisitor<'f, F>
+where
+	F: FnMut(&RawValue) -> Result<(), Error>,
+{
+	type Value = ();
+
+	fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("a list of traces")
+	}
+
+	fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+		while let Some(raw) = seq.next_element::<Box<RawValue>>()? {
+			(self.0)(&raw).map_err(|e| de::Error::custom(format!("{:#}", e)))?;
+		}
+		Ok(())
+	}
+}
+
+impl<'de, 'f, F> DeserializeSeed<'de> for DataVisitor<'f, F>
+where
+	F: FnMut(&RawValue) -> Result<(), Error>,
+{
+	type Value = ();
+
+	fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+		deserializer.deserialize_seq(self)
+	}
+}
+
+/// Whether a request failing with `error` may succeed if it is tried again.
+/// Network errors and server errors are retryable. Client errors, like a malformed request or an unknown trace, are not.
+fn is_retryable(error: &ureq::Error) -> bool {
+	match error {
+		ureq::Error::Status(status, _) => *status >= 500 || *status == 429,
+		ureq::Error::Transport(_) => {
+			matches!(error.kind(), ureq::ErrorKind::Dns | ureq::ErrorKind::ConnectionFailed | ureq::ErrorKind::Io)
+		}
+	}
+}
+
+/// Try `f` until it succeeds, fails with an error that is not retryable, or has been retried `retries` times.
+/// Waits `backoff` before the first retry, and twice as long before every further retry.
+fn retry<T, E, F>(
+	retries: usize,
+	backoff: Duration,
+	retryable: impl Fn(&E) -> bool,
+	mut sleep: impl FnMut(Duration),
+	mut f: F,
+) -> Result<T, E>
+where
+	E: fmt::Display,
+	F: FnMut() -> Result<T, E>,
+{
+	let mut wait = backoff;
+	for attempt in 0.. {
+		match f() {
+			Err(e) if attempt < retries && retryable(&e) => {
+				log::warn!("Request to Jaeger failed: {}. Retrying in {:?}", e, wait);
+				sleep(wait);
+				wait *= 2;
+			}
+			result => return result,
+		}
 	}
+	unreachable!("loop only exits by returning")
 }
 
-fn build_parameters(req: ureq::Request, app: &App) -> ureq::Request {
-	ParamBuilder::new().service(app.service.as_deref()).limit(app.limit).lookback(app.lookback.as_deref()).build(req)
+/// A page of traces returned by Jaeger.
+struct Page<T> {
+	/// the response, or nothing if the traces have already been handed on
+	body: T,
+	/// number of traces in the page
+	traces: usize,
+	/// earliest start time of any trace in the page, in microseconds
+	earliest: Option<usize>,
+}
+
+/// Fetch pages of at most `page_size` traces until `total_limit` traces have been fetched,
+/// or a page comes back short. `fetch` is given the limit and the end time of the page to request.
+fn paginate<T, E, F>(page_size: usize, total_limit: usize, mut fetch: F) -> Result<Vec<T>, E>
+where
+	F: FnMut(usize, Option<usize>) -> Result<Page<T>, E>,
+{
+	let mut pages = Vec::new();
+	let mut fetched = 0;
+	let mut end = None;
+	while fetched < total_limit {
+		let limit = std::cmp::min(page_size, total_limit - fetched);
+		let page = fetch(limit, end)?;
+		fetched += page.traces;
+		let exhausted = page.traces < limit;
+		end = page.earliest.map(|e| e.saturating_sub(1));
+		pages.push(page.body);
+		if exhausted || end.is_none() {
+			break;
+		}
+	}
+	Ok(pages)
+}
+
+/// The services to query traces of, one at a time. Without any `--service`, traces of any service are queried.
+pub(crate) fn queried_services(app: &App) -> Vec<Option<&str>> {
+	if app.service.is_empty() {
+		vec![None]
+	} else {
+		app.service.iter().map(|s| Some(s.as_str())).collect()
+	}
+}
+
+fn build_parameters<'a>(req: ureq::Request, app: &'a App, service: Option<&'a str>) -> ureq::Request {
+	parameters(app, service).build(req)
+}
+
+/// Parameters of a query for the traces of `service`.
+pub(crate) fn parameters<'a>(app: &'a App, service: Option<&'a str>) -> ParamBuilder<'a> {
+	ParamBuilder::new()
+		.service(service)
+		.limit(app.limit)
+		.lookback(app.lookback.as_deref())
+		.operation(app.operation.as_deref())
+		.start(app.start.map(Timestamp::micros))
+		.end(app.end.map(Timestamp::micros))
+}
+
+/// Parameters of the page of at most `limit` traces of `service` starting before `end`, or before `--end`
+/// for the first page.
+fn page_parameters<'a>(
+	req: ureq::Request,
+	app: &'a App,
+	service: Option<&'a str>,
+	limit: usize,
+	end: Option<usize>,
+) -> ureq::Request {
+	ParamBuilder::new()
+		.service(service)
+		.limit(Some(limit))
+		.lookback(app.lookback.as_deref())
+		.operation(app.operation.as_deref())
+		.start(app.start.map(Timestamp::micros))
+		.end(end.or_else(|| app.end.map(Timestamp::micros)))
+		.build(req)
+}
+
+/// Parse a lookback in the format Jaeger accepts for traces, like `30m`, `1h` or `2d`.
+fn parse_lookback(lookback: &str) -> Result<Duration, JaegerError> {
+	let invalid = || JaegerError::InvalidLookback(lookback.to_string());
+	let unit = lookback.chars().last().ok_or_else(invalid)?;
+	let seconds = match unit {
+		's' => 1,
+		'm' => 60,
+		'h' => 60 * 60,
+		'd' => 24 * 60 * 60,
+		_ => return Err(invalid()),
+	};
+	let count: u64 = lookback[..lookback.len() - unit.len_utf8()].parse().map_err(|_| invalid())?;
+	Ok(Duration::from_secs(count * seconds))
+}
+
+pub fn agent(timeout: Duration, proxy: Option<ureq::Proxy>, tls: Option<Arc<rustls::ClientConfig>>) -> ureq::Agent {
+	let mut builder = ureq::AgentBuilder::new().timeout_connect(timeout).timeout_read(timeout);
+	if let Some(proxy) = proxy {
+		builder = builder.proxy(proxy);
+	}
+	if let Some(tls) = tls {
+		builder = builder.tls_config(tls);
+	}
+	builder.build()
+}
+
+/// TLS configuration trusting the usual web roots, and presenting the certificate chain in the PEM file `cert`
+/// with the PKCS#8 or RSA private key in the PEM file `key`.
+fn client_tls_config(cert: &str, key: &str) -> Result<rustls::ClientConfig, JaegerError> {
+	use rustls::internal::pemfile;
+
+	let open = |path: &str| std::fs::File::open(path).map(io::BufReader::new).map_err(|e| file_error(path, e));
+	let certs = pemfile::certs(&mut open(cert)?).unwrap_or_default();
+	if certs.is_empty() {
+		return Err(JaegerError::ClientCert(format!("no PEM encoded certificate found in `{}`", cert)));
+	}
+	let mut keys = pemfile::pkcs8_private_keys(&mut open(key)?).unwrap_or_default();
+	keys.extend(pemfile::rsa_private_keys(&mut open(key)?).unwrap_or_default());
+	if keys.is_empty() {
+		return Err(JaegerError::ClientCert(format!("no PEM encoded PKCS#8 or RSA private key found in `{}`", key)));
+	}
+
+	let mut config = rustls::ClientConfig::new();
+	config.root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+	config.set_single_client_cert(certs, keys.remove(0)).map_err(|e| JaegerError::ClientCert(e.to_string()))?;
+	Ok(config)
+}
+
+/// Proxy for requests to `url` in the `HTTPS_PROXY` or `HTTP_PROXY` environment variable,
+/// depending on the scheme of `url`. Lowercase variables are honored too.
+pub fn proxy_from_env(url: &str) -> Option<String> {
+	let vars = if url.starts_with("https:") { ["HTTPS_PROXY", "https_proxy"] } else { ["HTTP_PROXY", "http_proxy"] };
+	vars.iter().filter_map(|var| std::env::var(var).ok()).find(|proxy| !proxy.is_empty())
+}
+
+/// `url` without trailing slashes, so endpoints can be appended to it.
+/// Fails if `url` does not parse, is not http(s), or has no host.
+pub(crate) fn normalize_url(url: &str) -> Result<&str, JaegerError> {
+	let invalid = |message: String| JaegerError::InvalidUrl { url: url.to_string(), message };
+	let parsed = url::Url::parse(url).map_err(|e| invalid(e.to_string()))?;
+	if !matches!(parsed.scheme(), "http" | "https") {
+		return Err(invalid(format!("unsupported scheme `{}`, expected http or https", parsed.scheme())));
+	}
+	if parsed.host_str().unwrap_or_default().is_empty() {
+		return Err(invalid("missing host".to_string()));
+	}
+	Ok(url.trim_end_matches('/'))
+}
+
+pub(crate) fn endpoint(url: &str, endpoint: Endpoint) -> String {
+	match endpoint {
+		Endpoint::Operations(ref service) => {
+			operations_url(url, service).unwrap_or_else(|| format!("{}{}", url, endpoint))
+		}
+		_ => format!("{}{}", url, endpoint),
+	}
 }
 
-fn endpoint(url: &str, endpoint: Endpoint) -> String {
-	format!("{}{}", url, endpoint)
+/// The service is percent-encoded as a path segment of its own, whatever characters it contains.
+/// `None` if `url` is not a valid base URL, which the request then reports.
+fn operations_url(url: &str, service: &str) -> Option<String> {
+	let mut parsed = url::Url::parse(&format!("{}{}", url, SERVICES)).ok()?;
+	parsed.path_segments_mut().ok()?.push(service).push("operations");
+	Some(parsed.to_string())
 }
 
-// Other possible parameters
-// operation
-// minDuration
-// maxDuration
-// start <- Unix timestamp in microseconds (presumably for internal Jaeger Use)
-// end <- Unix timestamp in microseconds (presumably for internal Jaeger Use)
+#[derive(Default)]
 pub struct ParamBuilder<'a> {
 	limit: Option<usize>,
 	service: Option<&'a str>,
 	lookback: Option<&'a str>,
+	end: Option<usize>,
+	operation: Option<&'a str>,
+	offset: Option<usize>,
+	start: Option<usize>,
+	min_duration: Option<&'a str>,
+	max_duration: Option<&'a str>,
+	tags: &'a [TagFilter],
 }
 
 impl<'a> ParamBuilder<'a> {
 	pub fn new() -> Self {
-		Self { limit: None, service: None, lookback: None }
+		Self::default()
 	}
 
 	/// Amount of JSON objects to return in one GET.
@@ -142,19 +1019,525 @@ impl<'a> ParamBuilder<'a> {
 		self
 	}
 
-	pub fn build(self, mut req: ureq::Request) -> ureq::Request {
+	/// Latest start time of traces to return, as a Unix timestamp in microseconds.
+	pub fn end(mut self, end: Option<usize>) -> Self {
+		self.end = end;
+		self
+	}
+
+	/// Only return traces containing spans of this operation.
+	pub fn operation(mut self, operation: Option<&'a str>) -> Self {
+		self.operation = operation;
+		self
+	}
+
+	/// Earliest start time of traces to return, as a Unix timestamp in microseconds.
+	pub fn start(mut self, start: Option<usize>) -> Self {
+		self.start = start;
+		self
+	}
+
+	/// Only return traces with a span lasting at least this long, like `100ms` or `1.5s`.
+	pub fn min_duration(mut self, min_duration: Option<&'a str>) -> Self {
+		self.min_duration = min_duration;
+		self
+	}
+
+	/// Only return traces with a span lasting at most this long, like `100ms` or `1.5s`.
+	pub fn max_duration(mut self, max_duration: Option<&'a str>) -> Self {
+		self.max_duration = max_duration;
+		self
+	}
+
+	/// Only return traces with spans having all of these tags.
+	pub fn tags(mut self, tags: &'a [TagFilter]) -> Self {
+		self.tags = tags;
+		self
+	}
+
+	/// Number of traces to skip before the first one returned.
+	pub fn offset(mut self, offset: Option<usize>) -> Self {
+		self.offset = offset;
+		self
+	}
+
+	/// The query parameters, as pairs of key and value.
+	pub fn pairs(self) -> Vec<(&'static str, String)> {
+		let mut pairs = Vec::new();
+
 		if let Some(service) = self.service {
-			req = req.query("service", &service.to_string());
+			pairs.push(("service", service.to_string()));
 		}
 
 		if let Some(limit) = self.limit {
-			req = req.query("limit", &limit.to_string());
+			pairs.push(("limit", limit.to_string()));
 		}
 
 		if let Some(lookback) = self.lookback {
-			req = req.query("lookback", &lookback.to_string());
+			pairs.push(("lookback", lookback.to_string()));
+		}
+
+		if let Some(end) = self.end {
+			pairs.push(("end", end.to_string()));
+		}
+
+		if let Some(operation) = self.operation {
+			pairs.push(("operation", operation.to_string()));
+		}
+
+		if let Some(offset) = self.offset {
+			pairs.push(("offset", offset.to_string()));
+		}
+
+		if let Some(start) = self.start {
+			pairs.push(("start", start.to_string()));
 		}
 
-		req
+		if let Some(min_duration) = self.min_duration {
+			pairs.push(("minDuration", min_duration.to_string()));
+		}
+
+		if let Some(max_duration) = self.max_duration {
+			pairs.push(("maxDuration", max_duration.to_string()));
+		}
+
+		// Jaeger expects the tags as a JSON object
+		if !self.tags.is_empty() {
+			let tags: serde_json::Map<String, serde_json::Value> =
+				self.tags.iter().map(|t| (t.key().to_string(), t.value().into())).collect();
+			pairs.push(("tags", serde_json::Value::Object(tags).to_string()));
+		}
+
+		pairs
+	}
+
+	pub fn build(self, req: ureq::Request) -> ureq::Request {
+		self.pairs().iter().fold(req, |req, (key, value)| req.query(key, value))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::tests::{ok, response, serve};
+	use anyhow::anyhow;
+
+	#[test]
+	fn pagination_should_halt_at_total_limit() -> Result<(), Error> {
+		let mut requests = Vec::new();
+		let pages = paginate::<_, Error, _>(10, 25, |limit, end| {
+			requests.push((limit, end));
+			let earliest = 1000 - requests.len() * 100;
+			Ok(Page { body: format!("page {}", requests.len()), traces: limit, earliest: Some(earliest) })
+		})?;
+		assert_eq!(pages, vec!["page 1", "page 2", "page 3"]);
+		assert_eq!(requests, vec![(10, None), (10, Some(899)), (5, Some(799))]);
+		Ok(())
+	}
+
+	#[test]
+	fn should_reject_responses_over_limit() {
+		struct Unread;
+		impl Read for Unread {
+			fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+				panic!("response should be rejected before it is read");
+			}
+		}
+		assert!(read_limited(Unread, Some(11), 10).is_err());
+
+		let body = "{\"data\": []}";
+		assert!(read_limited(body.as_bytes(), None, body.len() - 1).is_err());
+		assert_eq!(read_limited(body.as_bytes(), Some(body.len()), body.len()).unwrap(), body);
+	}
+
+	#[test]
+	fn should_count_bytes_read_with_progress() -> Result<(), Error> {
+		let body = "{\"data\": []}";
+		let mut progress = Progress::new(body.as_bytes(), Duration::from_secs(0));
+		let mut read = String::new();
+		progress.read_to_string(&mut read)?;
+		assert_eq!((read.as_str(), progress.read), (body, body.len()));
+		assert_eq!(mebibytes(3 * 1024 * 1024 / 2), "1.5 MiB");
+		Ok(())
+	}
+
+	#[test]
+	fn should_stream_traces_one_by_one() -> Result<(), Error> {
+		let trace = crate::tests::TEST_DATA;
+		let body =
+			format!(r#"{{"total": 0, "data": [{}, {}], "limit": 0, "offset": 0, "errors": null}}"#, trace, trace);
+		let mut traces = Vec::new();
+		let page = stream_traces(body.as_bytes(), |t| {
+			traces.push(t.trace_id.to_string());
+			Ok(())
+		})?;
+		assert_eq!(traces, vec!["6ga7nenJ21rhDy6Fwzjwz7KZQ5Jrii9"; 2]);
+		assert_eq!(page.traces, 2);
+		assert_eq!(page.earliest, serde_json::from_str::<TraceObject>(trace)?.start_time());
+
+		// errors of the callback end the stream
+		let mut calls = 0;
+		assert!(stream_traces(body.as_bytes(), |_| {
+			calls += 1;
+			Err(anyhow!("stop"))
+		})
+		.is_err());
+		assert_eq!(calls, 1);
+
+		let limited = Limited { inner: body.as_bytes(), remaining: body.len() - 1 };
+		assert!(stream_traces(limited, |_| Ok(())).is_err());
+
+		let failed = body.replace(r#""errors": null"#, r#""errors": [{"code": 500, "msg": "storage"}]"#);
+		let error = stream_traces(failed.as_bytes(), |_| Ok(())).err().map(|e| e.downcast::<JaegerError>());
+		assert!(matches!(error, Some(Ok(JaegerError::Errors(_)))));
+		Ok(())
+	}
+
+	#[test]
+	fn pagination_should_halt_on_short_page() -> Result<(), Error> {
+		let mut requests = 0;
+		let pages = paginate::<_, Error, _>(10, 100, |_, _| {
+			requests += 1;
+			Ok(Page { body: String::new(), traces: 4, earliest: Some(500) })
+		})?;
+		assert_eq!(pages.len(), 1);
+		assert_eq!(requests, 1);
+		Ok(())
+	}
+
+	#[test]
+	fn should_only_emit_operation_if_set() {
+		let req = ParamBuilder::new().build(ureq::get("http://localhost:16686/api/traces"));
+		assert!(!format!("{:?}", req).contains("operation"));
+
+		let req = ParamBuilder::new().operation(Some("testop")).build(ureq::get("http://localhost:16686/api/traces"));
+		assert!(format!("{:?}", req).contains(r#"("operation", "testop")"#));
+	}
+
+	#[test]
+	fn should_query_time_range() -> Result<(), Error> {
+		use argh::FromArgs;
+
+		let args = ["--start", "2021-03-19T10:30:00Z", "--end", "2021-03-19T11:00:00Z", "services"];
+		let app = App::from_args(&["dot-jaeger"], &args).map_err(|e| anyhow!("{}", e.output))?;
+		let pairs = parameters(&app, None).pairs();
+		assert!(pairs.contains(&("start", "1616149800000000".to_string())), "{:?}", pairs);
+		assert!(pairs.contains(&("end", "1616151600000000".to_string())), "{:?}", pairs);
+		assert!(app.api().is_ok());
+
+		for args in [
+			&["--lookback", "1h", "--start", "2021-03-19T10:30:00Z", "services"][..],
+			&["--start", "2021-03-19T11:00:00Z", "--end", "2021-03-19T10:30:00Z", "services"],
+		] {
+			let app = App::from_args(&["dot-jaeger"], args).map_err(|e| anyhow!("{}", e.output))?;
+			assert!(app.api().is_err(), "{:?}", args);
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn should_build_search_parameters() -> Result<(), Error> {
+		let tags: Vec<TagFilter> = vec!["candidate-stage=3".parse()?, "sampled=true".parse()?];
+		let pairs = ParamBuilder::new()
+			.service(Some("polkadot"))
+			.tags(&tags)
+			.min_duration(Some("100ms"))
+			.max_duration(Some("2s"))
+			.start(Some(1))
+			.end(Some(2))
+			.pairs();
+		assert_eq!(
+			pairs,
+			vec![
+				("service", "polkadot".to_string()),
+				("end", "2".to_string()),
+				("start", "1".to_string()),
+				("minDuration", "100ms".to_string()),
+				("maxDuration", "2s".to_string()),
+				("tags", r#"{"candidate-stage":"3","sampled":"true"}"#.to_string()),
+			]
+		);
+		Ok(())
+	}
+
+	#[test]
+	fn should_retry_retryable_errors_with_backoff() {
+		let retryable = |status: &u16| *status >= 500;
+		let mut waits = Vec::new();
+		let mut responses = vec![Err(503), Err(503), Ok("traces")].into_iter();
+		let result = retry(3, Duration::from_millis(10), retryable, |d| waits.push(d), || responses.next().unwrap());
+		assert_eq!(result, Ok("traces"));
+		assert_eq!(waits, vec![Duration::from_millis(10), Duration::from_millis(20)]);
+
+		// client errors are not retried
+		let mut attempts = 0;
+		let result: Result<(), u16> = retry(
+			3,
+			Duration::from_millis(10),
+			retryable,
+			|_| (),
+			|| {
+				attempts += 1;
+				Err(404)
+			},
+		);
+		assert_eq!((result, attempts), (Err(404), 1));
+
+		// give up after the last retry
+		let mut attempts = 0;
+		let result: Result<(), u16> = retry(
+			2,
+			Duration::from_millis(10),
+			retryable,
+			|_| (),
+			|| {
+				attempts += 1;
+				Err(503)
+			},
+		);
+		assert_eq!((result, attempts), (Err(503), 3));
+	}
+
+	#[test]
+	fn should_decompress_responses() -> Result<(), Error> {
+		use flate2::{write::GzEncoder, Compression};
+		use std::io::Write;
+
+		let body = r#"{"data": ["candidate-backing"], "total": 1, "limit": 0, "offset": 0, "errors": null}"#;
+		let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+		encoder.write_all(body.as_bytes())?;
+		let compressed = encoder.finish()?;
+		let mut gzipped = format!(
+			"HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+			compressed.len()
+		)
+		.into_bytes();
+		gzipped.extend(compressed);
+		// servers may ignore the accepted encodings
+		let (url, server) = crate::tests::serve_bytes(vec![gzipped, crate::tests::ok(body).into_bytes()]);
+
+		let api = JaegerApi::new(&url)?.retries(0);
+		assert_eq!(api.operations("polkadot")?, vec!["candidate-backing"]);
+		assert_eq!(api.operations("polkadot")?, vec!["candidate-backing"]);
+		let requests = server.join().map_err(|_| anyhow!("server thread panicked"))?;
+		for request in requests {
+			assert!(request.to_lowercase().contains("accept-encoding: gzip, deflate"), "{}", request);
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn should_query_each_service() -> Result<(), Error> {
+		use argh::FromArgs;
+
+		let body = r#"{"data": [], "total": 0, "limit": 0, "offset": 0, "errors": null}"#;
+		let (url, server) = serve(vec![ok(body), ok(body)]);
+
+		let app = App::from_args(&["dot-jaeger"], &["--service", "alice", "--service", "bob", "services"])
+			.map_err(|e| anyhow!("{}", e.output))?;
+		let api = JaegerApi::new(&url)?.retries(0);
+		assert_eq!(api.traces(&app)?.len(), 2);
+		let requests = server.join().map_err(|_| anyhow!("server thread panicked"))?;
+		assert!(requests[0].starts_with("GET /api/traces?service=alice"), "{:?}", requests);
+		assert!(requests[1].starts_with("GET /api/traces?service=bob"), "{:?}", requests);
+		Ok(())
+	}
+
+	#[test]
+	fn should_fetch_all_traces_by_offset() -> Result<(), Error> {
+		use argh::FromArgs;
+
+		let traces: Vec<String> = (0..5)
+			.map(|i| crate::tests::TEST_DATA.replace("6ga7nenJ21rhDy6Fwzjwz7KZQ5Jrii9", &i.to_string()))
+			.collect();
+		let responses = [&traces[0..2], &traces[2..4], &traces[4..]]
+			.iter()
+			.map(|data| {
+				ok(&format!(
+					r#"{{"data": [{}], "total": 5, "limit": 2, "offset": 0, "errors": null}}"#,
+					data.join(", ")
+				))
+			})
+			.collect();
+		let (url, server) = serve(responses);
+
+		let app =
+			App::from_args(&["dot-jaeger"], &["--limit", "2", "services"]).map_err(|e| anyhow!("{}", e.output))?;
+		let api = JaegerApi::new(&url)?.retries(0);
+		let pages = api.all_traces(&app)?;
+		assert_eq!(api.to_json_pages::<TraceObject>(&pages)?.len(), 5);
+		let requests = server.join().map_err(|_| anyhow!("server thread panicked"))?;
+		for (request, offset) in requests.iter().zip(&["offset=0", "offset=2", "offset=4"]) {
+			assert!(request.lines().next().unwrap_or_default().contains(offset), "{:?}", requests);
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn should_stop_when_offset_is_ignored() -> Result<(), Error> {
+		use argh::FromArgs;
+
+		let body = format!(
+			r#"{{"data": [{}], "total": 5, "limit": 2, "offset": 0, "errors": null}}"#,
+			crate::tests::TEST_DATA
+		);
+		let (url, server) = serve(vec![ok(&body), ok(&body)]);
+
+		let app =
+			App::from_args(&["dot-jaeger"], &["--limit", "2", "services"]).map_err(|e| anyhow!("{}", e.output))?;
+		let api = JaegerApi::new(&url)?.retries(0);
+		let pages = api.all_traces(&app)?;
+		assert_eq!(api.to_json_pages::<TraceObject>(&pages)?.len(), 1);
+		assert_eq!(server.join().map_err(|_| anyhow!("server thread panicked"))?.len(), 2);
+		Ok(())
+	}
+
+	#[test]
+	fn should_not_panic_without_trace() {
+		let empty = r#"{"data": [], "total": 0, "limit": 0, "offset": 0, "errors": null}"#;
+		let error = check_found(empty, "deadbeef").unwrap_err();
+		assert_eq!(error.to_string(), "no trace found for id deadbeef");
+
+		let found = r#"{"data": [{"traceID": "deadbeef"}], "total": 0, "limit": 0, "offset": 0, "errors": null}"#;
+		assert!(check_found(found, "deadbeef").is_ok());
+	}
+
+	#[test]
+	fn should_fail_with_structured_errors() -> Result<(), Error> {
+		use argh::FromArgs;
+
+		let empty = r#"{"data": [], "total": 0, "limit": 0, "offset": 0, "errors": null}"#;
+		let (url, server) = serve(vec![ok(empty), response("404 Not Found", "")]);
+
+		let app = App::from_args(&["dot-jaeger"], &["services"]).map_err(|e| anyhow!("{}", e.output))?;
+		let api = JaegerApi::new(&url)?.retries(0);
+		assert!(matches!(api.trace(&app, "abc"), Err(JaegerError::EmptyResponse(id)) if id == "abc"));
+		assert!(matches!(api.trace(&app, "abc"), Err(JaegerError::Http { status: 404, .. })));
+		server.join().map_err(|_| anyhow!("server thread panicked"))?;
+		Ok(())
+	}
+
+	#[test]
+	fn should_read_traces_from_file() -> Result<(), Error> {
+		use argh::FromArgs;
+
+		let trace = crate::tests::TEST_DATA;
+		let other = trace.replace("6ga7nenJ21rhDy6Fwzjwz7KZQ5Jrii9", "other");
+		let body =
+			format!(r#"{{"total": 0, "data": [{}, {}], "limit": 0, "offset": 0, "errors": null}}"#, trace, other);
+		let path = std::env::temp_dir().join(format!("dot-jaeger-traces-{}.json", std::process::id()));
+		std::fs::write(&path, &body)?;
+		let path = path.to_string_lossy().to_string();
+
+		// nothing listens at the URL, so every trace has to come from the file
+		let app =
+			App::from_args(&["dot-jaeger"], &["--limit", "1", "services"]).map_err(|e| anyhow!("{}", e.output))?;
+		let api = JaegerApi::new("http://127.0.0.1:1")?.retries(0).file(Some(&path));
+		assert_eq!(api.traces_pages(&app, Some(10))?, vec![body.clone()]);
+		assert_eq!(api.traces_streaming(&app, Some(10), |_| Ok(()))?, 2);
+
+		let found = api.trace(&app, "other")?;
+		let found = api.to_json::<TraceObject>(&found)?;
+		assert_eq!(found.iter().map(|t| t.trace_id).collect::<Vec<_>>(), vec!["other"]);
+		assert!(matches!(api.trace(&app, "missing"), Err(JaegerError::EmptyResponse(_))));
+
+		let api = JaegerApi::new("http://127.0.0.1:1")?.file(Some("/nonexistent/traces.json"));
+		assert!(matches!(api.traces(&app), Err(JaegerError::File { .. })));
+		Ok(())
+	}
+
+	#[test]
+	fn should_time_out_hung_requests() -> Result<(), Error> {
+		// accepts connections, but never responds
+		let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+		let url = format!("http://{}", listener.local_addr()?);
+		let api = JaegerApi::new(&url)?.timeout(Duration::from_millis(100)).retries(0);
+
+		let error = api.call(&endpoint(&url, Endpoint::Services), |req| req).unwrap_err().to_string();
+		assert!(error.starts_with(&format!("Request to {}/api/services timed out after", url)), "{}", error);
+		Ok(())
+	}
+
+	#[test]
+	fn should_authenticate_only_with_token() -> Result<(), Error> {
+		let api = JaegerApi::new("http://localhost:16686")?;
+		assert!(!format!("{:?}", api.get("http://localhost:16686/api/services")).contains("Authorization"));
+
+		let token: Token = "secret".parse().unwrap();
+		assert!(!format!("{:?}", token).contains("secret"));
+		let api = JaegerApi::new("http://localhost:16686")?.token(Some(token));
+		assert!(format!("{:?}", api.get("http://localhost:16686/api/services")).contains("Bearer secret"));
+		Ok(())
+	}
+
+	#[test]
+	fn should_route_requests_through_proxy() -> Result<(), Error> {
+		use argh::FromArgs;
+
+		// the proxy is asked to open a tunnel to Jaeger, through which the request is then sent
+		let body = r#"{"data": ["polkadot"], "total": 1, "limit": 0, "offset": 0, "errors": null}"#;
+		let (proxy, server) = serve(vec!["HTTP/1.1 200 Connection established\r\n\r\n".to_string(), ok(body)]);
+
+		let app = App::from_args(&["dot-jaeger"], &["services"]).map_err(|e| anyhow!("{}", e.output))?;
+		let api = JaegerApi::new("http://jaeger.invalid:16686")?.retries(0).proxy(Some(&proxy))?;
+		assert_eq!(api.services(&app)?, vec!["polkadot"]);
+		let requests = server.join().map_err(|_| anyhow!("server thread panicked"))?;
+		assert!(requests[0].starts_with("CONNECT jaeger.invalid:16686 HTTP/1.1"), "{:?}", requests);
+		assert!(requests[1].starts_with("GET /api/services HTTP/1.1"), "{:?}", requests);
+
+		assert!(matches!(
+			JaegerApi::new("http://localhost:16686")?.proxy(Some("ftp://proxy")),
+			Err(JaegerError::InvalidProxy(_))
+		));
+		Ok(())
+	}
+
+	#[test]
+	fn should_fail_with_invalid_client_cert() -> Result<(), Error> {
+		let api = || JaegerApi::new("https://localhost:16686");
+		assert!(matches!(api()?.client_cert(Some("client.pem"), None), Err(JaegerError::ClientCert(_))));
+		assert!(matches!(
+			api()?.client_cert(Some("/nonexistent/client.pem"), Some("/nonexistent/client.key")),
+			Err(JaegerError::File { .. })
+		));
+
+		let path = std::env::temp_dir().join(format!("dot-jaeger-not-a-cert-{}.pem", std::process::id()));
+		std::fs::write(&path, "not a certificate")?;
+		let path = path.to_string_lossy();
+		let error = api()?.client_cert(Some(&path), Some(&path)).err().map(|e| e.to_string()).unwrap_or_default();
+		std::fs::remove_file(path.as_ref())?;
+		assert!(error.contains("no PEM encoded certificate"), "{}", error);
+		Ok(())
+	}
+
+	#[test]
+	fn should_normalize_url() {
+		assert_eq!(normalize_url("http://localhost:16686/").unwrap(), "http://localhost:16686");
+		assert_eq!(normalize_url("https://jaeger.example/prefix//").unwrap(), "https://jaeger.example/prefix");
+		assert_eq!(normalize_url("http://localhost:16686").unwrap(), "http://localhost:16686");
+		for url in ["localhost:16686", "ftp://localhost", "http://", "not a url"] {
+			assert!(matches!(normalize_url(url), Err(JaegerError::InvalidUrl { .. })), "{}", url);
+		}
+	}
+
+	#[test]
+	fn should_build_operations_endpoint() {
+		assert_eq!(
+			endpoint("http://localhost:16686", Endpoint::Operations("polkadot".to_string())),
+			"http://localhost:16686/api/services/polkadot/operations"
+		);
+		assert_eq!(
+			endpoint("http://localhost:16686/prefix", Endpoint::Operations("polka dot/para?#1".to_string())),
+			"http://localhost:16686/prefix/api/services/polka%20dot%2Fpara%3F%231/operations"
+		);
+	}
+
+	#[test]
+	fn should_parse_lookback() -> Result<(), Error> {
+		assert_eq!(parse_lookback("30m")?, Duration::from_secs(30 * 60));
+		assert_eq!(parse_lookback("2d")?, Duration::from_secs(2 * 24 * 60 * 60));
+		assert!(parse_lookback("2w").is_err());
+		assert!(parse_lookback("h").is_err());
+		Ok(())
 	}
 }
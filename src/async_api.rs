@@ -0,0 +1,158 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of dot-jaeger.
+
+// dot-jaeger is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// dot-jaeger is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with dot-jaeger.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Asynchronous counterpart of [`JaegerApi`](crate::api::JaegerApi), built on `reqwest`.
+//! Enabled with the `async` feature, for programs embedding dot-jaeger that query several services at once.
+
+use crate::{
+	api::{
+		check_length, endpoint, normalize_url, parameters, queried_services, Endpoint, JaegerError, Token,
+		DEFAULT_MAX_RESPONSE_BYTES, DEFAULT_TIMEOUT,
+	},
+	cli::App,
+	primitives::RpcResponse,
+};
+use futures::future::join_all;
+use std::{
+	io,
+	time::{Duration, Instant},
+};
+
+/// Queries Jaeger without blocking. Requests must be awaited within a Tokio runtime.
+/// Unlike [`JaegerApi`](crate::api::JaegerApi), failed requests are not retried.
+pub struct AsyncJaegerApi<'a> {
+	/// URL Where Jaeger Agent is running, including port and protocol.
+	url: &'a str,
+	/// Largest response accepted from Jaeger, in bytes.
+	max_response_bytes: usize,
+	/// Time to wait for each request to complete, including connecting.
+	timeout: Duration,
+	/// Shared by all requests.
+	client: reqwest::Client,
+	/// Sent as a bearer token with every request, if any.
+	token: Option<Token>,
+}
+
+impl<'a> AsyncJaegerApi<'a> {
+	/// Instantiate a new API Object.
+	/// Fails if `url` is not the http(s) URL of a host, or the HTTP client can not be set up.
+	pub fn new(url: &'a str) -> Result<Self, JaegerError> {
+		let client = reqwest::Client::builder().build().map_err(|e| JaegerError::Client(e.to_string()))?;
+		Ok(Self {
+			url: normalize_url(url)?,
+			max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+			timeout: DEFAULT_TIMEOUT,
+			client,
+			token: None,
+		})
+	}
+
+	/// Authenticate every request with the bearer `token`.
+	pub fn token(mut self, token: Option<Token>) -> Self {
+		self.token = token;
+		self
+	}
+
+	/// Give up on requests to Jaeger that take longer than `timeout` to complete, including connecting.
+	pub fn timeout(mut self, timeout: Duration) -> Self {
+		self.timeout = timeout;
+		self
+	}
+
+	/// Reject responses from Jaeger larger than `max` bytes.
+	pub fn max_response_bytes(mut self, max: usize) -> Self {
+		self.max_response_bytes = max;
+		self
+	}
+
+	/// Send a GET request with the query parameters `query`, and read the body of the response.
+	async fn get(&self, url: &str, query: &[(&str, String)]) -> Result<String, JaegerError> {
+		let started = Instant::now();
+		let error = |e: reqwest::Error| {
+			let (url, elapsed) = (url.to_string(), started.elapsed());
+			match e.status() {
+				Some(status) => JaegerError::Http { url, status: status.as_u16(), elapsed },
+				None if e.is_timeout() => JaegerError::Timeout { url, message: e.to_string(), elapsed },
+				None => JaegerError::Network { url, message: e.to_string(), elapsed },
+			}
+		};
+		let mut request = self.client.get(url).query(query).timeout(self.timeout);
+		if let Some(token) = self.token.as_ref() {
+			request = request.header("Authorization", token.authorization());
+		}
+		let mut response = request.send().await.and_then(|r| r.error_for_status()).map_err(error)?;
+		check_length(response.content_length().map(|l| l as usize), self.max_response_bytes)?;
+		let mut body = Vec::new();
+		while let Some(chunk) = response.chunk().await.map_err(error)? {
+			body.extend_from_slice(&chunk);
+			if body.len() > self.max_response_bytes {
+				return Err(JaegerError::TooLarge { max: self.max_response_bytes });
+			}
+		}
+		String::from_utf8(body).map_err(|e| JaegerError::Read(io::Error::new(io::ErrorKind::InvalidData, e)))
+	}
+
+	/// Get many traces from this Jaeger Agent, one response for each `--service`.
+	/// Jaeger can only query the traces of one service at a time, so all services are queried concurrently.
+	pub async fn traces(&self, app: &App) -> Result<Vec<String>, JaegerError> {
+		let url = endpoint(self.url, Endpoint::Traces);
+		let queries: Vec<_> = queried_services(app).into_iter().map(|s| parameters(app, s).pairs()).collect();
+		join_all(queries.iter().map(|query| self.get(&url, query))).await.into_iter().collect()
+	}
+
+	/// Get a single trace from the Jaeger Agent
+	pub async fn trace(&self, app: &App, id: &str) -> Result<String, JaegerError> {
+		let url = format!("{}/{}", &endpoint(self.url, Endpoint::Traces), id);
+		self.get(&url, &parameters(app, None).pairs()).await
+	}
+
+	/// Query the services that reporting to this Jaeger Agent
+	pub async fn services(&self) -> Result<Vec<String>, JaegerError> {
+		let url = endpoint(self.url, Endpoint::Services);
+		let response: RpcResponse<String> = serde_json::from_str(&self.get(&url, &[]).await?)?;
+		response.try_consume()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::tests::{ok, response, serve};
+	use anyhow::{anyhow, Error};
+
+	fn block_on<F: std::future::Future>(future: F) -> F::Output {
+		tokio::runtime::Builder::new_current_thread()
+			.enable_all()
+			.build()
+			.expect("can not build the Tokio runtime")
+			.block_on(future)
+	}
+
+	#[test]
+	fn should_query_services() -> Result<(), Error> {
+		let body = r#"{"data": ["polkadot", "kusama"], "total": 2, "limit": 0, "offset": 0, "errors": null}"#;
+		let (url, server) = serve(vec![ok(body), response("503 Service Unavailable", "")]);
+		let api = AsyncJaegerApi::new(&url)?;
+		assert_eq!(block_on(api.services())?, vec!["polkadot", "kusama"]);
+		let error = block_on(api.services()).unwrap_err();
+		assert!(matches!(error, JaegerError::Http { status: 503, .. }), "{}", error);
+		let requests = server.join().map_err(|_| anyhow!("server thread panicked"))?;
+		assert!(requests[0].starts_with("GET /api/services"), "{:?}", requests);
+
+		assert!(matches!(AsyncJaegerApi::new("localhost:16686"), Err(JaegerError::InvalidUrl { .. })));
+		Ok(())
+	}
+}
@@ -14,31 +14,157 @@
 // You should have received a copy of the GNU General Public License
 // along with dot-jaeger.  If not, see <http://www.gnu.org/licenses/>.
 
-use anyhow::Error;
+use anyhow::{bail, Context, Error};
 use argh::FromArgs;
+use itertools::Itertools;
+use regex::Regex;
 
-use crate::{api::JaegerApi, daemon::PrometheusDaemon, primitives::TraceObject};
+use crate::{
+	api::{parameters, proxy_from_env, JaegerApi, Token, DEFAULT_MAX_RESPONSE_BYTES, DEFAULT_MAX_RETRIES},
+	daemon::{
+		daemon_limit, CandidateCollector, PrometheusDaemon, Stage, StageMap, StageNames, DEFAULT_LIFETIME_CANDIDATES,
+		DEFAULT_RECURSION_DEPTH, HASH_IDENTIFIER, STAGE_IDENTIFIER,
+	},
+	funnel::{Funnel, StageWeights},
+	graph::{Graph, Validation},
+	output::{search_table, OutputFormat, Summary},
+	primitives::{
+		dropped_fields, Dependency, DurationSource, DurationUnit, TagFilter, Timestamp, TraceObject, TraceOrder,
+	},
+	rate_limit::RateLimitedLog,
+	tail::SeenTraces,
+};
+use std::{
+	collections::{BTreeSet, HashSet},
+	fs::File,
+	io::Write,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc,
+	},
+	time::Duration,
+};
+
+/// Minimum number of seconds between logging the same error of `tail` again.
+const TAIL_ERROR_LOG_INTERVAL: u64 = 60;
 
 #[derive(FromArgs, PartialEq, Debug)]
 /// Jaeger Trace CLI App
 pub struct App {
 	#[argh(option)]
-	/// name a specific node that reports to the Jaeger Agent from which to query traces.
-	pub service: Option<String>,
+	/// name a specific node that reports to the Jaeger Agent from which to query traces. Repeat to query several
+	/// nodes. Jaeger can only query one service at a time, so each is queried on its own and the traces are merged.
+	pub service: Vec<String>,
 	#[argh(option, default = "String::from(\"http://localhost:16686\")")]
 	/// URL where Jaeger Service runs.
 	pub url: String,
 	#[argh(option)]
-	/// maximum number of traces to return.
+	/// maximum number of traces to return per request to Jaeger (the page size). The daemon uses its own `--limit`.
 	pub limit: Option<usize>,
 	#[argh(option)]
+	/// maximum number of traces to return in total, to bound memory usage. Traces are fetched `--limit` at a time
+	/// until this many are fetched, so a total below `--limit` caps it. With several services, this many are fetched
+	/// of each. The daemon defaults to its own `--limit`, a single request each update.
+	pub total_limit: Option<usize>,
+	#[argh(option)]
 	/// specify how far back in time to look for traces. In format: `1h`, `1d`
 	pub lookback: Option<String>,
+	#[argh(option)]
+	/// only return traces starting after this RFC3339 timestamp, like `2021-03-19T10:30:00Z`.
+	/// Can not be combined with `--lookback`
+	pub start: Option<Timestamp>,
+	#[argh(option)]
+	/// only return traces starting before this RFC3339 timestamp, like `2021-03-19T11:00:00+01:00`.
+	/// Can not be combined with `--lookback`
+	pub end: Option<Timestamp>,
+	#[argh(option)]
+	/// only return traces containing spans of this operation.
+	pub operation: Option<String>,
+	#[argh(option)]
+	/// comma-separated list of tag keys whose values should be replaced with `<redacted>` in the output.
+	pub redact_tags: Option<String>,
+	#[argh(option, default = "DEFAULT_MAX_RESPONSE_BYTES")]
+	/// largest response accepted from Jaeger, in bytes. Default 10 MiB
+	pub max_response_bytes: usize,
+	#[argh(option, default = "DEFAULT_MAX_RETRIES")]
+	/// times a request to Jaeger failing with a network or server error is retried, waiting twice as long before
+	/// every retry. Default 3
+	pub max_retries: usize,
+	#[argh(option, default = "500")]
+	/// milliseconds to wait before the first retry of a failed request to Jaeger. Default 500
+	pub retry_backoff: u64,
+	#[argh(option, default = "30")]
+	/// seconds to wait for Jaeger to accept a connection, and then for each read of a response. Default 30
+	pub timeout: u64,
+	#[argh(option)]
+	/// bearer token sent with every request to Jaeger. Defaults to the `DOT_JAEGER_TOKEN` environment variable.
+	pub token: Option<Token>,
+	#[argh(option)]
+	/// HTTP or SOCKS5 proxy to send every request to Jaeger through, like `http://proxy:3128`.
+	/// Defaults to the `HTTPS_PROXY` or `HTTP_PROXY` environment variable, depending on the scheme of `--url`.
+	pub proxy: Option<String>,
+	#[argh(option)]
+	/// PEM file of the client certificate chain to authenticate to Jaeger with, for mutual TLS.
+	/// Requires `--client-key`.
+	pub client_cert: Option<String>,
+	#[argh(option)]
+	/// PEM file of the PKCS#8 or RSA private key of `--client-cert`.
+	pub client_key: Option<String>,
+	#[argh(switch)]
+	/// log the size of responses received and the number of traces deserialized so far, every second,
+	/// while reading responses of Jaeger. Shows that large responses are progressing.
+	pub progress: bool,
+	#[argh(option)]
+	/// only output spans that were (`true`) or were not (`false`) sampled, according to their flags.
+	pub sampled: Option<bool>,
+	#[argh(option)]
+	/// only output traces with a span that has this tag, in format `key=value`. Repeat to require several tags,
+	/// which may be on different spans. Traces are filtered after they are returned by Jaeger.
+	pub tag: Vec<TagFilter>,
+	#[argh(option)]
+	/// order of the traces in the output of `traces`. One of `start-time`, `duration` (from the start of the earliest
+	/// span to the end of the latest span) or `span-count`. By default traces are in the order Jaeger returns them.
+	pub sort: Option<TraceOrder>,
+	#[argh(switch)]
+	/// sort traces in descending order. Sorts by start time if no `--sort` is given.
+	pub sort_desc: bool,
+	#[argh(switch)]
+	/// print the number of traces, spans and services, and the shortest, average and longest trace duration,
+	/// instead of the traces of `traces`.
+	pub summary: bool,
+	#[argh(switch)]
+	/// add the `serviceName` of the process of each span to the span in the output of `traces`, `trace` and
+	/// `tail`.
+	pub resolve_processes: bool,
+	#[argh(option, default = "OutputFormat::default()")]
+	/// format of the `traces`, `trace` and `tail` output. One of `json` (the default), `pretty-json`, which adds the
+	/// `durationHuman` of each span like `1.5s`, `csv`, which prints one row per span, `jsonl`, which prints
+	/// one compact JSON object per trace per line, `otlp`, which prints an OTLP/JSON export request of
+	/// OpenTelemetry that can be posted to `/v1/traces` of an OTLP collector, or `zipkin`, which prints the
+	/// spans in the JSON of the v2 API of Zipkin. `tail` prints `json` with one trace per line, and only supports
+	/// `json`, `pretty-json` and `jsonl`.
+	pub format: OutputFormat,
+	#[argh(switch)]
+	/// with `--format jsonl`, print one line per span instead of one line per trace.
+	pub jsonl_spans: bool,
+	#[argh(option)]
+	/// write the output of `traces`, `trace` and `tail` to this file instead of stdout. The file is overwritten.
+	pub output: Option<String>,
+	#[argh(option)]
+	/// read traces from this file instead of querying Jaeger, for example to replay saved traces through the daemon.
+	/// The file holds a response of Jaeger, like the output of `traces --raw` for a single service.
+	pub file: Option<String>,
+	#[argh(switch)]
+	/// warn about fields of the traces returned by Jaeger that dot-jaeger does not know, and drops from the output
+	/// of `traces` and `trace`. Helps to catch differences between dot-jaeger and the version of Jaeger in use.
+	pub strict: bool,
 	#[argh(subcommand)]
 	/// what action to perform on Jaeger Service.
 	action: TraceAction,
 }
 
+// Parsed once at startup; boxing the daemon options would only complicate matching on them.
+#[allow(clippy::large_enum_variant)]
 #[derive(FromArgs, PartialEq, Debug)]
 #[argh(subcommand)]
 enum TraceAction {
@@ -46,6 +172,12 @@ enum TraceAction {
 	Trace(Trace),
 	Services(Services),
 	Daemon(Daemon),
+	Funnel(FunnelReport),
+	Tail(Tail),
+	Explore(Explore),
+	Operations(Operations),
+	Dependencies(Dependencies),
+	Search(Search),
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -56,8 +188,32 @@ pub struct Trace {
 	/// the hex string ID of the trace to get. Example: --id 3c58a09870e2dced
 	pub id: String,
 	#[argh(switch)]
-	/// pretty print the JSON.
+	/// pretty print the JSON, adding the `durationHuman` of each span, like `1.5s`.
 	pretty_print: bool,
+	#[argh(switch)]
+	/// instead of the JSON, print the tags present on every span, the tags present on any span and the processes
+	/// of the trace.
+	tag_summary: bool,
+	#[argh(switch)]
+	/// before printing the trace, report its roots, cycles and references to spans missing from the trace.
+	validate: bool,
+	#[argh(switch)]
+	/// instead of the JSON, print the span tree as Graphviz DOT. Example: `dot-jaeger trace --id <id> --graph | dot -Tsvg`
+	graph: bool,
+	#[argh(switch)]
+	/// instead of the JSON, print the span tree as a Mermaid diagram, which renders in GitHub and GitLab markdown.
+	mermaid: bool,
+	#[argh(switch)]
+	/// instead of the JSON, print the chain of spans from each root to the leaf with the greatest cumulative duration,
+	/// with the time each span spent outside of its children.
+	critical_path: bool,
+	#[argh(switch)]
+	/// instead of the JSON, print folded stacks of the operations of the spans weighted by their self-time in
+	/// microseconds. Example: `dot-jaeger trace --id <id> --folded | inferno-flamegraph > trace.svg`
+	folded: bool,
+	#[argh(option)]
+	/// only keep spans at most this many levels below a root of the trace. Roots are at depth 0.
+	max_depth: Option<usize>,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -65,8 +221,20 @@ pub struct Trace {
 /// Get many traces as JSON
 pub struct AllTraces {
 	#[argh(switch)]
-	/// pretty print the JSON
+	/// pretty print the JSON, adding the `durationHuman` of each span, like `1.5s`
 	pub pretty_print: bool,
+	#[argh(option)]
+	/// only print traces with a span whose operation name or one of whose tag values matches this regex.
+	pub filter: Option<String>,
+	#[argh(switch)]
+	/// print the responses of Jaeger exactly as they were received, one per line, without deserializing them.
+	/// Fields dot-jaeger does not know about are kept. Can not be combined with `--filter` or `--redact-tags`.
+	pub raw: bool,
+	#[argh(switch)]
+	/// get every trace Jaeger has, page after page, skipping the traces of previous pages with an offset until the
+	/// `total` Jaeger reports is reached, or a page holds no new traces. Stock Jaeger ignores the offset, so this is
+	/// a single page there. Pages are `--limit` traces long. Can not be combined with `--total-limit`.
+	pub all: bool,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -76,6 +244,37 @@ pub struct Services {
 	#[argh(switch)]
 	/// pretty print the JSON
 	pretty_print: bool,
+	#[argh(option)]
+	/// only print services whose name matches this regex. Example: `polkadot.*`
+	filter: Option<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "operations")]
+/// List of operations of the service given with `--service`
+pub struct Operations {}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "dependencies")]
+/// Calls between services within the `--lookback` window (default 1d)
+pub struct Dependencies {}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "search")]
+/// Search the traces of the `--service` matching every given parameter, and print the trace ID, root operation,
+/// duration and number of spans of each. `--operation`, `--limit`, `--lookback`, `--start` and `--end` are sent to
+/// Jaeger as well
+pub struct Search {
+	#[argh(option)]
+	/// only find traces with a span that has this tag, in format `key=value`. Repeat to require several tags.
+	/// Unlike the `--tag` of `traces`, the tags are matched by Jaeger.
+	pub tag: Vec<TagFilter>,
+	#[argh(option)]
+	/// only find traces with a span lasting at least this long, with a unit, like `100ms` or `1.5s`
+	pub min_duration: Option<String>,
+	#[argh(option)]
+	/// only find traces with a span lasting at most this long, with a unit, like `2s`. Must exceed `--min-duration`
+	pub max_duration: Option<String>,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -83,11 +282,22 @@ pub struct Services {
 /// Daemonize Jaeger Trace collection to run at some interval
 pub struct Daemon {
 	#[argh(option)]
-	/// frequency to update jaeger metrics in milliseconds.
+	/// frequency to update jaeger metrics in milliseconds. Default 1000
 	pub frequency: Option<u64>,
+	#[argh(option)]
+	/// most traces to query Jaeger for in one request, instead of the global `--limit`. Default 1500, at most 10000
+	/// to protect Jaeger from being queried for huge responses every update.
+	pub limit: Option<usize>,
 	#[argh(option, default = "default_port()")]
 	/// port to expose prometheus metrics at. Default 9186
 	pub port: usize,
+	#[argh(option, default = "String::from(\"0.0.0.0\")")]
+	/// IP address of the interface to expose prometheus metrics at, like 127.0.0.1. Default 0.0.0.0, every interface
+	pub bind: String,
+	#[argh(option)]
+	/// name of the network the traces are of, like `kusama`, added as the `network` label to every metric.
+	/// Tells apart the metrics of daemons monitoring different networks.
+	pub network: Option<String>,
 	/// fallback to recursing through parent traces if the current span has one of a candidate hash or stage, but not the other.
 	#[argh(switch)]
 	pub recurse_parents: bool,
@@ -95,58 +305,466 @@ pub struct Daemon {
 	/// fallback to recursing through parent traces if the current span has one of a candidate hash or stage but not the other.
 	/// Recursing children is slower than recursing parents.
 	pub recurse_children: bool,
+	#[argh(option, default = "DEFAULT_RECURSION_DEPTH")]
+	/// number of parent or child spans inspected when recursing through parents or children. Higher values resolve
+	/// more candidates of deeply nested traces, but cost more CPU every update. Default 10
+	pub recursion_depth: usize,
 	#[argh(switch)]
 	/// include candidates that have a stage but no candidate-hash in the prometheus data.
 	pub include_unknown: bool,
+	#[argh(switch)]
+	/// encode the entire metrics response in memory before sending it, instead of streaming it.
+	pub buffer_metrics: bool,
+	#[argh(option, default = "DurationSource::default()")]
+	/// where candidate durations are measured from. One of `span` (span start to span end, the default),
+	/// `first-log-to-end` (first log event to span end) or `log-span` (first log event to last log event).
+	/// Log-based sources fall back to the full span duration for spans without logs.
+	pub duration_source: DurationSource,
+	#[argh(option, default = "60")]
+	/// minimum number of seconds between logging the same error reaching Jaeger again. Default 60
+	pub error_log_interval: u64,
+	#[argh(switch)]
+	/// only observe the durations of candidates that reached the terminal stage, excluding candidates still in flight.
+	/// Durations of a candidate are only observed once it completes, so the histograms lag behind.
+	pub completed_only: bool,
+	#[argh(option, default = "Stage::ApprovalChecking as usize")]
+	/// the stage at which a candidate is considered complete with `--completed-only`. Default 8 (approval checking).
+	/// Stages named with `--stages` can be terminal stages too
+	pub terminal_stage: usize,
+	#[argh(option, default = "0")]
+	/// ignore candidates at a stage before this one. Candidates without a stage are at stage 0,
+	/// so they are ignored by any minimum of 1 or more. Default 0, every candidate
+	pub min_stage: usize,
+	#[argh(option, default = "StageMap::default()")]
+	/// stages of spans without a stage tag, by operation name, as comma-separated `pattern=stage` pairs.
+	/// A span is at the stage of the first pattern contained in its operation name.
+	/// Given patterns take precedence over the defaults for known Polkadot subsystems. Example: `availability-recovery=6`
+	pub stage_map: StageMap,
+	#[argh(option)]
+	/// comma-separated list of services whose span durations and candidates are reported under their own label.
+	/// Spans of other services are reported under `other`. By default every service gets its own label.
+	pub service_allowlist: Option<String>,
+	#[argh(option)]
+	/// only collect candidates from spans of this operation. Repeat to collect from several operations.
+	/// By default spans of every operation are collected
+	pub include_op: Vec<String>,
+	#[argh(option)]
+	/// never collect candidates from spans of this operation, even if it is given with `--include-op`.
+	/// Repeat to exclude several operations
+	pub exclude_op: Vec<String>,
+	#[argh(option, default = "DEFAULT_LIFETIME_CANDIDATES")]
+	/// number of candidate hashes remembered to count unique candidates since startup. Candidates seen again after
+	/// being forgotten are counted twice, so a larger number is more accurate but uses more memory. Default 100000
+	pub lifetime_candidates: usize,
+	#[argh(option)]
+	/// seconds a candidate is remembered after it was last seen, so candidates returned by Jaeger again in later
+	/// updates are not observed into the histograms twice. Gauges still count every current candidate.
+	/// By default candidates are observed again every update
+	pub dedup_window: Option<u64>,
+	#[argh(option, default = "DurationUnit::default()")]
+	/// unit of the `duration` field of spans reported to Jaeger. One of `us` (the default), `ms` or `ns`.
+	/// Durations are converted to milliseconds for the histograms.
+	pub source_duration_unit: DurationUnit,
+	#[argh(option)]
+	/// milliseconds above which a duration is dropped instead of observed into the histograms, like the durations
+	/// of spans with skewed clocks. Negative durations are always dropped and counted the same way
+	pub max_plausible_duration: Option<f64>,
+	#[argh(switch)]
+	/// export the distributions of the durations of all spans by operation name, including spans of no candidate.
+	/// Every operation name gets its own series, so this is off by default.
+	pub track_operations: bool,
+	#[argh(option)]
+	/// path to a JSON object mapping stage numbers to names. Each named stage gets its own gauge and histogram,
+	/// candidates at other stages are counted as unknown. Extends the built-in stages 0 to 8
+	pub stages: Option<String>,
+	#[argh(option, default = "HASH_IDENTIFIER.to_string()")]
+	/// key of the tag holding the candidate hash of a span. Default `candidate-hash`
+	pub hash_tag: String,
+	#[argh(option, default = "STAGE_IDENTIFIER.to_string()")]
+	/// key of the tag holding the stage of a span. Default `candidate-stage`
+	pub stage_tag: String,
+	#[argh(option)]
+	/// URL of a Prometheus Pushgateway to push metrics to after every update and on shutdown, instead of serving them
+	/// at `--port`. Metrics are grouped under the job `dot-jaeger`. Example: http://pushgateway:9091
+	pub push_gateway: Option<String>,
+	#[argh(switch)]
+	/// deserialize traces one at a time while they are received, instead of buffering every response of an update.
+	/// Lowers memory use when querying many traces, but querying and deserializing are then timed together.
+	pub stream_traces: bool,
+	#[argh(switch)]
+	/// query Jaeger and collect metrics once, print the number of candidates found at each stage and exit,
+	/// without serving metrics. Fails if Jaeger can not be reached or returns no traces.
+	pub check: bool,
+	#[argh(switch)]
+	/// collect metrics right away, once, then serve or push them for `--grace` seconds and exit.
+	/// Runs the daemon as a batch job, for instance from cron.
+	pub once: bool,
+	#[argh(option, default = "30")]
+	/// seconds to keep serving the metrics of a single collection for with `--once`, so they can be scraped. Default 30
+	pub grace: u64,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "funnel")]
+/// Report the fraction of candidates that reach each stage of the inclusion pipeline
+pub struct FunnelReport {
+	#[argh(switch)]
+	/// fallback to recursing through parent traces if the current span has one of a candidate hash or stage, but not the other.
+	pub recurse_parents: bool,
+	#[argh(switch)]
+	/// fallback to recursing through child traces if the current span has one of a candidate hash or stage but not the other.
+	pub recurse_children: bool,
+	#[argh(option, default = "StageWeights::default()")]
+	/// severity of candidates dropping out at each stage, as comma-separated `stage=weight` pairs. Example: `2=1.5,5=3`.
+	/// Stages without a weight are weighted 1.
+	pub weights: StageWeights,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "tail")]
+/// Continuously poll for traces and print each new trace as it appears
+pub struct Tail {
+	#[argh(option, default = "1000")]
+	/// frequency to poll for new traces in milliseconds. Default 1000
+	pub frequency: u64,
+	#[argh(switch)]
+	/// pretty print the JSON
+	pub pretty_print: bool,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "explore")]
+/// Interactively explore traces loaded from a file
+pub struct Explore {
+	#[argh(option)]
+	/// file of traces as output by the `traces` subcommand. Line editing and history require building with the
+	/// `explore` feature.
+	pub input: String,
 }
 
 const fn default_port() -> usize {
 	9186
 }
 
+impl App {
+	/// The daemon queries Jaeger every update, so its own capped limit takes the place of the global one.
+	/// Without a `--total-limit`, a single request of that many traces is made each update.
+	pub fn apply_daemon_limits(&mut self) {
+		if let TraceAction::Daemon(daemon) = &self.action {
+			let limit = daemon_limit(daemon);
+			self.limit = Some(limit);
+			self.total_limit = Some(self.total_limit.unwrap_or(limit));
+		}
+	}
+
+	/// API to the Jaeger service configured by the options of the app.
+	pub fn api(&self) -> Result<JaegerApi<'_>, Error> {
+		self.check_time_range()?;
+		Ok(JaegerApi::new(&self.url)?
+			.max_response_bytes(self.max_response_bytes)
+			.retries(self.max_retries)
+			.retry_backoff(Duration::from_millis(self.retry_backoff))
+			.timeout(Duration::from_secs(self.timeout))
+			.token(self.token.clone().or_else(Token::from_env))
+			.file(self.file.as_deref())
+			.proxy(self.proxy.clone().or_else(|| proxy_from_env(&self.url)).as_deref())?
+			.client_cert(self.client_cert.as_deref(), self.client_key.as_deref())?
+			.progress(self.progress))
+	}
+
+	/// Fail if the window of traces to query is ambiguous or empty.
+	fn check_time_range(&self) -> Result<(), Error> {
+		if self.lookback.is_some() && (self.start.is_some() || self.end.is_some()) {
+			bail!(
+				"`--lookback` can not be combined with `--start` or `--end`, which give the window of traces precisely"
+			);
+		}
+		if let (Some(start), Some(end)) = (self.start, self.end) {
+			if end <= start {
+				bail!("`--end` must be after `--start`");
+			}
+		}
+		Ok(())
+	}
+
+	/// Format of the output, where `--pretty-print` of a subcommand is short for `--format pretty-json`.
+	fn output_format(&self, pretty_print: bool) -> OutputFormat {
+		match self.format {
+			OutputFormat::Json if pretty_print => OutputFormat::PrettyJson,
+			OutputFormat::JsonLines { .. } => OutputFormat::JsonLines { spans: self.jsonl_spans },
+			format => format,
+		}
+	}
+
+	/// Serialize `traces` in the output format. Pretty-printed spans also get their duration in a readable unit.
+	fn render(&self, pretty_print: bool, traces: &mut [TraceObject<'_>]) -> Result<String, Error> {
+		let format = self.output_format(pretty_print);
+		if format == OutputFormat::PrettyJson {
+			traces.iter_mut().for_each(TraceObject::humanize_durations);
+		}
+		format.render(traces)
+	}
+
+	/// Write serialized traces to the `--output` file, or to stdout if there is none.
+	fn write_output(&self, out: &str) -> Result<(), Error> {
+		match self.output.as_deref() {
+			Some(path) => {
+				std::fs::write(path, format!("{}\n", out)).with_context(|| format!("failed to write to {}", path))
+			}
+			None => {
+				println!("{}", out);
+				Ok(())
+			}
+		}
+	}
+
+	/// Tag keys that should be redacted from output.
+	pub fn redacted_tags(&self) -> Vec<&str> {
+		self.redact_tags.as_deref().map(|t| t.split(',').map(str::trim).collect()).unwrap_or_default()
+	}
+
+	/// Redact sensitive tags from the traces, if any were specified.
+	fn redact(&self, traces: &mut [TraceObject<'_>]) {
+		let keys = self.redacted_tags();
+		if !keys.is_empty() {
+			traces.iter_mut().for_each(|t| t.redact(&keys));
+		}
+	}
+
+	/// Add the service of its process to each span, if `--resolve-processes` is given.
+	fn resolve_processes(&self, traces: &mut [TraceObject<'_>]) {
+		if self.resolve_processes {
+			traces.iter_mut().for_each(TraceObject::resolve_processes);
+		}
+	}
+
+	/// Whether traces are sorted before they are output.
+	fn sorted(&self) -> bool {
+		self.sort.is_some() || self.sort_desc
+	}
+
+	/// Sort the traces by `--sort`, if they are sorted at all.
+	fn sort_traces(&self, traces: &mut [TraceObject<'_>]) {
+		if self.sorted() {
+			self.sort.unwrap_or_default().sort(traces, self.sort_desc);
+		}
+	}
+
+	/// Remove spans that should not be part of the output.
+	fn filter_spans(&self, traces: &mut [TraceObject<'_>]) {
+		if let Some(sampled) = self.sampled {
+			traces.iter_mut().for_each(|t| t.spans.retain(|_, s| s.is_sampled() == sampled));
+		}
+	}
+}
+
 pub fn app() -> Result<(), Error> {
-	let app: App = argh::from_env();
+	let mut app: App = argh::from_env();
+	app.apply_daemon_limits();
 
 	match &app.action {
-		TraceAction::AllTraces(all_traces) => traces(&app, &all_traces)?,
-		TraceAction::Trace(trace_opts) => trace(&app, &trace_opts)?,
-		TraceAction::Services(serv) => services(&app, &serv)?,
+		TraceAction::AllTraces(all_traces) => traces(&app, all_traces)?,
+		TraceAction::Trace(trace_opts) => trace(&app, trace_opts)?,
+		TraceAction::Services(serv) => services(&app, serv)?,
 		TraceAction::Daemon(daemon) => daemonize(&app, daemon)?,
+		TraceAction::Funnel(report) => funnel(&app, report)?,
+		TraceAction::Tail(tail_opts) => tail(&app, tail_opts)?,
+		TraceAction::Explore(explore_opts) => explore(&app, explore_opts)?,
+		TraceAction::Operations(ops) => operations(&app, ops)?,
+		TraceAction::Dependencies(deps) => dependencies(&app, deps)?,
+		TraceAction::Search(search_opts) => search(&app, search_opts)?,
 	}
 	Ok(())
 }
 
 /// Return All Traces.
 fn traces(app: &App, traces: &AllTraces) -> Result<(), Error> {
-	let api = JaegerApi::new(&app.url);
-	let data = api.traces(app)?;
-	let json = api.to_json::<TraceObject>(&data)?;
-	if traces.pretty_print {
-		println!("{}", serde_json::to_string_pretty(&json)?);
+	let api = app.api()?;
+	let pages = if traces.all {
+		if app.total_limit.is_some() {
+			bail!("`--all` gets every trace, so it can not be combined with `--total-limit`");
+		}
+		api.all_traces(app)?
 	} else {
-		println!("{}", serde_json::to_string(&json)?);
+		api.traces_pages(app, app.total_limit)?
+	};
+	if traces.raw {
+		if traces.filter.is_some() || app.redact_tags.is_some() || !app.tag.is_empty() || app.sorted() {
+			bail!(
+				"`--raw` prints traces unmodified, so it can not be combined with `--filter`, `--tag`, `--sort` or \
+				 `--redact-tags`"
+			);
+		}
+		if app.resolve_processes {
+			bail!("`--raw` prints traces unmodified, so it can not be combined with `--resolve-processes`");
+		}
+		if app.summary {
+			bail!("`--raw` and `--summary` can not be combined");
+		}
+		return app.write_output(pages.iter().map(|p| p.trim_end()).join("\n").as_str());
+	}
+	if app.strict {
+		warn_dropped_fields(&api, &pages)?;
+	}
+	let mut json = api.to_json_pages::<TraceObject>(&pages)?;
+	app.filter_spans(&mut json);
+	app.redact(&mut json);
+	app.resolve_processes(&mut json);
+	if let Some(filter) = traces.filter.as_deref() {
+		let filter = Regex::new(filter).with_context(|| format!("invalid --filter regex `{}`", filter))?;
+		json.retain(|t| t.matches(&filter));
+	}
+	json.retain(|t| app.tag.iter().all(|tag| t.has_tag(tag)));
+	if app.summary {
+		return app.write_output(&Summary::new(&json).to_string());
+	}
+	app.sort_traces(&mut json);
+	app.write_output(&app.render(traces.pretty_print, &mut json)?)?;
+	Ok(())
+}
+
+/// Warn about every field of the traces in `pages` that is lost by deserializing them.
+fn warn_dropped_fields(api: &JaegerApi, pages: &[String]) -> Result<(), Error> {
+	let mut dropped = BTreeSet::new();
+	for trace in api.to_json_pages::<serde_json::Value>(pages)?.iter() {
+		dropped.extend(dropped_fields(trace)?);
+	}
+	for field in dropped.iter() {
+		log::warn!(
+			"Field `{}` of the traces returned by Jaeger is unknown to dot-jaeger and dropped from the output",
+			field
+		);
 	}
 	Ok(())
 }
 
 /// Get a span by its Hex String ID
 fn trace(app: &App, trace: &Trace) -> Result<(), Error> {
-	let api = JaegerApi::new(&app.url);
+	let api = app.api()?;
 	let data = api.trace(app, &trace.id)?;
-	let json = api.to_json::<TraceObject>(&data)?;
-	if trace.pretty_print {
-		println!("{}", serde_json::to_string_pretty(&json)?);
+	if app.strict {
+		warn_dropped_fields(&api, std::slice::from_ref(&data))?;
+	}
+	let mut json = api.to_json::<TraceObject>(&data)?;
+	app.filter_spans(&mut json);
+	if let Some(max_depth) = trace.max_depth {
+		for t in json.iter_mut() {
+			let pruned: HashSet<String> =
+				Graph::new(t)?.prune_below_depth(max_depth).into_iter().map(String::from).collect();
+			t.spans.retain(|id, _| !pruned.contains(*id));
+		}
+	}
+	app.redact(&mut json);
+	app.resolve_processes(&mut json);
+	if trace.validate {
+		for t in json.iter() {
+			let validation = Validation::new(t);
+			if validation.is_valid() {
+				println!("Trace {} is well-formed", t.trace_id);
+			} else {
+				println!("Trace {}", t.trace_id);
+				println!("{}", validation);
+			}
+		}
+	}
+	if trace.graph {
+		for t in json.iter() {
+			println!("{}", Graph::with_follows_from(t)?.to_dot());
+		}
+	} else if trace.mermaid {
+		for t in json.iter() {
+			println!("{}", Graph::with_follows_from(t)?.to_mermaid());
+		}
+	} else if trace.folded {
+		for t in json.iter() {
+			println!("{}", Graph::new(t)?.to_folded()?);
+		}
+	} else if trace.critical_path {
+		for t in json.iter() {
+			let graph = Graph::new(t)?;
+			for root in Validation::new(t).roots {
+				let path = graph.critical_path(root)?;
+				// Jaeger stores durations in microseconds. We divide by 1000 to get milliseconds.
+				let total: f64 = path.iter().map(|s| s.duration).sum::<f64>() / 1000f64;
+				println!("Trace {}, critical path from {} ({}ms)", t.trace_id, root, total);
+				for span in path {
+					let self_time = graph.self_time(span.span_id)? / 1000f64;
+					println!(
+						"  {} {} ({}ms, {}ms self)",
+						span.span_id,
+						span.operation_name,
+						span.duration / 1000f64,
+						self_time
+					);
+				}
+			}
+		}
+	} else if trace.tag_summary {
+		for t in json.iter() {
+			println!("Trace {}", t.trace_id);
+			println!("Tags on every span: {}", t.common_tags().into_iter().join(", "));
+			println!("Tags on any span: {}", t.any_tags().into_iter().join(", "));
+			println!(
+				"Processes: {}",
+				t.processes_sorted().into_iter().map(|(id, p)| format!("{} ({})", id, p.service_name())).join(", ")
+			);
+		}
 	} else {
-		println!("{}", serde_json::to_string(&json)?);
+		app.write_output(&app.render(trace.pretty_print, &mut json)?)?;
 	}
 
 	Ok(())
 }
 
+/// Get a list of operations of a service and print them out.
+fn operations(app: &App, _: &Operations) -> Result<(), Error> {
+	if app.service.is_empty() {
+		bail!("`operations` requires a `--service`");
+	}
+	let api = app.api()?;
+	for service in app.service.iter() {
+		for item in api.operations(service)?.iter() {
+			println!("{}", item);
+		}
+	}
+	Ok(())
+}
+
+/// Print how often services called each other.
+fn dependencies(app: &App, _: &Dependencies) -> Result<(), Error> {
+	let api = app.api()?;
+	let data = api.dependencies(app)?;
+	for item in api.to_json::<Dependency>(&data)?.iter() {
+		println!("{}", item);
+	}
+	Ok(())
+}
+
+/// Search the traces of a single service with every parameter Jaeger supports, and print them as a table.
+fn search(app: &App, search: &Search) -> Result<(), Error> {
+	let service = match app.service.as_slice() {
+		[service] => service,
+		services => bail!("Jaeger searches the traces of one `--service` at a time, found {}", services.len()),
+	};
+	let params = parameters(app, Some(service))
+		.tags(&search.tag)
+		.min_duration(search.min_duration.as_deref())
+		.max_duration(search.max_duration.as_deref());
+	let api = app.api()?;
+	let response = api.search(params)?;
+	let mut traces = api.to_json::<TraceObject>(&response)?;
+	app.sort_traces(&mut traces);
+	app.write_output(&search_table(&traces))
+}
+
 /// Get a list of services reporting to the Jaeger Agent and print them out.
-fn services(app: &App, _: &Services) -> Result<(), Error> {
-	let api = JaegerApi::new(&app.url);
-	let data = api.services(app)?;
+fn services(app: &App, services: &Services) -> Result<(), Error> {
+	let api = app.api()?;
+	let mut data = api.services(app)?;
+	if let Some(filter) = services.filter.as_deref() {
+		let filter = Regex::new(filter).with_context(|| format!("invalid --filter regex `{}`", filter))?;
+		data.retain(|s| filter.is_match(s));
+	}
 	for item in data.iter() {
 		println!("{}", item);
 	}
@@ -155,8 +773,129 @@ fn services(app: &App, _: &Services) -> Result<(), Error> {
 
 /// Daemonize collecting Jaeger Metrics every few seconds, reporting everything to Prometheus.
 fn daemonize(app: &App, daemon: &Daemon) -> Result<(), Error> {
-	let api = JaegerApi::new(&app.url);
+	let api = app.api()?;
+	let check = daemon.check;
 	let mut daemon = PrometheusDaemon::new(daemon, &api, app)?;
+	if check {
+		for (stage, name, count) in daemon.check()? {
+			println!("stage {} ({}): {} candidates", stage, name, count);
+		}
+		return Ok(());
+	}
 	daemon.start()?;
 	Ok(())
 }
+
+/// Print how many candidates reached at least each stage, across all queried traces.
+fn funnel(app: &App, report: &FunnelReport) -> Result<(), Error> {
+	let api = app.api()?;
+	let pages = api.traces_pages(app, app.total_limit)?;
+	let traces = api.to_json_pages::<TraceObject>(&pages)?;
+	let mut collector = CandidateCollector::new(report.recurse_parents, report.recurse_children, false);
+	for trace in traces.iter() {
+		collector.collect(trace)?;
+	}
+	let funnel =
+		Funnel::new(collector.observed_stages().into_values(), &StageNames::default()).weights(report.weights.clone());
+	println!("{}", funnel);
+	Ok(())
+}
+
+/// Poll for traces every few seconds, printing the traces that were not seen before until interrupted.
+fn tail(app: &App, tail: &Tail) -> Result<(), Error> {
+	// each new trace is printed as soon as it appears, so only formats with a line per trace or span fit
+	let format = match app.output_format(tail.pretty_print) {
+		OutputFormat::Json => OutputFormat::JsonLines { spans: false },
+		format @ (OutputFormat::PrettyJson | OutputFormat::JsonLines { .. }) => format,
+		_ => {
+			bail!("`tail` prints each new trace as it appears, so `--format` must be `json`, `pretty-json` or `jsonl`")
+		}
+	};
+	let mut output: Box<dyn Write> = match app.output.as_deref() {
+		Some(path) => Box::new(File::create(path).with_context(|| format!("failed to write to {}", path))?),
+		None => Box::new(std::io::stdout()),
+	};
+	let api = app.api()?;
+	let mut seen = SeenTraces::default();
+	// Jaeger being unreachable for a while does not end the tail
+	let mut error_log = RateLimitedLog::new(Duration::from_secs(TAIL_ERROR_LOG_INTERVAL));
+	let running = Arc::new(AtomicBool::new(true));
+	let r = running.clone();
+	ctrlc::set_handler(move || r.store(false, Ordering::SeqCst))?;
+
+	while running.load(Ordering::SeqCst) {
+		match poll_tail(app, &api, &mut seen, format) {
+			Ok(Some(out)) => {
+				writeln!(output, "{}", out)?;
+				output.flush()?;
+			}
+			Ok(None) => (),
+			Err(e) => error_log.error(format!("Failed to query traces from Jaeger: {}", e)),
+		}
+		std::thread::sleep(Duration::from_millis(tail.frequency));
+	}
+	Ok(())
+}
+
+/// Query the traces that were not seen by a previous poll, and render those that pass the filters.
+/// `None` if there are no new traces to print.
+fn poll_tail(app: &App, api: &JaegerApi, seen: &mut SeenTraces, format: OutputFormat) -> Result<Option<String>, Error> {
+	let pages = api.traces_pages(app, app.total_limit)?;
+	let mut traces = seen.unseen(api.to_json_pages::<TraceObject>(&pages)?);
+	app.filter_spans(&mut traces);
+	traces.retain(|t| app.tag.iter().all(|tag| t.has_tag(tag)));
+	if traces.is_empty() {
+		return Ok(None);
+	}
+	app.redact(&mut traces);
+	app.resolve_processes(&mut traces);
+	render_tail(format, &mut traces).map(Some)
+}
+
+/// Serialize `traces` with one trace per line, or one pretty-printed trace after the other.
+fn render_tail(format: OutputFormat, traces: &mut [TraceObject<'_>]) -> Result<String, Error> {
+	if format != OutputFormat::PrettyJson {
+		return format.render(traces);
+	}
+	traces.iter_mut().for_each(TraceObject::humanize_durations);
+	Ok(traces.iter().map(serde_json::to_string_pretty).collect::<Result<Vec<_>, _>>()?.join("\n"))
+}
+
+/// Load traces from a file once, and answer commands about them until the user quits.
+fn explore(app: &App, explore: &Explore) -> Result<(), Error> {
+	let data = std::fs::read_to_string(&explore.input)?;
+	let mut traces: Vec<TraceObject> = serde_json::from_str(&data)?;
+	app.filter_spans(&mut traces);
+	app.redact(&mut traces);
+	crate::explore::repl(&crate::explore::Explorer::new(&traces))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::tests::{ok, serve, TEST_DATA};
+	use anyhow::anyhow;
+
+	#[test]
+	fn should_filter_tail_by_tag() -> Result<(), Error> {
+		let body = format!(r#"{{"data": [{}], "total": 1, "limit": 0, "offset": 0, "errors": null}}"#, TEST_DATA);
+		let poll = |tag: &str| -> Result<Option<String>, Error> {
+			let (url, server) = serve(vec![ok(&body)]);
+			let args = ["--url", url.as_str(), "--service", "polkadot", "--tag", tag, "tail"];
+			let app = App::from_args(&["dot-jaeger"], &args).map_err(|e| anyhow!("{}", e.output))?;
+			let polled = poll_tail(
+				&app,
+				&app.api()?.retries(0),
+				&mut SeenTraces::default(),
+				OutputFormat::JsonLines { spans: false },
+			);
+			server.join().map_err(|_| anyhow!("server thread panicked"))?;
+			polled
+		};
+		assert!(
+			poll("otel.library.name=mick-jaeger")?.is_some_and(|out| out.contains("6ga7nenJ21rhDy6Fwzjwz7KZQ5Jrii9"))
+		);
+		assert_eq!(poll("otel.library.name=other")?, None);
+		Ok(())
+	}
+}
@@ -21,27 +21,51 @@ use crate::{
 	cli::{App, Daemon},
 	graph::Graph,
 	http::Server,
-	primitives::{Span, TraceObject},
+	primitives::{DurationSource, DurationUnit, Span, TraceObject},
+	rate_limit::RateLimitedLog,
 };
-use anyhow::{bail, Error};
+use anyhow::{anyhow, bail, Context, Error};
 use itertools::Itertools;
-use prometheus::{register_gauge, register_histogram, Gauge, Histogram};
+use prometheus::{
+	proto::MetricFamily, register_gauge, register_gauge_vec, register_histogram_vec, register_int_counter,
+	register_int_counter_vec, Encoder as _, Gauge, GaugeVec, Histogram, HistogramOpts, HistogramVec, IntCounter,
+	IntCounterVec, Opts, Registry, TextEncoder,
+};
+use serde::{de::IgnoredAny, Serialize, Serializer};
 use std::{
-	collections::HashMap,
-	convert::TryFrom,
+	collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
 	iter::Iterator,
-	net::SocketAddr,
+	net::{IpAddr, SocketAddr},
 	str::FromStr,
 	sync::{
-		atomic::{AtomicBool, Ordering},
-		Arc,
+		mpsc::{self, RecvTimeoutError},
+		Arc, Mutex,
 	},
-	time::Duration,
+	time::{Duration, Instant},
 };
 
+/// Key of the tag holding the candidate hash of a span, if no `--hash-tag` is given.
 pub const HASH_IDENTIFIER: &str = "candidate-hash";
+/// Key of the tag holding the stage of a span, if no `--stage-tag` is given.
 pub const STAGE_IDENTIFIER: &str = "candidate-stage";
 pub const NAMESPACE: &str = "dotjaeger_";
+/// Label of the spans of services that are not in the service allowlist.
+pub const OTHER_SERVICE: &str = "other";
+/// Milliseconds between metrics updates if no `--frequency` is given.
+pub const DEFAULT_FREQUENCY: u64 = 1000;
+/// Most traces the daemon asks Jaeger for in one request if no daemon `--limit` is given.
+pub const DEFAULT_DAEMON_LIMIT: usize = 1500;
+/// Most traces the daemon asks Jaeger for in one request whatever its `--limit`, as it queries Jaeger every update.
+pub const MAX_DAEMON_LIMIT: usize = 10_000;
+/// Number of candidate hashes remembered to count unique candidates over the lifetime of the daemon.
+/// A hash takes 32 bytes, plus set and queue overhead, so the default bounds memory to a few megabytes.
+pub const DEFAULT_LIFETIME_CANDIDATES: usize = 100_000;
+/// Number of parent or child spans inspected to resolve a missing hash or stage, if no `--recursion-depth` is given.
+pub const DEFAULT_RECURSION_DEPTH: usize = 10;
+/// Job the metrics are grouped under on a Pushgateway.
+pub const PUSH_JOB: &str = "dot-jaeger";
+/// Prefix of the metrics describing the daemon itself, rather than the parachain.
+pub const INTERNAL_NAMESPACE: &str = "dotjaeger_internal";
 
 /// Default for Histogram Buckets.
 /// Buckets ranging from 250-20,000 milliseconds in steps of 250 milliseconds
@@ -60,269 +84,1064 @@ pub type CandidateHash = [u8; 32];
 
 pub struct PrometheusDaemon<'a> {
 	port: usize,
+	/// IP address of the interface metrics are served at
+	bind: String,
 	api: &'a JaegerApi<'a>,
 	app: &'a App,
 	metrics: Metrics,
-	/// frequency to update metrics in milliseconds
-	frequency: u64,
+	internal: InternalMetrics,
+	/// interval between metrics updates
+	frequency: Duration,
+	/// encode the metrics response in memory before sending it
+	buffer_metrics: bool,
+	/// errors reaching Jaeger, logged at most once per interval
+	error_log: RateLimitedLog,
+	/// if set, metrics are pushed here after every update instead of being served
+	push_gateway: Option<PushGateway>,
+	/// fold traces into the metrics while they are received, instead of after every response has been read
+	stream_traces: bool,
+	/// if set, metrics are collected once and kept available for this long, after which the daemon exits
+	once: Option<Duration>,
 }
 
 impl<'a> PrometheusDaemon<'a> {
 	pub fn new(daemon: &'a Daemon, api: &'a JaegerApi, app: &'a App) -> Result<Self, Error> {
 		let metrics = Metrics::new(daemon)?;
-		let frequency = daemon.frequency.unwrap_or(1000);
-		Ok(Self { port: daemon.port, api, app, metrics, frequency })
+		let internal = InternalMetrics::new(const_labels(daemon))?;
+		let frequency = poll_interval(daemon);
+		let error_log = RateLimitedLog::new(Duration::from_secs(daemon.error_log_interval));
+		let push_gateway =
+			daemon.push_gateway.as_deref().map(|url| PushGateway::new(url, Duration::from_secs(app.timeout)));
+		Ok(Self {
+			port: daemon.port,
+			bind: daemon.bind.clone(),
+			api,
+			app,
+			metrics,
+			internal,
+			frequency,
+			buffer_metrics: daemon.buffer_metrics,
+			error_log,
+			push_gateway,
+			stream_traces: daemon.stream_traces,
+			once: if daemon.once { Some(Duration::from_secs(daemon.grace)) } else { None },
+		})
 	}
 
 	pub fn start(&mut self) -> Result<(), Error> {
-		let addr_raw = format!("0.0.0.0:{}", self.port);
-		let addr: SocketAddr = addr_raw.parse().expect("can not parse listen addr");
-
-		// start the exporter and update metrics every five seconds
-		let exporter = Server::start(addr).expect("can not start exporter server");
-		let running = Arc::new(AtomicBool::new(true));
-		let r = running.clone();
-		ctrlc::set_handler(move || r.store(false, Ordering::SeqCst)).expect("Could not set the Ctrl-C handler.");
-
-		while running.load(Ordering::SeqCst) {
-			std::thread::sleep(Duration::from_millis(self.frequency));
-			self.metrics.clear();
-			let now = std::time::Instant::now();
-			let json = self.api.traces(self.app)?;
-			log::debug!("API Call took {:?} seconds", now.elapsed());
-			if let Err(e) = self.collect_metrics(&json) {
-				log::error!("{}", e.to_string());
-				running.store(false, Ordering::SeqCst);
+		let addr = listen_addr(&self.bind, self.port)?;
+		log::info!(
+			"Querying at most {} traces per request to Jaeger, and {} per update",
+			self.app.limit.unwrap_or(DEFAULT_DAEMON_LIMIT),
+			total_limit(self.app)
+		);
+
+		// start the exporter unless metrics are pushed, and update metrics every few seconds
+		let exporter = match self.push_gateway {
+			Some(ref gateway) => {
+				log::info!("pushing metrics to {}", gateway.url);
+				None
+			}
+			None => Some(
+				Server::start(
+					addr,
+					self.buffer_metrics,
+					self.internal.registry.clone(),
+					self.metrics.collector.clone(),
+				)
+				.with_context(|| format!("can not serve metrics at {}. Is the port already in use?", addr))?,
+			),
+		};
+		// SIGINT and SIGTERM interrupt the wait for the next update
+		let (shutdown, signal) = mpsc::channel();
+		ctrlc::set_handler(move || {
+			let _ = shutdown.send(());
+		})
+		.context("can not set the Ctrl-C handler")?;
+
+		// a single collection starts right away, and is followed by the grace period
+		let mut wait = if self.once.is_some() { Duration::from_secs(0) } else { self.frequency };
+		let mut collected = false;
+		loop {
+			if signal.recv_timeout(wait) != Err(RecvTimeoutError::Timeout) {
+				log::info!("Shutting down");
 				break;
 			}
+			if let Some(grace) = self.once {
+				if collected {
+					log::info!("Exiting after a single collection");
+					break;
+				}
+				collected = true;
+				wait = grace;
+			}
+			self.internal.cycles.inc();
+			let now = std::time::Instant::now();
+			if self.stream_traces {
+				if let Err(e) = self.stream_metrics() {
+					self.internal.errors.inc();
+					self.error_log.error(format!("Failed to collect metrics from the traces of Jaeger: {}", e));
+					continue;
+				}
+			} else {
+				let pages = match self.api.traces_paginated(self.app, total_limit(self.app)) {
+					Ok(pages) => pages,
+					Err(e) => {
+						self.internal.errors.inc();
+						self.error_log.error(format!("Failed to query traces from Jaeger: {}", e));
+						continue;
+					}
+				};
+				self.internal.api_latency.observe(now.elapsed().as_secs_f64());
+				log::debug!("API Call took {:?} seconds", now.elapsed());
+				if let Err(e) = self.collect_metrics(&pages) {
+					self.internal.errors.inc();
+					self.error_log.error(format!("Failed to collect metrics from the traces of Jaeger: {}", e));
+					continue;
+				}
+			}
+			self.internal.cycle_latency.observe(now.elapsed().as_secs_f64());
+			self.push_metrics();
 		}
-		exporter.stop();
+		// push the final state of the metrics on shutdown, including the errors of an update that did not get to push
+		self.push_metrics();
+		// let the server finish responding to the requests it already accepted
+		if let Some(exporter) = exporter {
+			exporter.stop();
+		}
+		self.metrics.log_totals();
 
 		Ok(())
 	}
 
-	fn collect_metrics(&mut self, json: &str) -> Result<(), Error> {
+	/// Push the metrics to the Pushgateway, if they are pushed at all.
+	fn push_metrics(&mut self) {
+		if let Some(gateway) = self.push_gateway.as_ref() {
+			// there is no server for `/debug/metrics`, so the internal metrics are pushed alongside
+			let mut metrics = prometheus::gather();
+			metrics.extend(self.internal.registry.gather());
+			if let Err(e) = gateway.push(&metrics) {
+				self.internal.errors.inc();
+				self.error_log.error(e.to_string());
+			}
+		}
+	}
+
+	/// Query Jaeger and collect metrics once, without serving or pushing them.
+	/// Fails if Jaeger can not be reached or returns no traces.
+	/// Returns the number and name of each stage candidates were seen at, with the number of unique candidates at it.
+	/// Stages missing from the stage names are named `unknown`.
+	pub fn check(&mut self) -> Result<Vec<(usize, String, usize)>, Error> {
+		let pages = self.api.traces(self.app)?;
+		if self.api.to_json_pages::<IgnoredAny>(&pages)?.is_empty() {
+			bail!("Jaeger returned no traces");
+		}
+		self.collect_metrics(&pages)?;
+		let collector = self.metrics.collector.lock().expect("candidate collector lock poisoned");
+		Ok(collector
+			.stage_counts()
+			.into_iter()
+			.map(|(stage, count)| (stage, collector.stage_names.name(stage).unwrap_or("unknown").to_string(), count))
+			.collect())
+	}
+
+	fn collect_metrics(&mut self, pages: &[String]) -> Result<(), Error> {
 		let now = std::time::Instant::now();
-		let traces = self.api.to_json::<TraceObject>(json)?;
+		let traces = self.api.to_json_pages::<TraceObject>(pages)?;
+		self.internal.deserialize_latency.observe(now.elapsed().as_secs_f64());
 		log::debug!("Deserialization took {:?}", now.elapsed());
-		log::info!("Total Traces: {}", traces.len());
 		let now = std::time::Instant::now();
 		self.metrics.update(traces)?;
+		self.internal.update_latency.observe(now.elapsed().as_secs_f64());
 		log::debug!("Updating took {:?}", now.elapsed());
 		Ok(())
 	}
+
+	/// Query traces and fold each into the metrics as soon as it has been deserialized,
+	/// so the traces of an update are never all held in memory at once.
+	/// Receiving and deserializing the traces are observed together as the latency of the API.
+	fn stream_metrics(&mut self) -> Result<(), Error> {
+		let (api, app) = (self.api, self.app);
+		let total_limit = total_limit(self.app);
+		let now = std::time::Instant::now();
+		let mut receiving = Duration::default();
+		self.metrics.update_with(|fold| {
+			let started = std::time::Instant::now();
+			let mut folding = Duration::default();
+			let traces = api.traces_streaming(app, Some(total_limit), |trace| {
+				let now = std::time::Instant::now();
+				let folded = fold(trace);
+				folding += now.elapsed();
+				folded
+			});
+			receiving = started.elapsed().saturating_sub(folding);
+			traces.map(drop)
+		})?;
+		self.internal.api_latency.observe(receiving.as_secs_f64());
+		self.internal.update_latency.observe(now.elapsed().saturating_sub(receiving).as_secs_f64());
+		log::debug!("Receiving traces took {:?}, updating took {:?}", receiving, now.elapsed() - receiving);
+		Ok(())
+	}
+}
+
+/// Pushes metrics to a Prometheus Pushgateway, for setups that scrape a Pushgateway rather than every host.
+struct PushGateway {
+	/// URL of the group of the metrics of dot-jaeger
+	url: String,
+	agent: ureq::Agent,
+}
+
+impl PushGateway {
+	fn new(url: &str, timeout: Duration) -> Self {
+		let url = format!("{}/metrics/job/{}", url.trim_end_matches('/'), PUSH_JOB);
+		Self { url, agent: crate::api::agent(timeout, None, None) }
+	}
+
+	/// Replace the metrics of the group on the Pushgateway with `metrics`.
+	fn push(&self, metrics: &[MetricFamily]) -> Result<(), Error> {
+		let encoder = TextEncoder::new();
+		let mut buffer = Vec::new();
+		encoder.encode(metrics, &mut buffer)?;
+		self.agent
+			.put(&self.url)
+			.set("Content-Type", encoder.format_type())
+			.send_bytes(&buffer)
+			.map_err(|e| anyhow!("Failed to push metrics to {}: {}", self.url, e))?;
+		Ok(())
+	}
+}
+
+/// Most traces queried each update, see [`App::apply_daemon_limits`].
+fn total_limit(app: &App) -> usize {
+	app.total_limit.or(app.limit).unwrap_or(DEFAULT_DAEMON_LIMIT)
+}
+
+/// Most traces to query in one request, given by the `--limit` of the daemon and capped at [`MAX_DAEMON_LIMIT`].
+pub fn daemon_limit(daemon: &Daemon) -> usize {
+	let limit = daemon.limit.unwrap_or(DEFAULT_DAEMON_LIMIT);
+	if limit > MAX_DAEMON_LIMIT {
+		log::warn!("--limit {} of the daemon exceeds the maximum of {}, using the maximum", limit, MAX_DAEMON_LIMIT);
+	}
+	limit.min(MAX_DAEMON_LIMIT)
+}
+
+/// Interval between metrics updates configured by `--frequency`.
+fn poll_interval(daemon: &Daemon) -> Duration {
+	Duration::from_millis(daemon.frequency.unwrap_or(DEFAULT_FREQUENCY))
+}
+
+/// Address metrics are served at, given by `--bind` and `--port`.
+fn listen_addr(bind: &str, port: usize) -> Result<SocketAddr, Error> {
+	let ip: IpAddr = bind.parse().with_context(|| format!("invalid bind address `{}`", bind))?;
+	let port: u16 = std::convert::TryFrom::try_from(port).with_context(|| format!("invalid port {}", port))?;
+	Ok(SocketAddr::new(ip, port))
+}
+
+/// Counts over the traces folded into one update.
+#[derive(Default)]
+struct TraceTally {
+	traces: usize,
+	/// traces missing their root span
+	rootless: usize,
+	/// references from spans to spans of a different trace
+	cross_trace: usize,
+	/// description of the first reference to a different trace
+	cross_trace_example: Option<String>,
+}
+
+/// Operational metrics of the daemon itself, kept in a registry separate from the parachain metrics.
+struct InternalMetrics {
+	registry: Registry,
+	/// collection cycles started
+	cycles: IntCounter,
+	/// errors encountered while querying Jaeger or collecting metrics
+	errors: IntCounter,
+	/// seconds it takes the Jaeger API to respond with traces
+	api_latency: Histogram,
+	/// seconds it takes to deserialize the traces
+	deserialize_latency: Histogram,
+	/// seconds it takes to collect candidates from the traces and update the metrics
+	update_latency: Histogram,
+	/// seconds a collection cycle takes from querying Jaeger to updating the metrics
+	cycle_latency: Histogram,
+}
+
+impl InternalMetrics {
+	pub fn new(labels: HashMap<String, String>) -> Result<Self, Error> {
+		let labels = Some(labels).filter(|l| !l.is_empty());
+		let registry = Registry::new_custom(Some(INTERNAL_NAMESPACE.to_string()), labels)?;
+		let cycles = IntCounter::new("cycles_total", "Collection cycles started by the daemon")?;
+		let errors = IntCounter::new("errors_total", "Errors encountered while querying Jaeger or collecting metrics")?;
+		let api_latency = Histogram::with_opts(HistogramOpts::new(
+			"api_latency_seconds",
+			"Time it takes the Jaeger API to respond with traces",
+		))?;
+		let deserialize_latency = Histogram::with_opts(HistogramOpts::new(
+			"deserialize_seconds",
+			"Time it takes to deserialize the traces returned by Jaeger",
+		))?;
+		let update_latency = Histogram::with_opts(HistogramOpts::new(
+			"update_seconds",
+			"Time it takes to collect candidates from the traces and update the metrics",
+		))?;
+		let cycle_latency = Histogram::with_opts(HistogramOpts::new(
+			"cycle_seconds",
+			"Time a collection cycle takes from querying Jaeger to updating the metrics",
+		))?;
+		registry.register(Box::new(cycles.clone()))?;
+		registry.register(Box::new(errors.clone()))?;
+		registry.register(Box::new(api_latency.clone()))?;
+		registry.register(Box::new(deserialize_latency.clone()))?;
+		registry.register(Box::new(update_latency.clone()))?;
+		registry.register(Box::new(cycle_latency.clone()))?;
+		Ok(Self { registry, cycles, errors, api_latency, deserialize_latency, update_latency, cycle_latency })
+	}
 }
 
 /// Objects that tracks metrics per-candidate.
-/// Keeps spans without a candidate in a separate list, for potential reference.
+/// Candidate metrics are labeled by the service that reported the candidate.
 struct Metrics {
-	candidates: HashMap<Stage, Vec<Candidate>>,
-	parachain_total_candidates: Gauge,
+	/// shared with the metrics server, which exposes the candidates for debugging
+	collector: Arc<Mutex<CandidateCollector>>,
+	parachain_total_candidates: GaugeVec,
+	/// candidates with a hash but without a stage, which suggests stage resolution is failing
+	parachain_unstaged_candidates: GaugeVec,
 	// the `zero` stage signifies a candidate that has no stage associated
-	parachain_stage_gauges: [Gauge; 9],
-	parachain_stage_histograms: [Histogram; 9],
-	recurse_parents: bool,
-	recurse_children: bool,
-	include_unknown: bool,
+	stage_metrics: BTreeMap<usize, StageMetrics>,
+	/// candidates at stages that are not in the stage mapping
+	unknown_stage_metrics: StageMetrics,
+	/// traces returned without their root span in the last collection
+	rootless_traces: Gauge,
+	/// candidates observed moving backwards through the pipeline, summed over all collections
+	stage_regressions: IntCounter,
+	/// references to spans of other traces in the last collection
+	cross_trace_references: Gauge,
+	/// warnings Jaeger reported for traces and spans, by [`warning_category`]
+	trace_warnings: IntCounterVec,
+	/// if set, only observe the durations of candidates that reached this stage
+	terminal_stage: Option<usize>,
+	/// durations of all spans, by the service that reported them
+	span_durations: HistogramVec,
+	/// spans in the last collection, by the service that reported them
+	spans_total: GaugeVec,
+	/// time candidates take to move from one stage to the next, by both stages
+	stage_transition_durations: HistogramVec,
+	/// services that get their own label in `span_durations` and the candidate metrics
+	service_allowlist: Option<HashSet<String>>,
+	/// converts span durations to milliseconds and drops implausible ones
+	durations: SpanDurations,
+	/// durations of all spans, by operation name, if enabled with `--track-operations`
+	operation_durations: Option<HistogramVec>,
+	/// unique candidates seen since the daemon started
+	unique_candidates_lifetime: Gauge,
+	lifetime_candidates: LifetimeCandidates,
+	/// candidates whose durations were observed recently, if `--dedup-window` is set
+	dedup_window: Option<DedupWindow>,
 }
 
 impl Metrics {
 	pub fn new(daemon: &Daemon) -> Result<Self, Error> {
-		let parachain_total_candidates = register_gauge!(
-			NAMESPACE.to_string() + "parachain_total_candidates",
-			"Total candidates registered on this node"
+		let labels = const_labels(daemon);
+		let parachain_total_candidates = register_gauge_vec!(
+			opts(
+				NAMESPACE.to_string() + "parachain_total_candidates",
+				"Total candidates registered on this node",
+				&labels
+			),
+			&["service"]
 		)
 		.expect("can not create gauge parachain_total_candidates metric");
-		let parachain_stage_gauges = [
-			register_gauge!(
-				NAMESPACE.to_string() + "stage_0_candidates",
-				"Total Candidates without an associated stage"
-			)
-			.expect("can not create gauge stage_0_candidates metric"),
-			register_gauge!(NAMESPACE.to_string() + "stage_1_candidates", "Total Candidates on Stage 1")
-				.expect("can not create gauge stage_1_candidates metric"),
-			register_gauge!(NAMESPACE.to_string() + "stage_2_candidates", "Total Candidates on Stage 2")
-				.expect("can not create gauge stage_2_candidates metric"),
-			register_gauge!(NAMESPACE.to_string() + "stage_3_candidates", "Total Candidates on Stage 3")
-				.expect("can not create gauge stage_3_candidates metric"),
-			register_gauge!(NAMESPACE.to_string() + "stage_4_candidates", "Total Candidates on Stage 4")
-				.expect("can not create gauge stage_4_candidates metric"),
-			register_gauge!(NAMESPACE.to_string() + "stage_5_candidates", "Total Candidates on Stage 5")
-				.expect("can not create gauge stage_5_candidates metric"),
-			register_gauge!(NAMESPACE.to_string() + "stage_6_candidates", "Total Candidates on Stage 6")
-				.expect("can not create gauge stage_6_candidates metric"),
-			register_gauge!(NAMESPACE.to_string() + "stage_7_candidates", "Total Candidates on Stage 7")
-				.expect("can not create gauge stage_7_candidates metric"),
-			register_gauge!(NAMESPACE.to_string() + "stage_8_candidates", "Total Candidates on Stage 8")
-				.expect("can not create gauge stage_8_candidates metric"),
-		];
-
-		let parachain_stage_histograms = [
-			register_histogram!(
-				NAMESPACE.to_string() + "stage_0_duration",
-				"Distributions of the time it takes for stage to complete",
-				HISTOGRAM_BUCKETS.to_vec()
-			)?,
-			register_histogram!(
-				NAMESPACE.to_string() + "stage_1_duration",
-				"Distributions of the time it takes for stage to complete",
-				HISTOGRAM_BUCKETS.to_vec()
-			)?,
-			register_histogram!(
-				NAMESPACE.to_string() + "stage_2_duration",
-				"Distributions of the time it takes for stage to complete",
-				HISTOGRAM_BUCKETS.to_vec()
-			)?,
-			register_histogram!(
-				NAMESPACE.to_string() + "stage_3_duration",
-				"Distributions of the time it takes for stage to complete",
-				HISTOGRAM_BUCKETS.to_vec()
-			)?,
-			register_histogram!(
-				NAMESPACE.to_string() + "stage_4_duration",
-				"Distributions of the time it takes for stage to complete",
-				HISTOGRAM_BUCKETS.to_vec()
-			)?,
-			register_histogram!(
-				NAMESPACE.to_string() + "stage_5_duration",
-				"Distributions of the time it takes for stage to complete",
-				HISTOGRAM_BUCKETS.to_vec()
-			)?,
-			register_histogram!(
-				NAMESPACE.to_string() + "stage_6_duration",
-				"Distributions of the time it takes for stage to complete",
-				HISTOGRAM_BUCKETS.to_vec()
-			)?,
-			register_histogram!(
-				NAMESPACE.to_string() + "stage_7_duration",
-				"Distributions of the time it takes for stage to complete",
-				HISTOGRAM_BUCKETS.to_vec()
-			)?,
-			register_histogram!(
-				NAMESPACE.to_string() + "stage_8_duration",
-				"Distributions of the time it takes for stage to complete",
-				HISTOGRAM_BUCKETS.to_vec()
-			)?,
-		];
+		let parachain_unstaged_candidates = register_gauge_vec!(
+			opts(
+				NAMESPACE.to_string() + "parachain_unstaged_candidates",
+				"Candidates with a hash but without a stage",
+				&labels
+			),
+			&["service"]
+		)
+		.expect("can not create gauge parachain_unstaged_candidates metric");
+		let rootless_traces = register_gauge!(opts(
+			NAMESPACE.to_string() + "rootless_traces",
+			"Traces returned by Jaeger without their root span. A high count suggests widening the lookback window",
+			&labels
+		))
+		.expect("can not create gauge rootless_traces metric");
+		let stage_regressions = register_int_counter!(opts(
+			NAMESPACE.to_string() + "stage_regressions_total",
+			"Candidates observed at an earlier stage after already being observed at a later one",
+			&labels
+		))?;
+		let cross_trace_references = register_gauge!(opts(
+			NAMESPACE.to_string() + "cross_trace_references",
+			"References from spans to spans of a different trace",
+			&labels
+		))
+		.expect("can not create gauge cross_trace_references metric");
+		let implausible_durations = register_int_counter!(opts(
+			NAMESPACE.to_string() + "implausible_durations_total",
+			"Durations dropped instead of being observed, because they were negative or above --max-plausible-duration",
+			&labels
+		))?;
+		let trace_warnings = register_int_counter_vec!(
+			opts(
+				NAMESPACE.to_string() + "parachain_trace_warnings_total",
+				"Warnings Jaeger reported for traces and spans, like clock skew. Their spans may have wrong durations",
+				&labels
+			),
+			&["category"]
+		)?;
+		let unique_candidates_lifetime = register_gauge!(opts(
+			NAMESPACE.to_string() + "unique_candidates_lifetime",
+			"Unique candidates seen since the daemon started",
+			&labels
+		))
+		.expect("can not create gauge unique_candidates_lifetime metric");
+		let span_durations = register_histogram_vec!(
+			histogram_opts(
+				NAMESPACE.to_string() + "span_duration",
+				"Distributions of the durations of all spans, by service",
+				&labels
+			),
+			&["service"]
+		)?;
+		let spans_total = register_gauge_vec!(
+			opts(
+				NAMESPACE.to_string() + "spans_total",
+				"Spans returned by Jaeger in the last update, by service",
+				&labels
+			),
+			&["service"]
+		)?;
+		let stage_transition_durations = register_histogram_vec!(
+			histogram_opts(NAMESPACE.to_string() + "stage_transition_duration", "Distributions of the time it takes candidates to move from one stage to the next stage they are seen at", &labels),
+			&["from", "to"]
+		)?;
+		let operation_durations = if daemon.track_operations {
+			Some(register_histogram_vec!(
+				histogram_opts(
+					NAMESPACE.to_string() + "operation_duration",
+					"Distributions of the durations of all spans, by operation name",
+					&labels
+				),
+				&["operation"]
+			)?)
+		} else {
+			None
+		};
+		let stage_names = daemon.stages.as_deref().map(StageNames::from_file).transpose()?.unwrap_or_default();
+		let last = stage_names.0.keys().next_back().copied();
+		let stage_metrics = stage_names
+			.0
+			.iter()
+			.map(|(number, name)| Ok((*number, StageMetrics::register(*number, name, Some(*number) == last, &labels)?)))
+			.collect::<Result<_, Error>>()?;
+		let unknown_stage_metrics = StageMetrics::register_unknown(&labels)?;
 
 		Ok(Self {
-			candidates: HashMap::new(),
+			collector: Arc::new(Mutex::new(
+				CandidateCollector::new(daemon.recurse_parents, daemon.recurse_children, daemon.include_unknown)
+					.duration_source(daemon.duration_source)
+					.stage_map(daemon.stage_map.clone())
+					.tag_keys(&daemon.hash_tag, &daemon.stage_tag)
+					.recursion_depth(daemon.recursion_depth)
+					.min_stage(daemon.min_stage)
+					.operations(&daemon.include_op, &daemon.exclude_op)
+					.stage_names(stage_names),
+			)),
 			parachain_total_candidates,
-			parachain_stage_gauges,
-			parachain_stage_histograms,
-			recurse_parents: daemon.recurse_parents,
-			recurse_children: daemon.recurse_children,
-			include_unknown: daemon.include_unknown,
+			parachain_unstaged_candidates,
+			stage_metrics,
+			unknown_stage_metrics,
+			rootless_traces,
+			stage_regressions,
+			cross_trace_references,
+			trace_warnings,
+			terminal_stage: if daemon.completed_only { Some(daemon.terminal_stage) } else { None },
+			span_durations,
+			spans_total,
+			stage_transition_durations,
+			service_allowlist: daemon
+				.service_allowlist
+				.as_ref()
+				.map(|s| s.split(',').map(|s| s.trim().to_string()).collect()),
+			durations: SpanDurations {
+				unit: daemon.source_duration_unit,
+				max: daemon.max_plausible_duration,
+				dropped: implausible_durations,
+			},
+			operation_durations,
+			unique_candidates_lifetime,
+			lifetime_candidates: LifetimeCandidates::new(daemon.lifetime_candidates),
+			dedup_window: daemon.dedup_window.map(|secs| DedupWindow::new(Duration::from_secs(secs))),
 		})
 	}
 
-	/// Collect all spans into candidates, and update the Metrics
+	/// Collect all spans into candidates, and update the Metrics.
+	/// Candidates from the previous update are discarded.
 	fn update(&mut self, traces: Vec<TraceObject<'_>>) -> Result<(), Error> {
+		self.update_with(|fold| traces.into_iter().try_for_each(fold))
+	}
+
+	/// Like [`update`](Self::update), but with the traces handed one by one to the callback `traces` is given.
+	/// Each trace is folded into the metrics right away, so it can be dropped before the next one is read.
+	fn update_with<F>(&mut self, traces: F) -> Result<(), Error>
+	where
+		F: FnOnce(&mut dyn FnMut(TraceObject<'_>) -> Result<(), Error>) -> Result<(), Error>,
+	{
+		// collect into a collector of its own, so `/debug/candidates` is not blocked while traces are received
+		let mut collector = self.collector.lock().expect("candidate collector lock poisoned").empty();
+		self.spans_total.reset();
+		let dropped = self.durations.dropped.get();
+		let mut tally = TraceTally::default();
 		let now = std::time::Instant::now();
-		for trace in traces.iter() {
-			self.collect_candidates(&trace)?;
+		traces(&mut |trace| self.fold(&mut collector, &mut tally, &trace))?;
+		log::debug!("Took {:?} to collect candidates and observe span durations", now.elapsed());
+		log::info!("Total Traces: {}", tally.traces);
+
+		self.rootless_traces.set(tally.rootless as f64);
+		if tally.rootless > 0 {
+			log::debug!("{} traces are missing their root span", tally.rootless);
 		}
-		log::debug!("Took {:?} to collect candidates", now.elapsed());
 
-		self.update_metrics()?;
+		self.cross_trace_references.set(tally.cross_trace as f64);
+		if let Some(example) = tally.cross_trace_example.as_ref() {
+			log::debug!("{} references to other traces. {}", tally.cross_trace, example);
+		}
+
+		self.update_metrics(&collector)?;
+		let dropped = self.durations.dropped.get() - dropped;
+		if dropped > 0 {
+			log::warn!("Dropped {} implausible durations. See `--max-plausible-duration`", dropped);
+		}
+
+		let hashes = collector.candidates.values().flatten().filter_map(|c| c.hash);
+		let lifetime = self.lifetime_candidates.insert(hashes);
+		self.unique_candidates_lifetime.set(lifetime as f64);
+
+		let regressions = collector.stage_regressions();
+		self.stage_regressions.inc_by(regressions.len() as u64);
+		if let Some(r) = regressions.first() {
+			log::warn!(
+				"{} stage regressions detected. Candidate 0x{} moved from stage {} back to stage {}",
+				regressions.len(),
+				hex::encode(r.hash),
+				r.from,
+				r.to
+			);
+		}
 
 		log::info!(
 			"Candidates with a hash but without a stage: {:?}",
-			self.candidates.get(&Stage::NoStage).map(|c| c.len())
+			collector.candidates.get(&Stage::NoStage).map(|c| c.len())
 		);
 
-		if self.include_unknown {
+		if collector.include_unknown {
 			log::info!(
 				"Candidates without a hash but with a stage: {}",
-				self.candidates.values().flatten().filter(|c| c.hash.is_none()).count()
+				collector.candidates.values().flatten().filter(|c| c.hash.is_none()).count()
 			);
 		}
+		*self.collector.lock().expect("candidate collector lock poisoned") = collector;
 		Ok(())
 	}
 
-	/// Finds which candidates have a Stage and Hash attached
-	fn collect_candidates<'a>(&mut self, trace: &'a TraceObject<'a>) -> Result<(), Error> {
-		let graph = Graph::new(trace)?;
+	/// Collect the spans of a single trace into candidates, and observe their durations.
+	fn fold(
+		&self,
+		collector: &mut CandidateCollector,
+		tally: &mut TraceTally,
+		trace: &TraceObject<'_>,
+	) -> Result<(), Error> {
+		collector.collect(trace)?;
+		let traces = std::slice::from_ref(trace);
+		observe_span_durations(&self.span_durations, traces, self.service_allowlist.as_ref(), &self.durations);
+		count_spans(&self.spans_total, traces, self.service_allowlist.as_ref());
+		count_warnings(&self.trace_warnings, traces);
+		if let Some(histogram) = self.operation_durations.as_ref() {
+			observe_operation_durations(histogram, traces, &self.durations);
+		}
 
-		for span in trace.spans.values() {
-			if span.get_tag(STAGE_IDENTIFIER).is_none() && span.get_tag(HASH_IDENTIFIER).is_none() {
-				continue;
-			} else if span.get_tag(HASH_IDENTIFIER).is_none() {
-				log::trace!("Missing Hash, trying to resolve..");
-				if let Some(c) = self.try_resolve_missing(&graph, span)? {
-					self.insert_candidate(c);
-				} else if self.include_unknown {
-					let stage = extract_stage_from_span(span)?.expect("Stage must exist because of if check");
-					self.insert_candidate(Candidate {
-						hash: None,
-						operation: span.operation_name.to_string(),
-						start_time: span.start_time,
-						duration: span.duration,
-						stage,
-					});
-				}
-			} else if span.get_tag(STAGE_IDENTIFIER).is_none() {
-				log::trace!("Missing Stage, trying to resolve..");
-				if let Some(c) = self.try_resolve_missing(&graph, span)? {
-					self.insert_candidate(c);
-				}
-			} else {
-				self.insert(span)?;
-			}
+		tally.traces += 1;
+		if !trace.has_root() {
+			tally.rootless += 1;
+		}
+		let cross_trace = trace.cross_trace_references();
+		tally.cross_trace += cross_trace.len();
+		if let (None, Some((span, reference))) = (tally.cross_trace_example.as_ref(), cross_trace.first()) {
+			tally.cross_trace_example = Some(format!(
+				"Span {} references span {} of trace {}",
+				span,
+				reference.span_id(),
+				reference.trace_id()
+			));
 		}
 		Ok(())
 	}
 
 	/// Updates the Prometheus metrics to reflect new trace data
-	fn update_metrics(&mut self) -> Result<(), Error> {
+	fn update_metrics(&mut self, collector: &CandidateCollector) -> Result<(), Error> {
 		let now = std::time::Instant::now();
+		let completed = self.terminal_stage.map(|terminal| collector.completed(terminal));
+		let candidates = || collector.candidates.values().flatten();
 		// Distribution of Candidate Stage deltas
-		for stage in self.candidates.keys() {
-			if let Some(c) = self.candidates.get(&stage) {
-				for candidate in c.iter().filter(|c| c.hash.is_some()).unique_by(|c| c.hash) {
-					// Jaeger stores durations in microseconds. We divide by 1000 to get milliseconds.
-					self.parachain_stage_histograms[*stage as usize].observe(candidate.duration / 1000f64)
-				}
-				// include candidates without a hash if enabled
-				if self.include_unknown {
-					for candidate in c.iter().filter(|c| c.hash.is_none()) {
-						self.parachain_stage_histograms[*stage as usize].observe(candidate.duration / 1000f64)
-					}
-				}
+		let observed = candidates().filter(|c| match (c.hash, completed.as_ref()) {
+			(Some(hash), Some(completed)) => completed.contains(&hash),
+			(hash, None) => hash.is_some(),
+			(None, _) => false,
+		});
+		let observed: Vec<_> =
+			observed.unique_by(|c| (c.stage_number, c.hash, self.service_label(&c.service))).collect();
+		// candidates still in the dedup window were already observed by a previous update
+		if let Some(window) = self.dedup_window.as_mut() {
+			window.expire(now);
+		}
+		for candidate in observed {
+			let key =
+				candidate.hash.map(|h| (h, candidate.stage_number, self.service_label(&candidate.service).to_string()));
+			let recent = match (self.dedup_window.as_mut(), key) {
+				(Some(window), Some(key)) => window.insert(key, now),
+				_ => false,
+			};
+			if !recent {
+				self.observe_duration(candidate);
 			}
 		}
+		// include candidates without a hash if enabled.
+		// Without a hash there is no way to tell whether the candidate completed.
+		if collector.include_unknown && completed.is_none() {
+			for candidate in candidates().filter(|c| c.hash.is_none()) {
+				self.observe_duration(candidate);
+			}
+		}
+
+		// Distribution of the time between consecutive stages of a candidate
+		for transition in collector.stage_transitions() {
+			let (from, to) = (transition.from.to_string(), transition.to.to_string());
+			self.stage_transition_durations
+				.with_label_values(&[&from, &to])
+				.observe(DurationUnit::Micros.to_millis(transition.delta as f64));
+		}
 
 		log::debug!("Took {:?} to update histograms", now.elapsed());
 		let now = std::time::Instant::now();
+		// Services that stopped reporting candidates are dropped, every other service reports every stage.
+		let services: HashSet<&str> = candidates().map(|c| self.service_label(&c.service)).collect();
 		// # Candidates in Each Stage
 		// If include_unknown is enabled, we don't count candidates without a candidate-hash (a `None` hash field), because we have nothing to say which candidates are unique
-		for (i, gauge) in self.parachain_stage_gauges.iter().enumerate() {
-			let count = self
-				.candidates
-				.get(&Stage::try_from(i)?)
-				.map(|c| c.iter().filter_map(|c| c.hash).unique().count())
-				.unwrap_or(0);
-			gauge.set(count as f64);
+		// Candidates at stages that are not in the stage mapping are counted under `None`.
+		let mut unique: HashMap<(Option<usize>, &str), HashSet<CandidateHash>> = HashMap::new();
+		for candidate in candidates() {
+			let stage = Some(candidate.stage_number).filter(|n| self.stage_metrics.contains_key(n));
+			if let Some(hash) = candidate.hash {
+				unique.entry((stage, self.service_label(&candidate.service))).or_default().insert(hash);
+			}
+		}
+		let unmapped: BTreeSet<usize> =
+			candidates().map(|c| c.stage_number).filter(|n| !self.stage_metrics.contains_key(n)).collect();
+		if !unmapped.is_empty() {
+			log::warn!("Counting candidates at stages {:?} as unknown stages. Name them with `--stages`", unmapped);
+		}
+		let stages = self.stage_metrics.iter().map(|(n, m)| (Some(*n), m));
+		for (stage, metrics) in stages.chain(std::iter::once((None, &self.unknown_stage_metrics))) {
+			metrics.candidates.reset();
+			for service in services.iter() {
+				let count = unique.get(&(stage, *service)).map(|h| h.len()).unwrap_or(0);
+				metrics.candidates.with_label_values(&[service]).set(count as f64);
+			}
+		}
+
+		// # Candidates stuck at each stage, seen at the stage but not at the next one
+		let stuck_counts = collector.stuck_counts(|s| service_label(s, self.service_allowlist.as_ref()));
+		for (number, metrics) in self.stage_metrics.iter() {
+			if let Some(stuck) = metrics.stuck.as_ref() {
+				stuck.reset();
+				for service in services.iter() {
+					let count = stuck_counts.get(&(*number, *service)).copied().unwrap_or(0);
+					stuck.with_label_values(&[service]).set(count as f64);
+				}
+			}
 		}
 
 		log::debug!("Took {:?} to update candidates in each stage", now.elapsed());
 		let now = std::time::Instant::now();
 		// Total Number of Candidates
-		let count: usize = self.candidates.values().flatten().unique_by(|c| c.hash).count();
-		self.parachain_total_candidates.set(count as f64);
+		self.parachain_total_candidates.reset();
+		for service in services.iter() {
+			let count = collector
+				.candidates
+				.values()
+				.flatten()
+				.filter(|c| self.service_label(&c.service) == *service)
+				.unique_by(|c| c.hash)
+				.count();
+			self.parachain_total_candidates.with_label_values(&[service]).set(count as f64);
+		}
 		log::debug!("Took {:?} to update total number of candidates", now.elapsed());
+
+		// Candidates without a stage
+		self.parachain_unstaged_candidates.reset();
+		for service in services.iter() {
+			let count = collector
+				.candidates
+				.get(&Stage::NoStage)
+				.map(|c| c.iter().filter(|c| self.service_label(&c.service) == *service).count())
+				.unwrap_or(0);
+			self.parachain_unstaged_candidates.with_label_values(&[service]).set(count as f64);
+		}
 		Ok(())
 	}
 
-	/// Inserts an item into the Candidate List.
-	pub fn insert<'a>(&mut self, span: &'a Span<'a>) -> Result<(), Error> {
-		if let Some(c) = Option::<Candidate>::try_from(span)? {
-			self.insert_candidate(c);
+	/// Log how many candidates were counted in the last update, and since the daemon started.
+	fn log_totals(&self) {
+		let collector = self.collector.lock().expect("candidate collector lock poisoned");
+		let last = collector.candidates.values().flatten().filter_map(|c| c.hash).unique().count();
+		log::info!(
+			"Counted {} unique candidates in the last update, {} since startup",
+			last,
+			self.lifetime_candidates.count
+		);
+	}
+
+	/// Observe the duration of `candidate` in the histogram of its stage.
+	fn observe_duration(&self, candidate: &Candidate) {
+		let metrics = self.stage_metrics.get(&candidate.stage_number).unwrap_or(&self.unknown_stage_metrics);
+		if let Some(millis) = self.durations.millis(candidate.duration) {
+			metrics.durations.with_label_values(&[self.service_label(&candidate.service)]).observe(millis);
+		}
+	}
+
+	/// The label of the candidates of `service`, see [`service_label`].
+	fn service_label<'s>(&self, service: &'s str) -> &'s str {
+		service_label(service, self.service_allowlist.as_ref())
+	}
+}
+
+/// The number of candidates at a stage and the distribution of their durations, by service.
+struct StageMetrics {
+	candidates: GaugeVec,
+	durations: HistogramVec,
+	/// candidates seen at this stage but not at the next stage, unless this is the last stage
+	stuck: Option<GaugeVec>,
+}
+
+impl StageMetrics {
+	/// Register the metrics of stage `number`. Candidates stuck at the stage are only counted if it is not the `last`.
+	fn register(number: usize, name: &str, last: bool, labels: &HashMap<String, String>) -> Result<Self, Error> {
+		let candidates_help = if number == Stage::NoStage as usize {
+			"Total Candidates without an associated stage".to_string()
+		} else {
+			format!("Total Candidates on Stage {} ({})", number, name)
+		};
+		let candidates = register_gauge_vec!(
+			opts(format!("{}stage_{}_candidates", NAMESPACE, number), candidates_help, labels),
+			&["service"]
+		)?;
+		let durations = register_histogram_vec!(
+			histogram_opts(
+				format!("{}stage_{}_duration", NAMESPACE, number),
+				format!("Distributions of the time it takes for stage {} ({}) to complete", number, name),
+				labels
+			),
+			&["service"]
+		)?;
+		let stuck = if number == Stage::NoStage as usize || last {
+			None
+		} else {
+			Some(register_gauge_vec!(
+				opts(
+					format!("{}stage_{}_stuck_candidates", NAMESPACE, number),
+					format!("Candidates seen at stage {} ({}), but not at the next stage", number, name),
+					labels
+				),
+				&["service"]
+			)?)
+		};
+		Ok(Self { candidates, durations, stuck })
+	}
+
+	/// Metrics of the candidates at stages that are not in the stage mapping.
+	fn register_unknown(labels: &HashMap<String, String>) -> Result<Self, Error> {
+		let candidates = register_gauge_vec!(
+			opts(NAMESPACE.to_string() + "stage_unknown_candidates", "Total Candidates on an unknown Stage", labels),
+			&["service"]
+		)?;
+		let durations = register_histogram_vec!(
+			histogram_opts(
+				NAMESPACE.to_string() + "stage_unknown_duration",
+				"Distributions of the time it takes for an unknown stage to complete",
+				labels
+			),
+			&["service"]
+		)?;
+		Ok(Self { candidates, durations, stuck: None })
+	}
+}
+
+/// Constant labels of every metric of the daemon: the `--network`, if one is given.
+fn const_labels(daemon: &Daemon) -> HashMap<String, String> {
+	daemon.network.iter().map(|network| ("network".to_string(), network.clone())).collect()
+}
+
+/// Converts the durations of spans to milliseconds for the histograms, dropping durations that can not be right,
+/// like the negative durations of spans whose clocks are skewed. One such span would otherwise skew the quantiles.
+struct SpanDurations {
+	/// unit of the span durations reported by Jaeger
+	unit: DurationUnit,
+	/// durations above this many milliseconds are dropped
+	max: Option<f64>,
+	/// durations dropped since the daemon started
+	dropped: IntCounter,
+}
+
+impl SpanDurations {
+	/// `duration` in milliseconds, or `None` if it is implausible.
+	fn millis(&self, duration: f64) -> Option<f64> {
+		let millis = self.unit.to_millis(duration);
+		if millis >= 0.0 && self.max.is_none_or(|max| millis <= max) {
+			Some(millis)
+		} else {
+			log::debug!("Dropping implausible duration of {}ms", millis);
+			self.dropped.inc();
+			None
+		}
+	}
+}
+
+/// Options of a metric carrying the constant `labels`.
+fn opts(name: String, help: impl Into<String>, labels: &HashMap<String, String>) -> Opts {
+	Opts::new(name, help).const_labels(labels.clone())
+}
+
+/// Options of a histogram of [`HISTOGRAM_BUCKETS`] carrying the constant `labels`.
+fn histogram_opts(name: String, help: impl Into<String>, labels: &HashMap<String, String>) -> HistogramOpts {
+	HistogramOpts::new(name, help).const_labels(labels.clone()).buckets(HISTOGRAM_BUCKETS.to_vec())
+}
+
+/// Counts the unique candidate hashes seen across collections.
+///
+/// Only the `cap` most recently first-seen hashes are remembered. A candidate seen again after its hash
+/// was evicted is counted twice, so the count may overestimate once more than `cap` candidates were seen.
+/// Memory usage is bounded by `cap` in exchange.
+struct LifetimeCandidates {
+	seen: HashSet<CandidateHash>,
+	/// hashes in the order they were first seen, oldest first
+	order: VecDeque<CandidateHash>,
+	cap: usize,
+	count: usize,
+}
+
+impl LifetimeCandidates {
+	fn new(cap: usize) -> Self {
+		Self { seen: HashSet::new(), order: VecDeque::new(), cap, count: 0 }
+	}
+
+	/// Remember `hashes`, evicting the oldest hashes past the cap. Returns the number of unique hashes seen so far.
+	fn insert(&mut self, hashes: impl IntoIterator<Item = CandidateHash>) -> usize {
+		for hash in hashes {
+			if self.cap == 0 || !self.seen.insert(hash) {
+				continue;
+			}
+			self.count += 1;
+			self.order.push_back(hash);
+			if self.order.len() > self.cap {
+				if let Some(oldest) = self.order.pop_front() {
+					self.seen.remove(&oldest);
+				}
+			}
+		}
+		self.count
+	}
+}
+
+/// Remembers the candidates whose durations were observed, so that candidates returned by Jaeger
+/// again in later updates are not observed into the histograms twice.
+///
+/// A candidate is forgotten once it has not been seen for `ttl`, which bounds memory usage.
+/// Candidates are identified by their hash, stage and service label.
+struct DedupWindow {
+	ttl: Duration,
+	/// when each candidate was last seen
+	seen: HashMap<(CandidateHash, usize, String), Instant>,
+}
+
+impl DedupWindow {
+	fn new(ttl: Duration) -> Self {
+		Self { ttl, seen: HashMap::new() }
+	}
+
+	/// Remember that `candidate` was seen at `now`. Returns whether it was already seen within the window.
+	fn insert(&mut self, candidate: (CandidateHash, usize, String), now: Instant) -> bool {
+		self.seen.insert(candidate, now).is_some()
+	}
+
+	/// Forget candidates that have not been seen for longer than the window.
+	fn expire(&mut self, now: Instant) {
+		let ttl = self.ttl;
+		self.seen.retain(|_, seen| now.saturating_duration_since(*seen) <= ttl);
+	}
+}
+
+/// Collects spans from traces into candidates, grouped by the stage they were observed at.
+pub struct CandidateCollector {
+	candidates: HashMap<Stage, Vec<Candidate>>,
+	recurse_parents: bool,
+	recurse_children: bool,
+	/// number of parents or children inspected when recursing
+	recursion_depth: usize,
+	/// candidates at an earlier stage are skipped
+	min_stage: usize,
+	include_unknown: bool,
+	duration_source: DurationSource,
+	stage_map: StageMap,
+	/// key of the tag holding the candidate hash
+	hash_tag: String,
+	/// key of the tag holding the stage
+	stage_tag: String,
+	/// if not empty, only spans of these operations are collected
+	include_operations: HashSet<String>,
+	/// spans of these operations are never collected
+	exclude_operations: HashSet<String>,
+	/// stages of the pipeline, candidates at other stages are left out of the analyses of the pipeline
+	stage_names: StageNames,
+}
+
+impl CandidateCollector {
+	pub fn new(recurse_parents: bool, recurse_children: bool, include_unknown: bool) -> Self {
+		Self {
+			candidates: HashMap::new(),
+			recurse_parents,
+			recurse_children,
+			recursion_depth: DEFAULT_RECURSION_DEPTH,
+			min_stage: 0,
+			include_unknown,
+			duration_source: DurationSource::default(),
+			stage_map: StageMap::default(),
+			hash_tag: HASH_IDENTIFIER.to_string(),
+			stage_tag: STAGE_IDENTIFIER.to_string(),
+			include_operations: HashSet::new(),
+			exclude_operations: HashSet::new(),
+			stage_names: StageNames::default(),
+		}
+	}
+
+	/// Stages of the inclusion pipeline, including stages named with `--stages`.
+	pub fn stage_names(mut self, names: StageNames) -> Self {
+		self.stage_names = names;
+		self
+	}
+
+	/// Whether `candidate` is at a stage of the pipeline, rather than without a stage or at a stage without a name.
+	fn in_pipeline(&self, candidate: &Candidate) -> bool {
+		candidate.stage_number != Stage::NoStage as usize && self.stage_names.0.contains_key(&candidate.stage_number)
+	}
+
+	/// Only collect spans of the `include` operations, if any are given, and never spans of the `exclude` operations.
+	/// Spans of other operations are still inspected to resolve a missing hash or stage.
+	pub fn operations(mut self, include: &[String], exclude: &[String]) -> Self {
+		self.include_operations = include.iter().cloned().collect();
+		self.exclude_operations = exclude.iter().cloned().collect();
+		self
+	}
+
+	/// Whether spans of `operation` are collected.
+	fn collects(&self, operation: &str) -> bool {
+		(self.include_operations.is_empty() || self.include_operations.contains(operation))
+			&& !self.exclude_operations.contains(operation)
+	}
+
+	/// Which stage a span is at if it has no stage tag, based on its operation name.
+	pub fn stage_map(mut self, stage_map: StageMap) -> Self {
+		self.stage_map = stage_map;
+		self
+	}
+
+	/// Number of parent or child spans inspected when recursing to resolve a missing hash or stage.
+	pub fn recursion_depth(mut self, depth: usize) -> Self {
+		self.recursion_depth = depth;
+		self
+	}
+
+	/// Skip candidates at a stage before `stage`. Candidates without a stage are at stage 0.
+	pub fn min_stage(mut self, stage: usize) -> Self {
+		self.min_stage = stage;
+		self
+	}
+
+	/// Keys of the tags holding the candidate hash and the stage of a span.
+	pub fn tag_keys(mut self, hash_tag: &str, stage_tag: &str) -> Self {
+		self.hash_tag = hash_tag.to_string();
+		self.stage_tag = stage_tag.to_string();
+		self
+	}
+
+	/// Where the duration of a candidate is measured from.
+	pub fn duration_source(mut self, source: DurationSource) -> Self {
+		self.duration_source = source;
+		self
+	}
+
+	/// Finds which candidates have a Stage and Hash attached
+	pub fn collect<'a>(&mut self, trace: &'a TraceObject<'a>) -> Result<(), Error> {
+		let graph = Graph::new(trace)?;
+
+		for span in trace.spans.values() {
+			if !self.collects(span.operation_name) {
+				continue;
+			}
+			let service = trace.service_name(span).unwrap_or(OTHER_SERVICE);
+			let stage = extract_stage_from_span(span, &self.stage_tag, &self.stage_map)?;
+			if stage.is_none() && span.get_tag(&self.hash_tag).is_none() {
+				continue;
+			} else if span.get_tag(&self.hash_tag).is_none() {
+				log::trace!("Missing Hash, trying to resolve..");
+				if let Some(c) = self.try_resolve_missing(&graph, span, service)? {
+					self.insert_candidate(c);
+				} else if self.include_unknown {
+					let stage = stage.expect("Stage must exist because of if check");
+					self.insert_candidate(Candidate {
+						hash: None,
+						operation: span.operation_name.to_string(),
+						start_time: span.start_time,
+						duration: span.duration_from(self.duration_source),
+						stage: Stage::from(stage),
+						stage_number: stage,
+						service: service.to_string(),
+					});
+				}
+			} else if stage.is_none() {
+				log::trace!("Missing Stage, trying to resolve..");
+				if let Some(c) = self.try_resolve_missing(&graph, span, service)? {
+					self.insert_candidate(c);
+				}
+			} else {
+				self.insert(span, service)?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Inserts an item reported by `service` into the Candidate List.
+	pub fn insert<'a>(&mut self, span: &'a Span<'a>, service: &str) -> Result<(), Error> {
+		if let Some(hash) = extract_hash_from_span(span, &self.hash_tag)? {
+			let stage =
+				extract_stage_from_span(span, &self.stage_tag, &self.stage_map)?.unwrap_or(Stage::NoStage as usize);
+			self.insert_candidate(Candidate {
+				hash: Some(hash),
+				operation: span.operation_name.to_string(),
+				start_time: span.start_time,
+				duration: span.duration_from(self.duration_source),
+				stage: Stage::from(stage),
+				stage_number: stage,
+				service: service.to_string(),
+			});
 		}
 		Ok(())
 	}
 
 	fn insert_candidate(&mut self, candidate: Candidate) {
+		if candidate.stage_number < self.min_stage {
+			return;
+		}
 		if let Some(v) = self.candidates.get_mut(&candidate.stage) {
 			v.push(candidate);
 		} else {
@@ -333,18 +1152,23 @@ impl Metrics {
 	/// Try to resolve a missing candidate hash or a missing stage by inspecting the children and parent spans.
 	/// If a no candidate hash is not found, then `None` will be returned.
 	/// If no stage is found but the hash exists, then the stage will be set to `NoStage`.
-	fn try_resolve_missing<'a>(&self, graph: &'a Graph<'a>, span: &Span<'a>) -> Result<Option<Candidate>, Error> {
+	fn try_resolve_missing<'a>(
+		&self,
+		graph: &'a Graph<'a>,
+		span: &Span<'a>,
+		service: &str,
+	) -> Result<Option<Candidate>, Error> {
 		// first check if the span has anything
-		let mut stage = extract_stage_from_span(span)?;
-		let mut hash = extract_hash_from_span(span)?;
+		let mut stage = extract_stage_from_span(span, &self.stage_tag, &self.stage_map)?;
+		let mut hash = extract_hash_from_span(span, &self.hash_tag)?;
 		if self.recurse_children {
-			for child in graph.search(span.span_id)? {
-				if child.get_tag(HASH_IDENTIFIER).is_some() && hash.is_none() {
-					hash = extract_hash_from_span(child)?;
+			for child in graph.descendants(span.span_id)?.take(self.recursion_depth) {
+				if child.get_tag(&self.hash_tag).is_some() && hash.is_none() {
+					hash = extract_hash_from_span(child, &self.hash_tag)?;
 				}
 
-				if child.get_tag(STAGE_IDENTIFIER).is_some() && stage.is_none() {
-					stage = extract_stage_from_span(child)?;
+				if stage.is_none() {
+					stage = extract_stage_from_span(child, &self.stage_tag, &self.stage_map)?;
 				}
 
 				if stage.is_some() && hash.is_some() {
@@ -354,12 +1178,12 @@ impl Metrics {
 		}
 
 		if self.recurse_parents {
-			for parent in graph.parents(span.span_id)? {
-				if parent.get_tag(HASH_IDENTIFIER).is_some() && hash.is_none() {
-					hash = extract_hash_from_span(parent)?;
+			for parent in graph.ancestors(span.span_id)?.take(self.recursion_depth) {
+				if parent.get_tag(&self.hash_tag).is_some() && hash.is_none() {
+					hash = extract_hash_from_span(parent, &self.hash_tag)?;
 				}
-				if parent.get_tag(STAGE_IDENTIFIER).is_some() && stage.is_none() {
-					stage = extract_stage_from_span(parent)?;
+				if stage.is_none() {
+					stage = extract_stage_from_span(parent, &self.stage_tag, &self.stage_map)?;
 				}
 
 				if stage.is_some() && hash.is_some() {
@@ -368,61 +1192,276 @@ impl Metrics {
 			}
 		}
 
-		let stage = stage.unwrap_or(Stage::NoStage);
+		let stage = stage.unwrap_or(Stage::NoStage as usize);
 
 		hash.map(|h| {
 			Ok(Candidate {
 				hash: Some(h),
 				operation: span.operation_name.to_string(),
 				start_time: span.start_time,
-				duration: span.duration,
-				stage,
+				duration: span.duration_from(self.duration_source),
+				stage: Stage::from(stage),
+				stage_number: stage,
+				service: service.to_string(),
 			})
 		})
 		.transpose()
 	}
 
-	/// Clear memory of candidates
-	pub fn clear(&mut self) {
-		self.candidates.clear();
+	/// The numbers of the stages each unique candidate hash was observed at.
+	/// Stages without a name can not be placed in the pipeline, so candidates only observed at such stages are left out.
+	pub fn observed_stages(&self) -> HashMap<CandidateHash, BTreeSet<usize>> {
+		let mut stages: HashMap<CandidateHash, BTreeSet<usize>> = HashMap::new();
+		let named = self.candidates.values().flatten().filter(|c| self.stage_names.0.contains_key(&c.stage_number));
+		for candidate in named {
+			if let Some(hash) = candidate.hash {
+				stages.entry(hash).or_default().insert(candidate.stage_number);
+			}
+		}
+		stages
+	}
+
+	/// The number of the furthest stage each unique candidate hash was observed at.
+	pub fn max_stages(&self) -> HashMap<CandidateHash, usize> {
+		self.observed_stages().into_iter().filter_map(|(hash, stages)| Some((hash, *stages.last()?))).collect()
+	}
+
+	/// Number of unique candidate hashes at each stage, by the number of the stage, including stages this version of
+	/// dot-jaeger does not know about. Candidates without a hash are not counted.
+	pub fn stage_counts(&self) -> BTreeMap<usize, usize> {
+		let mut unique: BTreeMap<usize, HashSet<CandidateHash>> = BTreeMap::new();
+		for candidate in self.candidates.values().flatten() {
+			if let Some(hash) = candidate.hash {
+				unique.entry(candidate.stage_number).or_default().insert(hash);
+			}
+		}
+		unique.into_iter().map(|(stage, hashes)| (stage, hashes.len())).collect()
+	}
+
+	/// Number of unique candidate hashes seen at each named stage but not at the next named stage, by stage and by the
+	/// `label` of the service of the candidate, for every stage but the last. Stages without stuck candidates are left
+	/// out. A candidate that skips stages is counted at every stage it was seen at before a gap.
+	pub fn stuck_counts<'s>(&'s self, label: impl Fn(&'s str) -> &'s str) -> HashMap<(usize, &'s str), usize> {
+		let mut observed: HashMap<(CandidateHash, &str), BTreeSet<usize>> = HashMap::new();
+		let named = self.candidates.values().flatten().filter(|c| self.stage_names.0.contains_key(&c.stage_number));
+		for candidate in named {
+			if let Some(hash) = candidate.hash {
+				observed.entry((hash, label(&candidate.service))).or_default().insert(candidate.stage_number);
+			}
+		}
+		let mut counts = HashMap::new();
+		for ((_, service), stages) in observed.iter() {
+			for (stage, next) in self.stage_names.numbers().tuple_windows() {
+				if stages.contains(&stage) && !stages.contains(&next) {
+					*counts.entry((stage, *service)).or_default() += 1;
+				}
+			}
+		}
+		counts
+	}
+
+	/// Hashes of the candidates that reached at least the stage numbered `terminal`.
+	pub fn completed(&self, terminal: usize) -> HashSet<CandidateHash> {
+		self.max_stages().into_iter().filter(|(_, stage)| *stage >= terminal).map(|(hash, _)| hash).collect()
+	}
+
+	/// Find candidates that were observed at an earlier stage than a stage they had already reached,
+	/// ordering the observations of each candidate by their start time.
+	/// Candidates without a stage or at a stage without a name are ignored.
+	pub fn stage_regressions(&self) -> Vec<StageRegression> {
+		let mut by_hash: HashMap<CandidateHash, Vec<&Candidate>> = HashMap::new();
+		for candidate in self.candidates.values().flatten().filter(|c| self.in_pipeline(c)) {
+			if let Some(hash) = candidate.hash {
+				by_hash.entry(hash).or_default().push(candidate);
+			}
+		}
+
+		let mut regressions = Vec::new();
+		for (hash, mut candidates) in by_hash.into_iter() {
+			candidates.sort_by_key(|c| c.start_time);
+			let mut furthest = Stage::NoStage as usize;
+			for candidate in candidates {
+				if candidate.stage_number < furthest {
+					regressions.push(StageRegression { hash, from: furthest, to: candidate.stage_number });
+				}
+				furthest = std::cmp::max(furthest, candidate.stage_number);
+			}
+		}
+		regressions
+	}
+
+	/// The time each candidate took to move from one stage to the next stage it was observed at,
+	/// from the earliest start of a span of the candidate at one stage to the earliest start at the next.
+	/// Candidates without a stage or at a stage without a name are ignored, as are transitions that went back in time.
+	pub fn stage_transitions(&self) -> Vec<StageTransition> {
+		let mut by_hash: HashMap<CandidateHash, BTreeMap<usize, usize>> = HashMap::new();
+		for candidate in self.candidates.values().flatten().filter(|c| self.in_pipeline(c)) {
+			if let Some(hash) = candidate.hash {
+				let start =
+					by_hash.entry(hash).or_default().entry(candidate.stage_number).or_insert(candidate.start_time);
+				*start = std::cmp::min(*start, candidate.start_time);
+			}
+		}
+
+		let mut transitions = Vec::new();
+		for stages in by_hash.values() {
+			for ((from, start), (to, end)) in stages.iter().tuple_windows() {
+				if let Some(delta) = end.checked_sub(*start) {
+					transitions.push(StageTransition { from: *from, to: *to, delta });
+				}
+			}
+		}
+		transitions
+	}
+
+	/// Serialize every collected candidate as JSON, grouped by stage.
+	pub fn to_json(&self) -> Result<String, Error> {
+		Ok(serde_json::to_string(&self.candidates)?)
+	}
+
+	/// A collector without candidates, configured like this one.
+	pub fn empty(&self) -> Self {
+		Self {
+			candidates: HashMap::new(),
+			recurse_parents: self.recurse_parents,
+			recurse_children: self.recurse_children,
+			recursion_depth: self.recursion_depth,
+			min_stage: self.min_stage,
+			include_unknown: self.include_unknown,
+			duration_source: self.duration_source,
+			stage_map: self.stage_map.clone(),
+			hash_tag: self.hash_tag.clone(),
+			stage_tag: self.stage_tag.clone(),
+			include_operations: self.include_operations.clone(),
+			exclude_operations: self.exclude_operations.clone(),
+			stage_names: self.stage_names.clone(),
+		}
 	}
 }
 
+/// A candidate moving from the stage numbered `from` to the next stage it was observed at, `to`.
 #[derive(Debug, PartialEq)]
+pub struct StageTransition {
+	pub from: usize,
+	pub to: usize,
+	/// time between the start of the candidate at both stages, in microseconds
+	pub delta: usize,
+}
+
+/// A candidate that was observed at the stage numbered `to` after it had already reached the later stage `from`.
+#[derive(Debug, PartialEq)]
+pub struct StageRegression {
+	pub hash: CandidateHash,
+	pub from: usize,
+	pub to: usize,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
 struct Candidate {
+	#[serde(serialize_with = "serialize_hash")]
 	hash: Option<CandidateHash>,
 	operation: String,
 	start_time: usize,
 	duration: f64,
 	stage: Stage,
+	/// the number of the stage as reported, which may be a stage this version of dot-jaeger does not know about
+	stage_number: usize,
+	/// the service that reported the span of the candidate
+	service: String,
 }
 
-impl<'a> TryFrom<&'a Span<'a>> for Option<Candidate> {
-	type Error = Error;
-	fn try_from(span: &'a Span<'a>) -> Result<Option<Candidate>, Error> {
-		let hash = extract_hash_from_span(span)?;
-		let stage = extract_stage_from_span(span)?.unwrap_or(Stage::NoStage);
-		Ok(hash.map(|h| Candidate {
-			hash: Some(h),
-			stage,
-			operation: span.operation_name.to_string(),
-			start_time: span.start_time,
-			duration: span.duration,
-		}))
+/// Observe the duration of every span under the service that reported it.
+/// Services not in the `allowlist` are observed under [`OTHER_SERVICE`], to bound the number of series.
+fn observe_span_durations(
+	histogram: &HistogramVec,
+	traces: &[TraceObject<'_>],
+	allowlist: Option<&HashSet<String>>,
+	durations: &SpanDurations,
+) {
+	for trace in traces.iter() {
+		for span in trace.spans.values() {
+			let service = service_label(trace.service_name(span).unwrap_or(OTHER_SERVICE), allowlist);
+			if let Some(millis) = durations.millis(span.duration) {
+				histogram.with_label_values(&[service]).observe(millis);
+			}
+		}
+	}
+}
+
+/// Count every span under the service that reported it, labeled like in [`observe_span_durations`].
+fn count_spans(gauge: &GaugeVec, traces: &[TraceObject<'_>], allowlist: Option<&HashSet<String>>) {
+	for trace in traces.iter() {
+		for span in trace.spans.values() {
+			gauge
+				.with_label_values(&[service_label(trace.service_name(span).unwrap_or(OTHER_SERVICE), allowlist)])
+				.inc();
+		}
+	}
+}
+
+/// Count every warning of the traces and their spans under its [`warning_category`].
+fn count_warnings(counter: &IntCounterVec, traces: &[TraceObject<'_>]) {
+	for warning in traces.iter().flat_map(|t| t.warnings()) {
+		counter.with_label_values(&[warning_category(warning)]).inc();
+	}
+}
+
+/// A coarse category of a warning of Jaeger, since the warnings themselves contain IDs and durations.
+fn warning_category(warning: &str) -> &'static str {
+	let warning = warning.to_lowercase();
+	// Jaeger skips the clock skew adjustment of spans with an invalid parent, and says so in the same warning
+	if warning.contains("invalid parent") {
+		"invalid_parent"
+	} else if warning.contains("clock skew") {
+		"clock_skew"
+	} else if warning.contains("truncat") || warning.contains("too large") {
+		"truncated"
+	} else {
+		"other"
+	}
+}
+
+/// Observe the duration of every span under its operation name.
+fn observe_operation_durations(histogram: &HistogramVec, traces: &[TraceObject<'_>], durations: &SpanDurations) {
+	for span in traces.iter().flat_map(|t| t.spans.values()) {
+		if let Some(millis) = durations.millis(span.duration) {
+			histogram.with_label_values(&[span.operation_name]).observe(millis);
+		}
 	}
 }
 
-/// Extract Hash and Stage from a span
-fn extract_stage_from_span(item: &Span) -> Result<Option<Stage>, Error> {
-	let stage = item.get_tag(STAGE_IDENTIFIER);
+/// The label of `service` in metrics. Services not in the `allowlist` are labeled [`OTHER_SERVICE`].
+fn service_label<'s>(service: &'s str, allowlist: Option<&HashSet<String>>) -> &'s str {
+	if allowlist.map(|a| a.contains(service)).unwrap_or(true) {
+		service
+	} else {
+		OTHER_SERVICE
+	}
+}
+
+fn serialize_hash<S>(hash: &Option<CandidateHash>, serializer: S) -> Result<S::Ok, S::Error>
+where
+	S: Serializer,
+{
+	hash.map(|h| format!("0x{}", hex::encode(h))).serialize(serializer)
+}
+
+/// Extract the number of the stage of a span.
+/// If the span has no stage tag, the stage is inferred from the operation name of the span.
+fn extract_stage_from_span(item: &Span, key: &str, stage_map: &StageMap) -> Result<Option<usize>, Error> {
+	let stage = item.get_tag(key);
 	let stage = stage.map(|s| s.value().parse()).transpose()?;
-	Ok(stage)
+	Ok(stage.or_else(|| stage_map.infer(item.operation_name).map(|s| s as usize)))
 }
 
-fn extract_hash_from_span(span: &Span) -> Result<Option<CandidateHash>, Error> {
-	let hash_string = span.get_tag(HASH_IDENTIFIER);
+fn extract_hash_from_span(span: &Span, key: &str) -> Result<Option<CandidateHash>, Error> {
 	let mut hash = [0u8; 32];
-	hash_string.map(|h| hex::decode_to_slice(&h.value()[2..], &mut hash)).transpose()?;
+	if let Some(tag) = span.get_tag(key) {
+		let value = tag.value();
+		let hex =
+			value.strip_prefix("0x").ok_or_else(|| anyhow!("candidate hash `{}` does not start with 0x", value))?;
+		hex::decode_to_slice(hex, &mut hash)?;
+	}
 	if [0u8; 32] == hash {
 		Ok(None)
 	} else {
@@ -436,9 +1475,10 @@ fn extract_hash_from_span(span: &Span) -> Result<Option<CandidateHash>, Error> {
 /// statistical metrics for which stage of the inclusion
 /// pipeline drops a significant amount of candidates,
 /// statistically speaking.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
 #[repr(u8)]
 #[non_exhaustive]
+#[allow(clippy::enum_variant_names)]
 pub enum Stage {
 	NoStage = 0,
 	CandidateSelection = 1,
@@ -449,6 +1489,8 @@ pub enum Stage {
 	AvailabilityRecovery = 6,
 	BitfieldDistribution = 7,
 	ApprovalChecking = 8,
+	/// A stage this version of dot-jaeger does not know about.
+	Unknown = 9,
 	// Expand as needed, numbers should be ascending according to the stage
 	// through the inclusion pipeline, or according to the descriptions
 	// in [the path of a para chain block]
@@ -456,44 +1498,748 @@ pub enum Stage {
 	// see [issue](https://github.com/paritytech/polkadot/issues/2389)
 }
 
+impl Stage {
+	/// Every stage, in ascending order through the inclusion pipeline.
+	pub const ALL: [Stage; 10] = [
+		Stage::NoStage,
+		Stage::CandidateSelection,
+		Stage::CandidateBacking,
+		Stage::StatementDistribution,
+		Stage::PoVDistribution,
+		Stage::AvailabilityDistribution,
+		Stage::AvailabilityRecovery,
+		Stage::BitfieldDistribution,
+		Stage::ApprovalChecking,
+		Stage::Unknown,
+	];
+}
+
+/// Maps operation names to the stage that spans with that operation are at,
+/// for spans which are missing the stage tag.
+/// A span is at the stage of the first pattern contained in its operation name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageMap(Vec<(String, Stage)>);
+
+impl StageMap {
+	/// The stage a span with the operation name `operation` is at.
+	pub fn infer(&self, operation: &str) -> Option<Stage> {
+		self.0.iter().find(|(pattern, _)| operation.contains(pattern.as_str())).map(|(_, stage)| *stage)
+	}
+}
+
+/// Operation names of the subsystems of Polkadot.
+impl Default for StageMap {
+	fn default() -> Self {
+		Self(
+			vec![
+				("candidate-selection", Stage::CandidateSelection),
+				("candidate-backing", Stage::CandidateBacking),
+				("statement-distribution", Stage::StatementDistribution),
+				("pov-distribution", Stage::PoVDistribution),
+				("availability-distribution", Stage::AvailabilityDistribution),
+				("availability-recovery", Stage::AvailabilityRecovery),
+				("bitfield-distribution", Stage::BitfieldDistribution),
+				("approval-checking", Stage::ApprovalChecking),
+			]
+			.into_iter()
+			.map(|(pattern, stage)| (pattern.to_string(), stage))
+			.collect(),
+		)
+	}
+}
+
+/// Names of the stages that get their own metrics, by stage number.
+/// Candidates at other stages are counted as unknown.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageNames(BTreeMap<usize, String>);
+
+impl StageNames {
+	/// Read stage names from a JSON file mapping stage numbers to names. Example: `{"9": "dispute-coordinator"}`.
+	/// The given names extend the built-in names, and take precedence over them.
+	pub fn from_file(path: &str) -> Result<Self, Error> {
+		let file = std::fs::read_to_string(path).with_context(|| format!("failed to read stages from {}", path))?;
+		let names: BTreeMap<usize, String> =
+			serde_json::from_str(&file).with_context(|| format!("invalid stages in {}", path))?;
+		let mut stages = StageNames::default();
+		stages.0.extend(names);
+		Ok(stages)
+	}
+
+	/// The name of the stage numbered `stage`, if it has one.
+	pub fn name(&self, stage: usize) -> Option<&str> {
+		self.0.get(&stage).map(String::as_str)
+	}
+
+	/// Numbers of the named stages, in ascending order.
+	pub fn numbers(&self) -> impl Iterator<Item = usize> + '_ {
+		self.0.keys().copied()
+	}
+}
+
+/// The stages of the inclusion pipeline this version of dot-jaeger knows about.
+impl Default for StageNames {
+	fn default() -> Self {
+		let mut names = BTreeMap::new();
+		names.insert(Stage::NoStage as usize, "no-stage".to_string());
+		for (pattern, stage) in StageMap::default().0 {
+			names.insert(stage as usize, pattern);
+		}
+		Self(names)
+	}
+}
+
+/// Parses a comma-separated list of `pattern=stage` pairs. Example: `availability-recovery=6`.
+/// The given patterns take precedence over the default mapping.
+impl FromStr for StageMap {
+	type Err = Error;
+	fn from_str(s: &str) -> Result<Self, Error> {
+		let mut map = Vec::new();
+		for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+			let (pattern, stage) =
+				pair.split_once('=').ok_or_else(|| anyhow!("expected `pattern=stage`, found `{}`", pair))?;
+			map.push((pattern.trim().to_string(), stage.trim().parse()?));
+		}
+		map.extend(StageMap::default().0);
+		Ok(Self(map))
+	}
+}
+
 impl FromStr for Stage {
 	type Err = Error;
 	fn from_str(s: &str) -> Result<Self, Error> {
-		match s.parse()? {
-			0 => Ok(Stage::NoStage),
-			1 => Ok(Stage::CandidateSelection),
-			2 => Ok(Stage::CandidateBacking),
-			3 => Ok(Stage::StatementDistribution),
-			4 => Ok(Stage::PoVDistribution),
-			5 => Ok(Stage::AvailabilityDistribution),
-			6 => Ok(Stage::AvailabilityRecovery),
-			7 => Ok(Stage::BitfieldDistribution),
-			8 => Ok(Stage::ApprovalChecking),
-			_ => bail!(format!("stage {} does not exist", s)),
+		let stage = Stage::from(s.parse::<usize>()?);
+		if stage == Stage::Unknown {
+			log::warn!("stage {} does not exist, counting it as an unknown stage", s);
 		}
+		Ok(stage)
 	}
 }
 
-impl TryFrom<usize> for Stage {
-	type Error = Error;
-	fn try_from(num: usize) -> Result<Stage, Error> {
+impl From<usize> for Stage {
+	fn from(num: usize) -> Stage {
 		match num {
-			0 => Ok(Stage::NoStage),
-			1 => Ok(Stage::CandidateSelection),
-			2 => Ok(Stage::CandidateBacking),
-			3 => Ok(Stage::StatementDistribution),
-			4 => Ok(Stage::PoVDistribution),
-			5 => Ok(Stage::AvailabilityDistribution),
-			6 => Ok(Stage::AvailabilityRecovery),
-			7 => Ok(Stage::BitfieldDistribution),
-			8 => Ok(Stage::ApprovalChecking),
-			_ => bail!(format!("stage {} does not exist", num)),
+			0 => Stage::NoStage,
+			1 => Stage::CandidateSelection,
+			2 => Stage::CandidateBacking,
+			3 => Stage::StatementDistribution,
+			4 => Stage::PoVDistribution,
+			5 => Stage::AvailabilityDistribution,
+			6 => Stage::AvailabilityRecovery,
+			7 => Stage::BitfieldDistribution,
+			8 => Stage::ApprovalChecking,
+			_ => Stage::Unknown,
 		}
 	}
 }
 
 impl std::fmt::Display for Stage {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-		write!(f, "{}", (*self as usize))
+		match self {
+			Stage::Unknown => write!(f, "unknown"),
+			_ => write!(f, "{}", (*self as usize)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn candidate(hash: u8, stage: Stage, start_time: usize) -> Candidate {
+		Candidate {
+			hash: Some([hash; 32]),
+			operation: "testop".to_string(),
+			start_time,
+			duration: 150.0,
+			stage,
+			stage_number: stage as usize,
+			service: "polkadot-insi-testing".to_string(),
+		}
+	}
+
+	/// `data` with a tag `key` holding the candidate hash `0x{hash}` on its first span.
+	fn with_candidate_hash(data: &str, key: &str, hash: &str) -> String {
+		let tag = format!(r#""tags": [{{ "key": "{}", "type": "string", "value": "0x{}" }},"#, key, hash);
+		data.replacen(r#""tags": ["#, &tag, 1)
+	}
+
+	/// A candidate at the stage numbered `stage`, which may be a stage this version of dot-jaeger does not know about.
+	fn numbered(hash: u8, stage: usize, start_time: usize) -> Candidate {
+		Candidate { stage: Stage::from(stage), stage_number: stage, ..candidate(hash, Stage::NoStage, start_time) }
+	}
+
+	#[test]
+	fn should_analyse_named_stages() {
+		let mut names = StageNames::default();
+		names.0.insert(9, "dispute-coordinator".to_string());
+		let mut collector = CandidateCollector::new(false, false, false).stage_names(names);
+		collector.insert_candidate(numbered(1, 8, 0));
+		collector.insert_candidate(numbered(1, 9, 10));
+		collector.insert_candidate(numbered(1, 8, 20));
+		// stage 10 has no name
+		collector.insert_candidate(numbered(2, 8, 0));
+		collector.insert_candidate(numbered(2, 10, 10));
+
+		assert_eq!(collector.stage_regressions(), vec![StageRegression { hash: [1; 32], from: 9, to: 8 }]);
+		assert_eq!(collector.stage_transitions(), vec![StageTransition { from: 8, to: 9, delta: 10 }]);
+		let completed = collector.completed(9);
+		assert!(completed.contains(&[1; 32]));
+		assert!(!completed.contains(&[2; 32]));
+		assert_eq!(collector.max_stages().get(&[2; 32]), Some(&8));
+
+		// without the name, stage 9 is not part of the pipeline
+		let mut collector = CandidateCollector::new(false, false, false);
+		collector.insert_candidate(numbered(1, 8, 0));
+		collector.insert_candidate(numbered(1, 9, 10));
+		collector.insert_candidate(numbered(1, 8, 20));
+		assert!(collector.stage_regressions().is_empty());
+		assert!(collector.completed(9).is_empty());
+	}
+
+	#[test]
+	fn should_detect_stage_regression() {
+		let mut collector = CandidateCollector::new(false, false, false);
+		collector.insert_candidate(candidate(1, Stage::CandidateSelection, 0));
+		collector.insert_candidate(candidate(1, Stage::AvailabilityDistribution, 10));
+		collector.insert_candidate(candidate(1, Stage::CandidateBacking, 20));
+		// a candidate progressing monotonically
+		collector.insert_candidate(candidate(2, Stage::CandidateSelection, 0));
+		collector.insert_candidate(candidate(2, Stage::CandidateBacking, 10));
+
+		assert_eq!(
+			collector.stage_regressions(),
+			vec![StageRegression {
+				hash: [1; 32],
+				from: Stage::AvailabilityDistribution as usize,
+				to: Stage::CandidateBacking as usize
+			}]
+		);
+	}
+
+	#[test]
+	fn should_map_unknown_stages() -> Result<(), Error> {
+		assert_eq!("8".parse::<Stage>()?, Stage::ApprovalChecking);
+		assert_eq!("42".parse::<Stage>()?, Stage::Unknown);
+		assert_eq!(Stage::from(9), Stage::Unknown);
+		assert!("not-a-stage".parse::<Stage>().is_err());
+		Ok(())
+	}
+
+	#[test]
+	fn should_count_stuck_candidates() {
+		let mut collector = CandidateCollector::new(false, false, false);
+		// stuck at candidate backing
+		collector.insert_candidate(candidate(1, Stage::CandidateSelection, 0));
+		collector.insert_candidate(candidate(1, Stage::CandidateBacking, 10));
+		// stuck at candidate selection
+		collector.insert_candidate(candidate(2, Stage::CandidateSelection, 0));
+		// skipped from candidate backing to pov distribution, so stuck at both
+		collector.insert_candidate(candidate(3, Stage::CandidateBacking, 0));
+		collector.insert_candidate(candidate(3, Stage::PoVDistribution, 10));
+		collector.insert_candidate(candidate(4, Stage::NoStage, 0));
+		// seen at candidate selection by another service, which reported nothing else of it
+		collector.insert_candidate(Candidate {
+			service: "kusama".to_string(),
+			..candidate(1, Stage::CandidateSelection, 0)
+		});
+
+		let counts = collector.stuck_counts(|s| s);
+		let service = "polkadot-insi-testing";
+		assert_eq!(counts[&(Stage::NoStage as usize, service)], 1);
+		assert_eq!(counts[&(Stage::CandidateSelection as usize, service)], 1);
+		assert_eq!(counts[&(Stage::CandidateBacking as usize, service)], 2);
+		assert_eq!(counts.get(&(Stage::StatementDistribution as usize, service)), None);
+		assert_eq!(counts[&(Stage::PoVDistribution as usize, service)], 1);
+		assert_eq!(counts[&(Stage::CandidateSelection as usize, "kusama")], 1);
+		assert_eq!(counts.len(), 5);
+
+		// services are counted under their label
+		let counts = collector.stuck_counts(|_| OTHER_SERVICE);
+		assert_eq!(counts[&(Stage::CandidateSelection as usize, OTHER_SERVICE)], 1);
+	}
+
+	#[test]
+	fn should_measure_stage_transitions() {
+		let mut collector = CandidateCollector::new(false, false, false);
+		collector.insert_candidate(candidate(1, Stage::CandidateSelection, 100));
+		// the earliest span of a stage counts
+		collector.insert_candidate(candidate(1, Stage::CandidateSelection, 0));
+		collector.insert_candidate(candidate(1, Stage::CandidateBacking, 1000));
+		collector.insert_candidate(candidate(1, Stage::AvailabilityDistribution, 4000));
+		// a transition back in time
+		collector.insert_candidate(candidate(2, Stage::CandidateSelection, 500));
+		collector.insert_candidate(candidate(2, Stage::CandidateBacking, 200));
+
+		let mut transitions = collector.stage_transitions();
+		transitions.sort_by_key(|t| t.from);
+		assert_eq!(
+			transitions,
+			vec![StageTransition { from: 1, to: 2, delta: 1000 }, StageTransition { from: 2, to: 5, delta: 3000 },]
+		);
+	}
+
+	#[test]
+	fn should_serialize_candidates() -> Result<(), Error> {
+		let mut collector = CandidateCollector::new(false, false, false);
+		collector.insert_candidate(candidate(1, Stage::CandidateBacking, 20));
+
+		let json: serde_json::Value = serde_json::from_str(&collector.to_json()?)?;
+		let candidate = &json["CandidateBacking"][0];
+		assert_eq!(candidate["hash"], format!("0x{}", "01".repeat(32)));
+		assert_eq!(candidate["operation"], "testop");
+		assert_eq!(candidate["stage"], "CandidateBacking");
+		assert_eq!(candidate["start_time"], 20);
+		assert_eq!(candidate["duration"], 150.0);
+		Ok(())
+	}
+
+	#[test]
+	fn should_count_unique_candidates_per_stage() {
+		let mut collector = CandidateCollector::new(false, false, false);
+		collector.insert_candidate(candidate(1, Stage::CandidateSelection, 0));
+		collector.insert_candidate(candidate(1, Stage::CandidateSelection, 10));
+		collector.insert_candidate(candidate(2, Stage::CandidateSelection, 0));
+		collector.insert_candidate(candidate(2, Stage::CandidateBacking, 10));
+
+		collector.insert_candidate(numbered(3, 12, 0));
+
+		let counts = collector.stage_counts();
+		assert_eq!(counts.get(&(Stage::CandidateSelection as usize)), Some(&2));
+		assert_eq!(counts.get(&(Stage::CandidateBacking as usize)), Some(&1));
+		assert_eq!(counts.get(&(Stage::ApprovalChecking as usize)), None);
+		// stages this version does not know about keep their number
+		assert_eq!(counts.get(&12), Some(&1));
+	}
+
+	#[test]
+	fn should_exclude_incomplete_candidates() {
+		let mut collector = CandidateCollector::new(false, false, false);
+		collector.insert_candidate(candidate(1, Stage::CandidateSelection, 0));
+		collector.insert_candidate(candidate(1, Stage::ApprovalChecking, 10));
+		// still in flight
+		collector.insert_candidate(candidate(2, Stage::CandidateSelection, 0));
+		collector.insert_candidate(candidate(2, Stage::CandidateBacking, 10));
+
+		let completed = collector.completed(Stage::ApprovalChecking as usize);
+		assert!(completed.contains(&[1; 32]));
+		assert!(!completed.contains(&[2; 32]));
+	}
+
+	#[test]
+	fn should_infer_stage_from_operation() -> Result<(), Error> {
+		let stage_map = StageMap::default();
+		assert_eq!(stage_map.infer("availability-recovery"), Some(Stage::AvailabilityRecovery));
+		assert_eq!(stage_map.infer("unrelated"), None);
+
+		let stage_map: StageMap = "recovery=5".parse()?;
+		assert_eq!(stage_map.infer("availability-recovery"), Some(Stage::AvailabilityDistribution));
+		assert_eq!(stage_map.infer("bitfield-distribution"), Some(Stage::BitfieldDistribution));
+
+		let data = crate::tests::TEST_DATA.replace("testop", "availability-recovery");
+		let traces: TraceObject = serde_json::from_str(&data)?;
+		let mut span = traces.spans.get("parent").unwrap().clone();
+		assert_eq!(
+			extract_stage_from_span(&span, STAGE_IDENTIFIER, &StageMap::default())?,
+			Some(Stage::PoVDistribution as usize)
+		);
+		span.tags.clear();
+		assert_eq!(
+			extract_stage_from_span(&span, STAGE_IDENTIFIER, &StageMap::default())?,
+			Some(Stage::AvailabilityRecovery as usize)
+		);
+		Ok(())
+	}
+
+	#[test]
+	fn should_observe_span_durations_by_service() -> Result<(), Error> {
+		let mut data = crate::tests::TEST_DATA.to_string();
+		data = data.replacen(r#""processID": "p1""#, r#""processID": "p2""#, 1);
+		data = data.replace(
+			r#""processes": {"#,
+			r#""processes": { "p2": { "serviceName": "polkadot-other-node", "tags": [] },"#,
+		);
+		let traces: Vec<TraceObject> = vec![serde_json::from_str(&data)?];
+
+		let histogram = HistogramVec::new(HistogramOpts::new("test_span_duration", "test"), &["service"])?;
+		observe_span_durations(&histogram, &traces, None, &durations(DurationUnit::Micros, None));
+		assert_eq!(histogram.with_label_values(&["polkadot-insi-testing"]).get_sample_count(), 3);
+		assert_eq!(histogram.with_label_values(&["polkadot-other-node"]).get_sample_count(), 1);
+
+		let histogram = HistogramVec::new(HistogramOpts::new("test_span_duration", "test"), &["service"])?;
+		let allowlist = vec!["polkadot-insi-testing".to_string()].into_iter().collect();
+		observe_span_durations(&histogram, &traces, Some(&allowlist), &durations(DurationUnit::Micros, None));
+		assert_eq!(histogram.with_label_values(&["polkadot-insi-testing"]).get_sample_count(), 3);
+		assert_eq!(histogram.with_label_values(&[OTHER_SERVICE]).get_sample_count(), 1);
+
+		let gauge = GaugeVec::new(prometheus::Opts::new("test_spans_total", "test"), &["service"])?;
+		count_spans(&gauge, &traces, Some(&allowlist));
+		count_spans(&gauge, &traces, Some(&allowlist));
+		assert_eq!(gauge.with_label_values(&["polkadot-insi-testing"]).get(), 6.0);
+		assert_eq!(gauge.with_label_values(&[OTHER_SERVICE]).get(), 2.0);
+		Ok(())
+	}
+
+	#[test]
+	fn should_label_candidates_by_service() -> Result<(), Error> {
+		let data = with_candidate_hash(crate::tests::TEST_DATA, HASH_IDENTIFIER, &"01".repeat(32));
+		let traces: TraceObject = serde_json::from_str(&data)?;
+		let mut collector = CandidateCollector::new(false, false, false);
+		collector.collect(&traces)?;
+
+		let candidates = &collector.candidates[&Stage::PoVDistribution];
+		assert_eq!(candidates.len(), 1);
+		assert_eq!(candidates[0].service, "polkadot-insi-testing");
+
+		let allowlist = vec!["polkadot-insi-testing".to_string()].into_iter().collect();
+		assert_eq!(service_label("polkadot-insi-testing", Some(&allowlist)), "polkadot-insi-testing");
+		assert_eq!(service_label("polkadot-other-node", Some(&allowlist)), OTHER_SERVICE);
+		assert_eq!(service_label("polkadot-other-node", None), "polkadot-other-node");
+		Ok(())
+	}
+
+	#[test]
+	fn should_count_trace_warnings() -> Result<(), Error> {
+		let data = crate::tests::TEST_DATA
+			.replacen(
+				r#""warnings": null"#,
+				r#""warnings": ["clock skew adjustment disabled; not applying calculated delta of 35ms", "span is truncated"]"#,
+				1,
+			)
+			.replacen(
+				r#""warnings": null"#,
+				r#""warnings": ["invalid parent span IDs=child-7; skipping clock skew adjustment"]"#,
+				1,
+			);
+		let traces: Vec<TraceObject> = vec![serde_json::from_str(&data)?];
+		assert_eq!(traces[0].warnings().count(), 3);
+
+		let counter = IntCounterVec::new(prometheus::Opts::new("test_trace_warnings_total", "test"), &["category"])?;
+		count_warnings(&counter, &traces);
+		count_warnings(&counter, &traces);
+		assert_eq!(counter.with_label_values(&["clock_skew"]).get(), 2);
+		assert_eq!(counter.with_label_values(&["invalid_parent"]).get(), 2);
+		assert_eq!(counter.with_label_values(&["truncated"]).get(), 2);
+		assert_eq!(counter.with_label_values(&["other"]).get(), 0);
+		assert_eq!(warning_category("unexpected"), "other");
+		Ok(())
+	}
+
+	#[test]
+	fn should_observe_operation_durations() -> Result<(), Error> {
+		let data = crate::tests::TEST_DATA.replacen("testop", "otherop", 1);
+		let traces: Vec<TraceObject> = vec![serde_json::from_str(&data)?];
+		let histogram = HistogramVec::new(HistogramOpts::new("test_operation_duration", "test"), &["operation"])?;
+		observe_operation_durations(&histogram, &traces, &durations(DurationUnit::Micros, None));
+		assert_eq!(histogram.with_label_values(&["testop"]).get_sample_count(), 3);
+		assert_eq!(histogram.with_label_values(&["otherop"]).get_sample_count(), 1);
+		assert_eq!(histogram.with_label_values(&["otherop"]).get_sample_sum(), 0.15);
+		Ok(())
+	}
+
+	#[test]
+	fn should_name_stages_from_file() -> Result<(), Error> {
+		let path = std::env::temp_dir().join(format!("dot-jaeger-stages-{}.json", std::process::id()));
+		std::fs::write(&path, r#"{ "9": "dispute-coordinator", "2": "backing" }"#)?;
+		let stages = StageNames::from_file(path.to_str().unwrap())?;
+		std::fs::remove_file(&path)?;
+		assert_eq!(stages.0.get(&9).map(String::as_str), Some("dispute-coordinator"));
+		assert_eq!(stages.0.get(&2).map(String::as_str), Some("backing"));
+		assert_eq!(stages.0.get(&8).map(String::as_str), Some("approval-checking"));
+		assert_eq!(stages.0.len(), 10);
+		assert!(StageNames::from_file("/nonexistent/stages.json").is_err());
+
+		// candidates keep the number of a stage this version does not know about
+		let data = crate::tests::TEST_DATA.replacen(r#""value": "4""#, r#""value": "9""#, 1);
+		let data = with_candidate_hash(&data, HASH_IDENTIFIER, &"01".repeat(32));
+		let traces: TraceObject = serde_json::from_str(&data)?;
+		let mut collector = CandidateCollector::new(false, false, false);
+		collector.collect(&traces)?;
+		assert_eq!(collector.candidates[&Stage::Unknown][0].stage_number, 9);
+		Ok(())
+	}
+
+	#[test]
+	fn should_reject_hash_without_prefix() -> Result<(), Error> {
+		for value in ["", "0", "é", "01"] {
+			let data = crate::tests::TEST_DATA.replacen(r#""key": "otel.library.name","#, r#""key": "hash","#, 1);
+			let data = data.replacen(r#""value": "mick-jaeger""#, &format!(r#""value": "{}""#, value), 1);
+			let trace: TraceObject = serde_json::from_str(&data)?;
+			let error = extract_hash_from_span(&trace.spans["parent"], "hash").unwrap_err();
+			assert_eq!(error.to_string(), format!("candidate hash `{}` does not start with 0x", value));
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn should_use_configured_tag_keys() -> Result<(), Error> {
+		let data = with_candidate_hash(
+			&crate::tests::TEST_DATA.replace(STAGE_IDENTIFIER, "para-stage"),
+			"para-hash",
+			&"01".repeat(32),
+		);
+		let traces: TraceObject = serde_json::from_str(&data)?;
+
+		let mut collector = CandidateCollector::new(false, false, false);
+		collector.collect(&traces)?;
+		assert!(collector.candidates.is_empty());
+
+		let mut collector = CandidateCollector::new(false, false, false).tag_keys("para-hash", "para-stage");
+		collector.collect(&traces)?;
+		assert_eq!(collector.candidates[&Stage::PoVDistribution][0].hash, Some([1; 32]));
+		Ok(())
+	}
+
+	#[test]
+	fn should_configure_empty_collector_alike() -> Result<(), Error> {
+		let data = with_candidate_hash(
+			&crate::tests::TEST_DATA.replace(STAGE_IDENTIFIER, "para-stage"),
+			"para-hash",
+			&"01".repeat(32),
+		);
+		let traces: TraceObject = serde_json::from_str(&data)?;
+		let mut collector = CandidateCollector::new(false, false, false).tag_keys("para-hash", "para-stage");
+		collector.collect(&traces)?;
+
+		let mut empty = collector.empty();
+		assert!(empty.candidates.is_empty());
+		empty.collect(&traces)?;
+		assert_eq!(empty.candidates[&Stage::PoVDistribution], collector.candidates[&Stage::PoVDistribution]);
+		Ok(())
+	}
+
+	#[test]
+	fn should_limit_recursion_depth() -> Result<(), Error> {
+		// only the root span has a hash, its descendants are one to three levels deep
+		let data = with_candidate_hash(crate::tests::TEST_DATA, HASH_IDENTIFIER, &"01".repeat(32));
+		let traces: TraceObject = serde_json::from_str(&data)?;
+
+		let mut collector = CandidateCollector::new(true, false, false).recursion_depth(1);
+		collector.collect(&traces)?;
+		assert_eq!(collector.candidates[&Stage::PoVDistribution].len(), 2);
+
+		let mut collector = CandidateCollector::new(true, false, false);
+		collector.collect(&traces)?;
+		assert_eq!(collector.candidates[&Stage::PoVDistribution].len(), 4);
+		Ok(())
+	}
+
+	#[test]
+	fn should_skip_candidates_before_min_stage() -> Result<(), Error> {
+		let data = with_candidate_hash(crate::tests::TEST_DATA, HASH_IDENTIFIER, &"01".repeat(32));
+		let traces: TraceObject = serde_json::from_str(&data)?;
+
+		let mut collector = CandidateCollector::new(true, false, false).min_stage(Stage::PoVDistribution as usize);
+		collector.collect(&traces)?;
+		assert_eq!(collector.candidates[&Stage::PoVDistribution].len(), 4);
+
+		let mut collector = CandidateCollector::new(true, false, false).min_stage(Stage::PoVDistribution as usize + 1);
+		collector.collect(&traces)?;
+		assert!(collector.candidates.is_empty());
+		Ok(())
+	}
+
+	#[test]
+	fn should_only_collect_included_operations() -> Result<(), Error> {
+		let data = with_candidate_hash(crate::tests::TEST_DATA, HASH_IDENTIFIER, &"01".repeat(32))
+			.replacen("testop", "otherop", 1);
+		let traces: TraceObject = serde_json::from_str(&data)?;
+		let collected = |include: &[&str], exclude: &[&str]| -> Result<usize, Error> {
+			let to_vec = |ops: &[&str]| ops.iter().map(|op| op.to_string()).collect::<Vec<_>>();
+			let mut collector =
+				CandidateCollector::new(true, false, false).operations(&to_vec(include), &to_vec(exclude));
+			collector.collect(&traces)?;
+			Ok(collector.candidates.values().map(Vec::len).sum())
+		};
+
+		assert_eq!(collected(&[], &[])?, 4);
+		assert_eq!(collected(&["otherop"], &[])?, 1);
+		assert_eq!(collected(&[], &["otherop"])?, 3);
+		assert_eq!(collected(&["otherop"], &["otherop"])?, 0);
+		Ok(())
+	}
+
+	#[test]
+	fn should_count_lifetime_candidates_once() {
+		let mut lifetime = LifetimeCandidates::new(2);
+		assert_eq!(lifetime.insert(vec![[1; 32], [1; 32]]), 1);
+		assert_eq!(lifetime.insert(vec![[1; 32]]), 1);
+		assert_eq!(lifetime.insert(vec![[2; 32], [3; 32]]), 3);
+		// the first hash was evicted to stay within the cap
+		assert_eq!(lifetime.seen.len(), 2);
+		assert_eq!(lifetime.insert(vec![[3; 32]]), 3);
+	}
+
+	#[test]
+	fn should_not_observe_candidates_within_dedup_window() {
+		let mut window = DedupWindow::new(Duration::from_secs(10));
+		let start = Instant::now();
+		let candidate = ([1; 32], 2, "polkadot".to_string());
+		assert!(!window.insert(candidate.clone(), start));
+		assert!(!window.insert(([1; 32], 3, "polkadot".to_string()), start));
+
+		// seeing the candidate again slides the window
+		window.expire(start + Duration::from_secs(8));
+		assert!(window.insert(candidate.clone(), start + Duration::from_secs(8)));
+		window.expire(start + Duration::from_secs(16));
+		assert!(window.insert(candidate.clone(), start + Duration::from_secs(16)));
+
+		window.expire(start + Duration::from_secs(30));
+		assert!(window.seen.is_empty());
+		assert!(!window.insert(candidate, start + Duration::from_secs(30)));
+	}
+
+	#[test]
+	fn should_convert_source_duration_unit() -> Result<(), Error> {
+		let traces: Vec<TraceObject> = vec![serde_json::from_str(crate::tests::TEST_DATA)?];
+		let histogram = HistogramVec::new(HistogramOpts::new("test_span_duration", "test"), &["service"])?;
+		observe_span_durations(&histogram, &traces, None, &durations(DurationUnit::Millis, None));
+		// every span lasts 150 in the source unit
+		assert_eq!(histogram.with_label_values(&["polkadot-insi-testing"]).get_sample_sum(), 600.0);
+
+		let histogram = HistogramVec::new(HistogramOpts::new("test_span_duration", "test"), &["service"])?;
+		observe_span_durations(&histogram, &traces, None, &durations(DurationUnit::Micros, None));
+		assert_eq!(histogram.with_label_values(&["polkadot-insi-testing"]).get_sample_sum(), 0.6);
+		Ok(())
+	}
+
+	fn durations(unit: DurationUnit, max: Option<f64>) -> SpanDurations {
+		SpanDurations { unit, max, dropped: IntCounter::new("test_implausible_durations_total", "test").unwrap() }
+	}
+
+	#[test]
+	fn should_drop_implausible_durations() -> Result<(), Error> {
+		let data = crate::tests::TEST_DATA.replacen(r#""duration": 150"#, r#""duration": -20"#, 1).replacen(
+			r#""duration": 150"#,
+			r#""duration": 90000000"#,
+			1,
+		);
+		let traces: Vec<TraceObject> = vec![serde_json::from_str(&data)?];
+		let histogram = HistogramVec::new(HistogramOpts::new("test_span_duration", "test"), &["service"])?;
+		let durations = durations(DurationUnit::Micros, Some(60_000.0));
+		observe_span_durations(&histogram, &traces, None, &durations);
+		assert_eq!(histogram.with_label_values(&["polkadot-insi-testing"]).get_sample_count(), 2);
+		assert_eq!(durations.dropped.get(), 2);
+
+		// without a maximum only negative durations are dropped
+		let durations = self::durations(DurationUnit::Micros, None);
+		assert_eq!(durations.millis(90_000_000.0), Some(90_000.0));
+		assert_eq!(durations.millis(-1.0), None);
+		assert_eq!(durations.dropped.get(), 1);
+		Ok(())
+	}
+
+	#[test]
+	fn should_cap_daemon_limit() -> Result<(), Error> {
+		use argh::FromArgs;
+
+		for (args, limit) in [
+			(&["daemon"][..], DEFAULT_DAEMON_LIMIT),
+			(&["daemon", "--limit", "50"], 50),
+			(&["daemon", "--limit", "1000000"], MAX_DAEMON_LIMIT),
+		] {
+			let daemon = Daemon::from_args(&["dot-jaeger"], &args[1..]).map_err(|e| anyhow!("{}", e.output))?;
+			assert_eq!(daemon_limit(&daemon), limit, "{:?}", args);
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn should_query_daemon_limit_each_update() -> Result<(), Error> {
+		use argh::FromArgs;
+
+		let traces = vec![crate::tests::TEST_DATA; 30].join(", ");
+		let body = format!(r#"{{"data": [{}], "total": 30, "limit": 0, "offset": 0, "errors": null}}"#, traces);
+		let (url, server) = crate::tests::serve(vec![crate::tests::ok(&body)]);
+
+		let mut app = App::from_args(&["dot-jaeger"], &["--url", &url, "--service", "polkadot", "daemon"])
+			.map_err(|e| anyhow!("{}", e.output))?;
+		app.apply_daemon_limits();
+		assert_eq!(total_limit(&app), DEFAULT_DAEMON_LIMIT);
+		let api = app.api()?.retries(0);
+		let pages = api.traces_paginated(&app, total_limit(&app))?;
+		assert_eq!(api.to_json_pages::<TraceObject>(&pages)?.len(), 30);
+		let requests = server.join().map_err(|_| anyhow!("server thread panicked"))?;
+		assert!(requests[0].contains(&format!("limit={}", DEFAULT_DAEMON_LIMIT)), "{}", requests[0]);
+
+		let mut app = App::from_args(&["dot-jaeger"], &["--total-limit", "3000", "daemon", "--limit", "100"])
+			.map_err(|e| anyhow!("{}", e.output))?;
+		app.apply_daemon_limits();
+		assert_eq!((app.limit, total_limit(&app)), (Some(100), 3000));
+		Ok(())
+	}
+
+	#[test]
+	fn should_register_internal_metrics() -> Result<(), Error> {
+		let internal = InternalMetrics::new(HashMap::new())?;
+		internal.deserialize_latency.observe(0.5);
+		let names: Vec<String> = internal.registry.gather().iter().map(|f| f.get_name().to_string()).collect();
+		for name in ["api_latency_seconds", "deserialize_seconds", "update_seconds", "cycle_seconds", "cycles_total"] {
+			assert!(names.contains(&format!("{}_{}", INTERNAL_NAMESPACE, name)), "{:?}", names);
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn should_label_metrics_with_network() -> Result<(), Error> {
+		let mut labels = HashMap::new();
+		labels.insert("network".to_string(), "kusama".to_string());
+		let gauge = Gauge::with_opts(opts("test_labeled".to_string(), "labeled gauge", &labels))?;
+		gauge.set(1.);
+		let families = prometheus::core::Collector::collect(&gauge);
+		let label = &families[0].get_metric()[0].get_label()[0];
+		assert_eq!((label.get_name(), label.get_value()), ("network", "kusama"));
+
+		let internal = InternalMetrics::new(labels)?;
+		internal.cycles.inc();
+		let families = internal.registry.gather();
+		assert!(families
+			.iter()
+			.flat_map(|f| f.get_metric())
+			.all(|m| m.get_label().iter().any(|l| l.get_name() == "network")));
+		Ok(())
+	}
+
+	#[test]
+	fn should_push_metrics_to_gateway() -> Result<(), Error> {
+		let (url, server) = crate::tests::serve(vec![crate::tests::ok("")]);
+		let gateway = PushGateway::new(&format!("{}/", url), Duration::from_secs(5));
+
+		let gauge = Gauge::new("test_pushed", "test")?;
+		gauge.set(3.0);
+		let registry = Registry::new();
+		registry.register(Box::new(gauge))?;
+		gateway.push(&registry.gather())?;
+
+		let requests = server.join().map_err(|_| anyhow!("gateway thread panicked"))?;
+		assert!(requests[0].starts_with("PUT /metrics/job/dot-jaeger HTTP/1.1"), "{}", requests[0]);
+		assert!(requests[0].contains("test_pushed 3"), "{}", requests[0]);
+		Ok(())
+	}
+
+	#[test]
+	fn should_poll_at_configured_frequency() -> Result<(), Error> {
+		use argh::FromArgs;
+		let daemon = Daemon::from_args(&["daemon"], &["--frequency", "5000"]).map_err(|e| anyhow!(e.output))?;
+		assert_eq!(poll_interval(&daemon), Duration::from_millis(5000));
+
+		let daemon = Daemon::from_args(&["daemon"], &[]).map_err(|e| anyhow!(e.output))?;
+		assert_eq!(poll_interval(&daemon), Duration::from_millis(DEFAULT_FREQUENCY));
+		Ok(())
+	}
+
+	#[test]
+	fn should_bind_configured_address() -> Result<(), Error> {
+		use argh::FromArgs;
+		let daemon = Daemon::from_args(&["daemon"], &[]).map_err(|e| anyhow!(e.output))?;
+		assert_eq!(listen_addr(&daemon.bind, daemon.port)?, "0.0.0.0:9186".parse()?);
+
+		let daemon = Daemon::from_args(&["daemon"], &["--bind", "127.0.0.1"]).map_err(|e| anyhow!(e.output))?;
+		assert_eq!(listen_addr(&daemon.bind, daemon.port)?, "127.0.0.1:9186".parse()?);
+		assert_eq!(listen_addr("::1", 9186)?, "[::1]:9186".parse()?);
+
+		let error = listen_addr("localhost:80", 9186).unwrap_err();
+		assert_eq!(error.to_string(), "invalid bind address `localhost:80`");
+		assert!(listen_addr("127.0.0.1", 70000).is_err());
+		Ok(())
 	}
 }
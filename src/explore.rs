@@ -0,0 +1,243 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of dot-jaeger.
+
+// dot-jaeger is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// dot-jaeger is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with dot-jaeger.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Interactive exploration of a dump of traces loaded into memory.
+
+use crate::{
+	daemon::{CandidateCollector, StageNames, HASH_IDENTIFIER, STAGE_IDENTIFIER},
+	funnel::Funnel,
+	graph::Graph,
+	primitives::{Span, TraceObject},
+};
+use anyhow::{anyhow, bail, Error};
+use std::fmt::Write;
+
+pub const HELP: &str = "Commands:
+  find <hash>         spans of the candidate with the hash
+  tree <span_id>      the span and its descendants
+  parents <span_id>   the ancestors of the span through all of its parents, nearest first
+  stages              how many candidates reached at least each stage
+  tags <span_id>      the tags of the span
+  logs <span_id> [start end]
+                      the logs of the span, optionally only those within start and end (microseconds)
+  help                this message
+  quit                leave";
+
+/// Answers commands against traces that were loaded once.
+pub struct Explorer<'a> {
+	traces: &'a [TraceObject<'a>],
+}
+
+impl<'a> Explorer<'a> {
+	pub fn new(traces: &'a [TraceObject<'a>]) -> Self {
+		Self { traces }
+	}
+
+	/// Evaluate a single command, returning what should be printed.
+	pub fn eval(&self, line: &str) -> Result<String, Error> {
+		let mut words = line.split_whitespace();
+		let command = words.next().unwrap_or_default();
+		let arg = words.next();
+		if let ("logs", Some(id)) = (command, arg) {
+			return self.logs(id, words.next(), words.next());
+		}
+		match (command, arg) {
+			("find", Some(hash)) => self.find(hash),
+			("tree", Some(id)) => self.tree(id),
+			("parents", Some(id)) => self.parents(id),
+			("stages", None) => self.stages(),
+			("tags", Some(id)) => self.tags(id),
+			("help", None) | ("", None) => Ok(HELP.to_string()),
+			_ => bail!("unknown command `{}`. Type `help` for a list of commands", line.trim()),
+		}
+	}
+
+	fn find(&self, hash: &str) -> Result<String, Error> {
+		let hash = hash.trim_start_matches("0x").to_lowercase();
+		let mut out = String::new();
+		for trace in self.traces.iter() {
+			let mut spans: Vec<&Span> = trace
+				.spans
+				.values()
+				.filter(|s| {
+					s.get_tag(HASH_IDENTIFIER).map(|t| t.value().trim_start_matches("0x").to_lowercase() == hash)
+						== Some(true)
+				})
+				.collect();
+			spans.sort_unstable_by_key(|s| (s.start_time, s.span_id));
+			for span in spans {
+				let stage = span.get_tag(STAGE_IDENTIFIER).map(|t| t.value()).unwrap_or_else(|| "-".to_string());
+				writeln!(out, "{} {} {} stage {}", trace.trace_id, span.span_id, span.operation_name, stage)?;
+			}
+		}
+		if out.is_empty() {
+			bail!("no spans of candidate 0x{}", hash);
+		}
+		Ok(out.trim_end().to_string())
+	}
+
+	fn tree(&self, id: &str) -> Result<String, Error> {
+		let trace = self.trace_of(id)?;
+		let graph = Graph::new(trace)?;
+		let depths = graph.depths_below(id)?;
+		let mut out = String::new();
+		for span in std::iter::once(&trace.spans[id]).chain(graph.descendants(id)?) {
+			writeln!(out, "{}{} {}", "  ".repeat(depths[span.span_id]), span.span_id, span.operation_name)?;
+		}
+		Ok(out.trim_end().to_string())
+	}
+
+	fn parents(&self, id: &str) -> Result<String, Error> {
+		let trace = self.trace_of(id)?;
+		let graph = Graph::new(trace)?;
+		let parents: Vec<String> =
+			graph.ancestors(id)?.map(|s| format!("{} {}", s.span_id, s.operation_name)).collect();
+		Ok(parents.join("\n"))
+	}
+
+	fn stages(&self) -> Result<String, Error> {
+		let mut collector = CandidateCollector::new(false, false, false);
+		for trace in self.traces.iter() {
+			collector.collect(trace)?;
+		}
+		Ok(Funnel::new(collector.observed_stages().into_values(), &StageNames::default()).to_string())
+	}
+
+	fn tags(&self, id: &str) -> Result<String, Error> {
+		let span = &self.trace_of(id)?.spans[id];
+		let tags: Vec<String> = span.tags.iter().map(|t| format!("{} = {}", t.key(), t.value())).collect();
+		Ok(tags.join("\n"))
+	}
+
+	fn logs(&self, id: &str, start: Option<&str>, end: Option<&str>) -> Result<String, Error> {
+		let span = &self.trace_of(id)?.spans[id];
+		let start = start.map(str::parse).transpose()?.unwrap_or(span.start_time);
+		let end = end.map(str::parse).transpose()?.unwrap_or(usize::MAX);
+		let mut out = String::new();
+		for log in span.logs_between(start, end) {
+			let fields: Vec<String> = log.fields.iter().map(|f| format!("{}={}", f.key(), f.value())).collect();
+			writeln!(out, "{} {}", log.timestamp, fields.join(" "))?;
+		}
+		Ok(out.trim_end().to_string())
+	}
+
+	/// The trace that contains the span with `id`.
+	fn trace_of(&self, id: &str) -> Result<&'a TraceObject<'a>, Error> {
+		self.traces.iter().find(|t| t.spans.contains_key(id)).ok_or_else(|| anyhow!("span {} not found", id))
+	}
+}
+
+/// Prompt for commands until the user quits.
+#[cfg(feature = "explore")]
+pub fn repl(explorer: &Explorer<'_>) -> Result<(), Error> {
+	use rustyline::error::ReadlineError;
+
+	let mut editor = rustyline::Editor::<()>::new();
+	println!("{}", HELP);
+	loop {
+		let line = match editor.readline("> ") {
+			Ok(line) => line,
+			Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => break,
+			Err(e) => return Err(e.into()),
+		};
+		editor.add_history_entry(line.as_str());
+		match line.trim() {
+			"quit" | "exit" => break,
+			_ => match explorer.eval(&line) {
+				Ok(out) => println!("{}", out),
+				Err(e) => println!("error: {}", e),
+			},
+		}
+	}
+	Ok(())
+}
+
+/// Read commands from standard input until the user quits.
+/// Build with the `explore` feature for line editing and history.
+#[cfg(not(feature = "explore"))]
+pub fn repl(explorer: &Explorer<'_>) -> Result<(), Error> {
+	use std::io::{BufRead, Write as _};
+
+	println!("{}", HELP);
+	let stdin = std::io::stdin();
+	let mut lines = stdin.lock().lines();
+	loop {
+		print!("> ");
+		std::io::stdout().flush()?;
+		let line = match lines.next() {
+			Some(line) => line?,
+			None => break,
+		};
+		match line.trim() {
+			"quit" | "exit" => break,
+			_ => match explorer.eval(&line) {
+				Ok(out) => println!("{}", out),
+				Err(e) => println!("error: {}", e),
+			},
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::tests::*;
+
+	#[test]
+	fn should_answer_commands() -> Result<(), Error> {
+		let traces: Vec<TraceObject> = vec![serde_json::from_str(TEST_DATA)?];
+		let explorer = Explorer::new(&traces);
+
+		assert_eq!(explorer.eval("tree child-0")?, "child-0 testop\n  child-1 testop\n    child-2 testop");
+		assert_eq!(explorer.eval("parents child-1")?, "child-0 testop\nparent testop");
+		assert!(explorer.eval("tags parent")?.contains("candidate-stage = 4"));
+		assert!(explorer.eval("stages")?.contains("Total unique candidates: 0"));
+		assert_eq!(explorer.eval("logs parent")?, "");
+		assert!(explorer.eval("logs parent soon").is_err());
+		assert!(explorer.eval("find 0xdeadbeef").is_err());
+		assert!(explorer.eval("tree missing").is_err());
+		assert!(explorer.eval("frobnicate").is_err());
+
+		// child-2 is also CHILD_OF the root, through the first of its references
+		let data = TEST_DATA.replacen(
+			r#"{
+						"refType": "CHILD_OF",
+						"traceID": "6ga7nenJ21rhDy6Fwzjwz7KZQ5Jrii9",
+						"spanID": "child-1"
+					}"#,
+			r#"{
+						"refType": "CHILD_OF",
+						"traceID": "6ga7nenJ21rhDy6Fwzjwz7KZQ5Jrii9",
+						"spanID": "parent"
+					},
+					{
+						"refType": "CHILD_OF",
+						"traceID": "6ga7nenJ21rhDy6Fwzjwz7KZQ5Jrii9",
+						"spanID": "child-1"
+					}"#,
+			1,
+		);
+		let traces: Vec<TraceObject> = vec![serde_json::from_str(&data)?];
+		let explorer = Explorer::new(&traces);
+		assert_eq!(explorer.eval("tree child-1")?, "child-1 testop\n  child-2 testop");
+		assert_eq!(
+			explorer.eval("tree parent")?,
+			"parent testop\n  child-0 testop\n    child-1 testop\n  child-2 testop"
+		);
+		Ok(())
+	}
+}
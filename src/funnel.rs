@@ -0,0 +1,205 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of dot-jaeger.
+
+// dot-jaeger is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// dot-jaeger is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with dot-jaeger.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Stage-progression (funnel) report of candidates observed across many traces.
+
+use crate::daemon::StageNames;
+use anyhow::{anyhow, Error};
+use std::{
+	collections::{BTreeSet, HashMap},
+	fmt,
+	str::FromStr,
+};
+
+/// Weight of a drop-off at a stage that was not given an explicit weight.
+pub const DEFAULT_WEIGHT: f64 = 1.0;
+
+/// Severity of candidates dropping out of the pipeline at each stage.
+/// Stages without an explicit weight are weighted with [`DEFAULT_WEIGHT`].
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct StageWeights(HashMap<usize, f64>);
+
+impl StageWeights {
+	pub fn get(&self, stage: usize) -> f64 {
+		self.0.get(&stage).copied().unwrap_or(DEFAULT_WEIGHT)
+	}
+}
+
+/// Parses a comma-separated list of `stage=weight` pairs. Example: `2=1.5,5=3`
+impl FromStr for StageWeights {
+	type Err = Error;
+	fn from_str(s: &str) -> Result<Self, Error> {
+		let mut weights = HashMap::new();
+		for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+			let (stage, weight) =
+				pair.split_once('=').ok_or_else(|| anyhow!("expected `stage=weight`, found `{}`", pair))?;
+			weights.insert(stage.trim().parse()?, weight.trim().parse()?);
+		}
+		Ok(Self(weights))
+	}
+}
+
+/// How many candidates reached at least each stage of the inclusion pipeline, and how many dropped out after it.
+#[derive(Debug, PartialEq)]
+pub struct Funnel {
+	/// Total number of unique candidates.
+	total: usize,
+	/// Number of candidates that reached at least the stage, by stage number in ascending order.
+	reached: Vec<(usize, usize)>,
+	/// Number of candidates seen at the stage but not at the next one, by stage number in ascending order.
+	dropped: Vec<(usize, usize)>,
+	weights: StageWeights,
+	names: StageNames,
+}
+
+impl Funnel {
+	/// Build a funnel of the stages named in `names` from the numbers of the stages each unique candidate was
+	/// observed at.
+	pub fn new(observed: impl IntoIterator<Item = BTreeSet<usize>>, names: &StageNames) -> Self {
+		let observed: Vec<BTreeSet<usize>> = observed.into_iter().collect();
+		let reached = names
+			.numbers()
+			.map(|stage| (stage, observed.iter().filter(|s| s.last().is_some_and(|last| *last >= stage)).count()))
+			.collect();
+		let next_stages = names.numbers().skip(1).map(Some).chain(std::iter::once(None));
+		let dropped = names
+			.numbers()
+			.zip(next_stages)
+			.map(|(stage, next)| match next {
+				Some(next) => (stage, observed.iter().filter(|s| s.contains(&stage) && !s.contains(&next)).count()),
+				None => (stage, 0),
+			})
+			.collect();
+		Self { total: observed.len(), reached, dropped, weights: StageWeights::default(), names: names.clone() }
+	}
+
+	/// Weigh the drop-offs at each stage.
+	pub fn weights(mut self, weights: StageWeights) -> Self {
+		self.weights = weights;
+		self
+	}
+
+	/// Number of candidates seen at the stage but not at the next stage, in ascending stage order.
+	/// A candidate that skips stages drops out at every stage it was seen at before a gap.
+	/// Candidates that reached the last stage of the pipeline did not drop out.
+	pub fn dropped(&self) -> &[(usize, usize)] {
+		&self.dropped
+	}
+
+	/// Drop-offs at each stage multiplied by the weight of the stage, in ascending stage order.
+	pub fn scores(&self) -> Vec<(usize, f64)> {
+		self.dropped.iter().map(|(stage, dropped)| (*stage, *dropped as f64 * self.weights.get(*stage))).collect()
+	}
+
+	fn percentage(&self, count: usize) -> f64 {
+		if self.total == 0 {
+			0.0
+		} else {
+			count as f64 / self.total as f64 * 100.0
+		}
+	}
+}
+
+impl fmt::Display for Funnel {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(
+			f,
+			"{:<6}{:<26}{:>12}{:>12}{:>10}{:>10}",
+			"Stage", "Name", "Candidates", "Percentage", "Dropped", "Score"
+		)?;
+		for (((stage, count), (_, dropped)), (_, score)) in
+			self.reached.iter().zip(self.dropped().iter()).zip(self.scores().iter())
+		{
+			writeln!(
+				f,
+				"{:<6}{:<26}{:>12}{:>11.2}%{:>10}{:>10.2}",
+				stage.to_string(),
+				self.names.name(*stage).unwrap_or_default(),
+				count,
+				self.percentage(*count),
+				dropped,
+				score
+			)?;
+		}
+		write!(f, "Total unique candidates: {}", self.total)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		daemon::{CandidateCollector, Stage},
+		primitives::TraceObject,
+		tests::{span_json, trace_json},
+	};
+
+	fn span(id: &str, hash: char, stage: usize) -> String {
+		let hash = format!("0x{}", hash.to_string().repeat(64));
+		span_json("funnel", id, &[], &[("candidate-hash", &hash), ("candidate-stage", &stage.to_string())])
+	}
+
+	#[test]
+	fn should_build_funnel() -> Result<(), Error> {
+		let spans = [
+			span("a-1", 'a', 1),
+			span("a-2", 'a', 2),
+			span("a-3", 'a', 3),
+			span("b-1", 'b', 1),
+			span("c-1", 'c', 1),
+			span("c-2", 'c', 2),
+		];
+		let json = trace_json("funnel", &spans);
+		let trace: TraceObject = serde_json::from_str(&json)?;
+		let mut collector = CandidateCollector::new(false, false, false);
+		collector.collect(&trace)?;
+
+		let funnel = Funnel::new(collector.observed_stages().into_values(), &StageNames::default());
+		assert_eq!(funnel.total, 3);
+		assert_eq!(funnel.reached[Stage::CandidateSelection as usize], (1, 3));
+		assert_eq!(funnel.reached[Stage::CandidateBacking as usize], (2, 2));
+		assert_eq!(funnel.reached[Stage::StatementDistribution as usize], (3, 1));
+		assert_eq!(funnel.reached[Stage::PoVDistribution as usize], (4, 0));
+		assert!(funnel.to_string().contains("candidate-backing"));
+		Ok(())
+	}
+
+	#[test]
+	fn should_drop_candidates_at_skipped_stages() {
+		// seen at candidate backing and pov distribution only
+		let observed = [BTreeSet::from([2, 4]), BTreeSet::from([1, 2, 3, 4])];
+		let funnel = Funnel::new(observed.iter().cloned(), &StageNames::default());
+		assert_eq!(funnel.reached[Stage::StatementDistribution as usize], (3, 2));
+		let dropped = funnel.dropped();
+		assert_eq!(dropped[Stage::CandidateSelection as usize], (1, 0));
+		assert_eq!(dropped[Stage::CandidateBacking as usize], (2, 1));
+		assert_eq!(dropped[Stage::StatementDistribution as usize], (3, 0));
+		assert_eq!(dropped[Stage::PoVDistribution as usize], (4, 2));
+	}
+
+	#[test]
+	fn should_weigh_drop_offs() -> Result<(), Error> {
+		let observed = [BTreeSet::from([1]), BTreeSet::from([1]), BTreeSet::from([1, 2]), BTreeSet::from([8])];
+		let funnel = Funnel::new(observed.iter().cloned(), &StageNames::default()).weights("1=0.5, 2=3".parse()?);
+
+		let scores = funnel.scores();
+		assert_eq!(scores[0], (0, 0.0));
+		assert_eq!(scores[1], (1, 1.0));
+		assert_eq!(scores[2], (2, 3.0));
+		assert_eq!(scores[8], (8, 0.0));
+		Ok(())
+	}
+}
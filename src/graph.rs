@@ -15,17 +15,35 @@
 // along with dot-jaeger.  If not, see <http://www.gnu.org/licenses/>.
 
 use crate::primitives::{Span, TraceObject};
-use anyhow::{Context, Error};
-use daggy::{Dag, NodeIndex, Walker};
-use petgraph::visit::Dfs;
-use std::collections::HashMap;
+use anyhow::{anyhow, Context, Error};
+use daggy::{Dag, EdgeIndex, NodeIndex, Walker};
+use std::{
+	collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+	fmt,
+};
 
-const EDGE_WEIGHT: u32 = 1;
-type DirectedGraph<'a> = Dag<Span<'a>, u32, u32>;
+type DirectedGraph<'a> = Dag<Span<'a>, Relation, u32>;
+
+/// How the span an edge points to relates to the span the edge starts from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Relation {
+	/// The span is a child of the other span.
+	ChildOf,
+	/// The span was caused by the other span, but is not part of it.
+	FollowsFrom,
+}
+
+impl fmt::Display for Relation {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Relation::ChildOf => write!(f, "CHILD_OF"),
+			Relation::FollowsFrom => write!(f, "FOLLOWS_FROM"),
+		}
+	}
+}
 
 #[derive(Debug)]
 pub struct Graph<'a> {
-	trace: &'a TraceObject<'a>,
 	graph: DirectedGraph<'a>,
 	/// Dictionary of the nodes present in the graph
 	index_lookup: HashMap<&'a str, NodeIndex<u32>>,
@@ -34,6 +52,16 @@ pub struct Graph<'a> {
 impl<'a> Graph<'a> {
 	/// Instantiate a new graph object for span traversal.
 	pub fn new(trace: &'a TraceObject<'a>) -> Result<Self, Error> {
+		Self::build(trace, false)
+	}
+
+	/// Instantiate a new graph object that also links spans to the spans they follow from.
+	/// Edges are told apart by their [`Relation`].
+	pub fn with_follows_from(trace: &'a TraceObject<'a>) -> Result<Self, Error> {
+		Self::build(trace, true)
+	}
+
+	fn build(trace: &'a TraceObject<'a>, follows_from: bool) -> Result<Self, Error> {
 		let mut graph = Dag::new();
 		let mut index_lookup = HashMap::new();
 
@@ -42,31 +70,346 @@ impl<'a> Graph<'a> {
 			index_lookup.insert(span.span_id, index);
 		}
 
-		for id in trace.spans.values().map(|s| s.span_id) {
-			if let Some(parent) = trace.get_parent(id) {
-				let parent_node = index_lookup.get(&parent.span_id).unwrap();
-				let index = index_lookup.get(id).unwrap();
-				graph.add_edge(*parent_node, *index, EDGE_WEIGHT)?;
+		// Jaeger may return a partial trace. Spans whose parent is missing become roots.
+		for span in trace.spans.values() {
+			let index = index_lookup[span.span_id];
+			for parent in span.parent_references().filter(|r| r.trace_id() == trace.trace_id).map(|r| r.span_id()) {
+				let parent_node = match index_lookup.get(parent) {
+					Some(node) => *node,
+					None => {
+						log::debug!(
+							"Span {} is CHILD_OF span {}, which is missing from the trace",
+							span.span_id,
+							parent
+						);
+						continue;
+					}
+				};
+				graph.add_edge(parent_node, index, Relation::ChildOf).map_err(|_| {
+					anyhow!("span {} is CHILD_OF span {}, which makes the trace a cycle", span.span_id, parent)
+				})?;
 			}
 		}
 
-		Ok(Self { trace, graph, index_lookup })
+		if follows_from {
+			for span in trace.spans.values() {
+				if let Some((cause_id, cause)) = span.follows_from().and_then(|id| Some((id, index_lookup.get(id)?))) {
+					graph.add_edge(*cause, index_lookup[span.span_id], Relation::FollowsFrom).map_err(|_| {
+						anyhow!("span {} FOLLOWS_FROM span {}, which makes the trace a cycle", span.span_id, cause_id)
+					})?;
+				}
+			}
+		}
+
+		Ok(Self { graph, index_lookup })
 	}
 
-	/// Do a depth-first search for a span that meets the requirements of the predicate `fun`.
-	pub fn search(&'a self, id: &'a str) -> Result<impl Iterator<Item = &'a Span<'a>>, Error> {
-		let span_node = self.index_lookup.get(id).context(format!("Span {} not found in index", id))?;
+	/// Every span below a span, depth-first. Children are visited in order of their span ID.
+	/// Spans that follow from a span are not its descendants. Each descendant is returned once,
+	/// even if it descends from the span through several parents.
+	pub fn descendants(&'a self, id: &str) -> Result<impl Iterator<Item = &'a Span<'a>>, Error> {
+		let node = self.index_lookup.get(id).context(format!("Span {} not found in index", id))?;
+		let mut visited = HashSet::new();
+		let mut descendants = Vec::new();
+		let mut stack: Vec<_> = self.child_of(self.graph.children(*node).iter(&self.graph)).into_iter().rev().collect();
+		while let Some(n) = stack.pop() {
+			if visited.insert(n) {
+				descendants.push(n);
+				stack.extend(self.child_of(self.graph.children(n).iter(&self.graph)).into_iter().rev());
+			}
+		}
+		Ok(descendants.into_iter().map(move |n| &self.graph[n]))
+	}
 
-		let depth_first = Dfs::new(&self.graph, *span_node);
-		Ok(depth_first.iter(&self.graph).map(move |n| &self.graph.raw_nodes()[n.index()].weight))
+	/// Every span above a span across all of its parents, nearest first.
+	/// Spans the span follows from are not its ancestors. Each ancestor is returned once,
+	/// even if it is an ancestor through several parents.
+	pub fn ancestors(&'a self, id: &str) -> Result<impl Iterator<Item = &'a Span<'a>>, Error> {
+		let node = self.index_lookup.get(id).context(format!("Span {} not found in index", id))?;
+		let mut visited = HashSet::new();
+		let mut ancestors = Vec::new();
+		let mut queue = VecDeque::from(vec![*node]);
+		while let Some(n) = queue.pop_front() {
+			for parent in self.child_of(self.graph.parents(n).iter(&self.graph)) {
+				if visited.insert(parent) {
+					ancestors.push(parent);
+					queue.push_back(parent);
+				}
+			}
+		}
+		Ok(ancestors.into_iter().map(move |n| &self.graph[n]))
 	}
 
-	/// Recursively walk through the parents of a span.
+	/// How many levels below a span each of its descendants is, by span ID, found breadth-first from the span.
+	/// The span itself is at depth 0. A span below it through several parents is at the depth of the shortest path.
+	pub fn depths_below(&self, id: &str) -> Result<HashMap<&'a str, usize>, Error> {
+		let node = self.index_lookup.get(id).context(format!("Span {} not found in index", id))?;
+		let mut depths = HashMap::from([(*node, 0)]);
+		let mut queue = VecDeque::from(vec![*node]);
+		while let Some(n) = queue.pop_front() {
+			let depth = depths[&n] + 1;
+			for child in self.child_of(self.graph.children(n).iter(&self.graph)) {
+				if let Entry::Vacant(entry) = depths.entry(child) {
+					entry.insert(depth);
+					queue.push_back(child);
+				}
+			}
+		}
+		Ok(depths.into_iter().map(|(n, depth)| (self.graph[n].span_id, depth)).collect())
+	}
+
+	/// The IDs of the spans more than `max_depth` levels below the nearest root, found breadth-first from the roots.
+	/// Roots, the spans without a parent in the trace, are at depth 0. Spans that follow from a span are not below it.
+	pub fn prune_below_depth(&self, max_depth: usize) -> HashSet<&'a str> {
+		let mut depths = HashMap::new();
+		let mut queue = VecDeque::new();
+		for node in self.index_lookup.values() {
+			if self.child_of(self.graph.parents(*node).iter(&self.graph)).is_empty() {
+				depths.insert(*node, 0);
+				queue.push_back(*node);
+			}
+		}
+		while let Some(n) = queue.pop_front() {
+			let depth = depths[&n] + 1;
+			for child in self.child_of(self.graph.children(n).iter(&self.graph)) {
+				if let Entry::Vacant(entry) = depths.entry(child) {
+					entry.insert(depth);
+					queue.push_back(child);
+				}
+			}
+		}
+		self.index_lookup
+			.iter()
+			.filter(|(_, n)| depths.get(n).copied().unwrap_or(0) > max_depth)
+			.map(|(id, _)| *id)
+			.collect()
+	}
+
+	/// The chain of spans from `root` down to the leaf with the greatest cumulative duration, ordered root to leaf.
+	/// Of branches with the same duration, the branch of the child with the smallest span ID is taken.
+	pub fn critical_path(&'a self, root: &str) -> Result<Vec<&'a Span<'a>>, Error> {
+		let root = *self.index_lookup.get(root).context(format!("Span {} not found in index", root))?;
+		let mut durations = HashMap::new();
+		let mut path = vec![root];
+		loop {
+			let node = path[path.len() - 1];
+			let children = self.child_of(self.graph.children(node).iter(&self.graph));
+			let mut heaviest = None;
+			for child in children {
+				let duration = self.path_duration(child, &mut durations);
+				if heaviest.map(|(_, d)| duration > d).unwrap_or(true) {
+					heaviest = Some((child, duration));
+				}
+			}
+			match heaviest {
+				Some((child, _)) => path.push(child),
+				None => break,
+			}
+		}
+		Ok(path.into_iter().map(|n| &self.graph[n]).collect())
+	}
+
+	/// Folded stacks of the spans, one line per span, for `inferno` or `flamegraph.pl`.
+	/// Each line is the operation names from the root down to the span, separated by `;`, followed by the self-time
+	/// of the span in microseconds. Of a span with several parents, the stack goes through the first parent.
+	/// Spans without self-time are left out, and lines are ordered by stack.
+	pub fn to_folded(&'a self) -> Result<String, Error> {
+		let mut lines = Vec::new();
+		for span in self.sorted_spans() {
+			let self_time = self.self_time(span.span_id)?.round() as u64;
+			if self_time == 0 {
+				continue;
+			}
+			let mut stack: Vec<String> = self.parents(span.span_id)?.map(|s| folded_frame(s.operation_name)).collect();
+			stack.reverse();
+			stack.push(folded_frame(span.operation_name));
+			lines.push(format!("{} {}", stack.join(";"), self_time));
+		}
+		lines.sort();
+		Ok(lines.join("\n"))
+	}
+
+	/// The duration of a span not spent in its direct children, in the unit of the span duration.
+	/// Overlapping children may last longer than the span together, in which case the self-time is zero.
+	pub fn self_time(&self, id: &str) -> Result<f64, Error> {
+		let node = *self.index_lookup.get(id).context(format!("Span {} not found in index", id))?;
+		let children = self.child_of(self.graph.children(node).iter(&self.graph));
+		let children: f64 = children.into_iter().map(|c| self.graph[c].duration).sum();
+		Ok((self.graph[node].duration - children).max(0f64))
+	}
+
+	/// The greatest cumulative duration of a path from `node` down to a leaf, remembered in `durations`.
+	fn path_duration(&self, node: NodeIndex<u32>, durations: &mut HashMap<NodeIndex<u32>, f64>) -> f64 {
+		if let Some(duration) = durations.get(&node) {
+			return *duration;
+		}
+		let children = self.child_of(self.graph.children(node).iter(&self.graph));
+		let longest = children.into_iter().map(|c| self.path_duration(c, durations)).fold(0f64, f64::max);
+		let duration = self.graph[node].duration + longest;
+		durations.insert(node, duration);
+		duration
+	}
+
+	/// The nodes of `edges` that are CHILD_OF edges, ordered by span ID.
+	fn child_of(&self, edges: impl Iterator<Item = (EdgeIndex<u32>, NodeIndex<u32>)>) -> Vec<NodeIndex<u32>> {
+		let mut nodes: Vec<_> = edges.filter(|(e, _)| self.graph[*e] == Relation::ChildOf).map(|(_, n)| n).collect();
+		nodes.sort_unstable_by_key(|n| self.graph[*n].span_id);
+		nodes
+	}
+
+	/// Recursively walk through the parents of a span. Spans the span follows from are not its parents.
+	/// Of a span with several parents, the walk goes through the first parent in the order of its references that
+	/// is part of the trace, like [`Span::parent_reference`]. See [`Graph::ancestors`] for all of them.
 	pub fn parents(&'a self, id: &'a str) -> Result<impl Iterator<Item = &'a Span<'a>>, Error> {
 		let id = self.index_lookup.get(id).context(format!("Parent span {} not found in index", id))?;
-		let iter = self.graph.recursive_walk(*id, |rgraph, n| rgraph.parents(n).iter(&rgraph).nth(0));
+		let iter = self.graph.recursive_walk(*id, |rgraph, n| {
+			let parents: Vec<_> =
+				rgraph.parents(n).iter(rgraph).filter(|(e, _)| rgraph[*e] == Relation::ChildOf).collect();
+			rgraph[n]
+				.parent_span_ids()
+				.into_iter()
+				.find_map(|id| parents.iter().copied().find(|(_, p)| rgraph[*p].span_id == id))
+		});
 		Ok(iter.iter(&self.graph).map(move |(_, n)| &self.graph.raw_nodes()[n.index()].weight))
 	}
+
+	/// Spans ordered by span ID.
+	fn sorted_spans(&self) -> Vec<&Span<'a>> {
+		let mut spans: Vec<_> = self.graph.raw_nodes().iter().map(|n| &n.weight).collect();
+		spans.sort_unstable_by_key(|s| s.span_id);
+		spans
+	}
+
+	/// Edges as the span IDs they link, and their relation, ordered by span IDs.
+	fn sorted_edges(&self) -> Vec<(&'a str, &'a str, Relation)> {
+		let span_id = |n: NodeIndex<u32>| self.graph.raw_nodes()[n.index()].weight.span_id;
+		let mut edges: Vec<_> =
+			self.graph.raw_edges().iter().map(|e| (span_id(e.source()), span_id(e.target()), e.weight)).collect();
+		edges.sort_unstable_by_key(|(parent, child, _)| (*parent, *child));
+		edges
+	}
+
+	/// Graphviz DOT of the spans, labeled with their operation name and span ID, ordered by span ID.
+	/// Edges are labeled with their relation, and edges to spans that follow from another are dashed.
+	pub fn to_dot(&self) -> String {
+		let mut dot = String::from("digraph {\n");
+		for span in self.sorted_spans() {
+			let label = format!("{}\\n{}", escape_dot(span.operation_name), escape_dot(span.span_id));
+			dot.push_str(&format!("    \"{}\" [label=\"{}\"];\n", escape_dot(span.span_id), label));
+		}
+		for (parent, child, relation) in self.sorted_edges() {
+			let style = if relation == Relation::FollowsFrom { ", style=dashed" } else { "" };
+			dot.push_str(&format!(
+				"    \"{}\" -> \"{}\" [label=\"{}\"{}];\n",
+				escape_dot(parent),
+				escape_dot(child),
+				relation,
+				style
+			));
+		}
+		dot.push('}');
+		dot
+	}
+
+	/// Mermaid `graph TD` of the spans, labeled with their operation name and duration, ordered by span ID.
+	/// Edges to spans that follow from another are dotted.
+	pub fn to_mermaid(&self) -> String {
+		let mut mermaid = String::from("graph TD\n");
+		for span in self.sorted_spans() {
+			// Jaeger stores durations in microseconds. We divide by 1000 to get milliseconds.
+			let label = format!("{} ({}ms)", span.operation_name, span.duration / 1000f64).replace('"', "#quot;");
+			mermaid.push_str(&format!("    {}[\"{}\"]\n", mermaid_id(span.span_id), label));
+		}
+		for (parent, child, relation) in self.sorted_edges() {
+			let arrow = if relation == Relation::FollowsFrom { "-.->" } else { "-->" };
+			mermaid.push_str(&format!("    {} {} {}\n", mermaid_id(parent), arrow, mermaid_id(child)));
+		}
+		mermaid.trim_end().to_string()
+	}
+}
+
+/// A Mermaid node ID for a span ID. Characters Mermaid does not allow in IDs are replaced.
+fn mermaid_id(span_id: &str) -> String {
+	span_id.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' }).collect()
+}
+
+/// A frame of a folded stack for an operation name. Semicolons separate frames, so they are replaced.
+fn folded_frame(operation: &str) -> String {
+	operation.replace(';', "_")
+}
+
+/// Escape a string to be used inside of a quoted DOT ID.
+fn escape_dot(s: &str) -> String {
+	s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Structural anomalies of a trace.
+#[derive(Debug, Default, PartialEq)]
+pub struct Validation<'a> {
+	/// Spans that do not reference a parent in the same trace.
+	pub roots: Vec<&'a str>,
+	/// Spans that are their own ancestors, one entry per cycle, starting from the smallest span ID.
+	pub cycles: Vec<Vec<&'a str>>,
+	/// Spans whose parent is not part of the trace, alongside the missing parent ID.
+	pub dangling: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> Validation<'a> {
+	/// Check the parent-child structure of a trace. Unlike [`Graph::new`], this does not fail on cycles.
+	pub fn new(trace: &TraceObject<'a>) -> Self {
+		let mut ids: Vec<&'a str> = trace.spans.values().map(|s| s.span_id).collect();
+		ids.sort_unstable();
+
+		let mut validation = Self::default();
+		for id in ids.iter() {
+			match trace.parent_in_trace(&trace.spans[*id]) {
+				None => validation.roots.push(id),
+				Some(parent) if !trace.spans.contains_key(parent) => validation.dangling.push((id, parent)),
+				Some(_) => (),
+			}
+		}
+
+		// every span has at most one parent, so following parents from each span finds every cycle.
+		let mut visited = HashSet::new();
+		for id in ids.iter() {
+			let mut path = Vec::new();
+			let mut current = Some(*id);
+			while let Some(span) = current {
+				if let Some(pos) = path.iter().position(|s| *s == span) {
+					let mut cycle: Vec<&'a str> = path[pos..].to_vec();
+					let min = cycle.iter().enumerate().min_by_key(|(_, s)| **s).map(|(i, _)| i).unwrap_or(0);
+					cycle.rotate_left(min);
+					validation.cycles.push(cycle);
+					break;
+				}
+				if !visited.insert(span) {
+					break;
+				}
+				path.push(span);
+				current = trace.get_parent(span).map(|p| p.span_id);
+			}
+		}
+		validation
+	}
+
+	pub fn is_valid(&self) -> bool {
+		self.roots.len() == 1 && self.cycles.is_empty() && self.dangling.is_empty()
+	}
+}
+
+impl fmt::Display for Validation<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(f, "Roots: {} ({})", self.roots.len(), self.roots.join(", "))?;
+		writeln!(f, "Cycles: {}", self.cycles.len())?;
+		for cycle in self.cycles.iter() {
+			writeln!(f, "  {} -> {}", cycle.join(" -> "), cycle[0])?;
+		}
+		write!(f, "Dangling references: {}", self.dangling.len())?;
+		for (span, parent) in self.dangling.iter() {
+			write!(f, "\n  {} -> {} (missing)", span, parent)?;
+		}
+		Ok(())
+	}
 }
 
 #[cfg(test)]
@@ -92,12 +435,244 @@ mod tests {
 		let traces: TraceObject = serde_json::from_str(TEST_DATA)?;
 		let graph = Graph::new(&traces)?;
 
-		let mut iterator = graph.search("parent")?;
-		assert_eq!(Some("parent"), iterator.next().map(|s| s.span_id));
+		let mut iterator = graph.descendants("parent")?;
 		assert_eq!(Some("child-0"), iterator.next().map(|s| s.span_id));
 		assert_eq!(Some("child-1"), iterator.next().map(|s| s.span_id));
 		assert_eq!(Some("child-2"), iterator.next().map(|s| s.span_id));
+		assert_eq!(None, iterator.next().map(|s| s.span_id));
+
+		Ok(())
+	}
+
+	#[test]
+	fn should_prune_below_depth() -> Result<(), Error> {
+		let traces: TraceObject = serde_json::from_str(TEST_DATA)?;
+		let graph = Graph::new(&traces)?;
+
+		assert_eq!(graph.prune_below_depth(1), vec!["child-1", "child-2"].into_iter().collect());
+		assert_eq!(graph.prune_below_depth(0).len(), 3);
+		assert!(graph.prune_below_depth(3).is_empty());
+		Ok(())
+	}
+
+	#[test]
+	fn should_report_anomalies() -> Result<(), Error> {
+		let span = |id: &str, parents: &[&str]| span_json("malformed", id, parents, &[]);
+		let spans = [span("a", &[]), span("b", &["x"]), span("d", &["c"]), span("c", &["d"])];
+		let json = trace_json("malformed", &spans);
+		let trace: TraceObject = serde_json::from_str(&json)?;
+
+		let validation = Validation::new(&trace);
+		assert_eq!(validation.roots, vec!["a"]);
+		assert_eq!(validation.cycles, vec![vec!["c", "d"]]);
+		assert_eq!(validation.dangling, vec![("b", "x")]);
+		assert!(!validation.is_valid());
 
+		let err = Graph::new(&trace).unwrap_err().to_string();
+		assert!(
+			err == "span c is CHILD_OF span d, which makes the trace a cycle"
+				|| err == "span d is CHILD_OF span c, which makes the trace a cycle"
+		);
+
+		let traces: TraceObject = serde_json::from_str(TEST_DATA)?;
+		assert!(Validation::new(&traces).is_valid());
+		Ok(())
+	}
+
+	#[test]
+	fn should_make_spans_with_missing_parent_roots() -> Result<(), Error> {
+		let data = TEST_DATA.replacen(
+			r#""spanID": "child-1"
+					}"#,
+			r#""spanID": "evicted"
+					}"#,
+			1,
+		);
+		let traces: TraceObject = serde_json::from_str(&data)?;
+		assert!(!traces.spans.contains_key("evicted"));
+
+		let graph = Graph::new(&traces)?;
+		assert_eq!(graph.sorted_edges().len(), 2);
+		assert_eq!(graph.parents("child-2")?.count(), 0);
+		assert!(!graph.prune_below_depth(0).contains("child-2"));
+		assert_eq!(graph.descendants("child-2")?.count(), 0);
+		Ok(())
+	}
+
+	#[test]
+	fn should_export_dot() -> Result<(), Error> {
+		let traces: TraceObject = serde_json::from_str(TEST_DATA)?;
+		let dot = Graph::new(&traces)?.to_dot();
+		assert!(dot.starts_with("digraph {\n    \"child-0\" [label=\"testop\\nchild-0\"];"));
+		assert!(dot.contains("    \"parent\" -> \"child-0\" [label=\"CHILD_OF\"];"));
+		assert!(dot.ends_with('}'));
+		assert_eq!(dot.matches("->").count(), 3);
+		assert_eq!(escape_dot(r#"a"b"#), r#"a\"b"#);
+		Ok(())
+	}
+
+	#[test]
+	fn should_export_mermaid() -> Result<(), Error> {
+		let traces: TraceObject = serde_json::from_str(TEST_DATA)?;
+		let mermaid = Graph::new(&traces)?.to_mermaid();
+		assert!(mermaid.starts_with("graph TD\n    child_0[\"testop (0.15ms)\"]"));
+		assert!(mermaid.contains("\n    parent --> child_0"));
+		assert_eq!(mermaid.matches("-->").count(), 3);
+		Ok(())
+	}
+
+	#[test]
+	fn should_link_follows_from() -> Result<(), Error> {
+		let data = TEST_DATA.replacen(
+			r#""references": [],"#,
+			r#""references": [{ "refType": "FOLLOWS_FROM", "traceID": "6ga7nenJ21rhDy6Fwzjwz7KZQ5Jrii9", "spanID": "child-2" }],"#,
+			1,
+		);
+		// make `child-2` a root that `parent` follows from
+		let data = data.replacen(
+			r#""spanID": "child-1"
+					}"#,
+			r#""spanID": "elsewhere"
+					}"#,
+			1,
+		);
+		let traces: TraceObject = serde_json::from_str(&data)?;
+		assert_eq!(traces.spans["parent"].follows_from(), Some("child-2"));
+
+		let graph = Graph::new(&traces)?;
+		assert_eq!(graph.sorted_edges().len(), 2);
+
+		let graph = Graph::with_follows_from(&traces)?;
+		assert!(graph.sorted_edges().contains(&("child-2", "parent", Relation::FollowsFrom)));
+		// following from a span does not make it a parent
+		assert_eq!(graph.parents("parent")?.count(), 0);
+		assert!(graph.to_dot().contains(r#""child-2" -> "parent" [label="FOLLOWS_FROM", style=dashed];"#));
+		assert!(graph.to_mermaid().contains("child_2 -.-> parent"));
+		Ok(())
+	}
+
+	#[test]
+	fn should_link_every_parent() -> Result<(), Error> {
+		let data = TEST_DATA.replacen(
+			r#""spanID": "child-1"
+					}"#,
+			r#""spanID": "child-1"
+					},
+					{
+						"refType": "CHILD_OF",
+						"traceID": "6ga7nenJ21rhDy6Fwzjwz7KZQ5Jrii9",
+						"spanID": "parent"
+					}"#,
+			1,
+		);
+		let traces: TraceObject = serde_json::from_str(&data)?;
+		let graph = Graph::new(&traces)?;
+		let edges = graph.sorted_edges();
+		assert_eq!(edges.len(), 4);
+		assert!(edges.contains(&("child-1", "child-2", Relation::ChildOf)));
+		assert!(edges.contains(&("parent", "child-2", Relation::ChildOf)));
+		// the walk goes through the first parent `child-2` references
+		let parents: Vec<&str> = graph.parents("child-2")?.map(|s| s.span_id).collect();
+		assert_eq!(parents, vec!["child-1", "child-0", "parent"]);
+
+		let reversed = data.replacen(
+			r#""spanID": "child-1"
+					},
+					{
+						"refType": "CHILD_OF",
+						"traceID": "6ga7nenJ21rhDy6Fwzjwz7KZQ5Jrii9",
+						"spanID": "parent"
+					}"#,
+			r#""spanID": "parent"
+					},
+					{
+						"refType": "CHILD_OF",
+						"traceID": "6ga7nenJ21rhDy6Fwzjwz7KZQ5Jrii9",
+						"spanID": "child-1"
+					}"#,
+			1,
+		);
+		let traces: TraceObject = serde_json::from_str(&reversed)?;
+		let graph = Graph::new(&traces)?;
+		let parents: Vec<&str> = graph.parents("child-2")?.map(|s| s.span_id).collect();
+		assert_eq!(parents, vec!["parent"]);
+		Ok(())
+	}
+
+	#[test]
+	fn should_walk_diamond() -> Result<(), Error> {
+		let span = |id: &str, parents: &[&str]| span_json("diamond", id, parents, &[]);
+		// `root` -> `left`, `right` -> `bottom`
+		let spans =
+			[span("root", &[]), span("left", &["root"]), span("right", &["root"]), span("bottom", &["left", "right"])];
+		let json = trace_json("diamond", &spans);
+		let trace: TraceObject = serde_json::from_str(&json)?;
+		let graph = Graph::new(&trace)?;
+
+		let ancestors: Vec<&str> = graph.ancestors("bottom")?.map(|s| s.span_id).collect();
+		assert_eq!(ancestors, vec!["left", "right", "root"]);
+		let descendants: Vec<&str> = graph.descendants("root")?.map(|s| s.span_id).collect();
+		assert_eq!(descendants, vec!["left", "bottom", "right"]);
+		// only the first parent is walked through
+		assert_eq!(graph.parents("bottom")?.count(), 2);
+		Ok(())
+	}
+
+	#[test]
+	fn should_find_critical_path() -> Result<(), Error> {
+		let traces: TraceObject = serde_json::from_str(TEST_DATA)?;
+		let graph = Graph::new(&traces)?;
+		let path: Vec<&str> = graph.critical_path("parent")?.into_iter().map(|s| s.span_id).collect();
+		assert_eq!(path, vec!["parent", "child-0", "child-1", "child-2"]);
+		let path: Vec<&str> = graph.critical_path("child-2")?.into_iter().map(|s| s.span_id).collect();
+		assert_eq!(path, vec!["child-2"]);
+
+		// `child-2` becomes a second child of `parent`, leaving the longer branch through `child-0`
+		let data = TEST_DATA.replace(
+			r#""spanID": "child-1"
+					}"#,
+			r#""spanID": "parent"
+					}"#,
+		);
+		let traces: TraceObject = serde_json::from_str(&data)?;
+		let graph = Graph::new(&traces)?;
+		let path: Vec<&str> = graph.critical_path("parent")?.into_iter().map(|s| s.span_id).collect();
+		assert_eq!(path, vec!["parent", "child-0", "child-1"]);
+		assert!(graph.critical_path("missing").is_err());
+		Ok(())
+	}
+
+	#[test]
+	fn should_compute_self_time() -> Result<(), Error> {
+		let traces: TraceObject = serde_json::from_str(TEST_DATA)?;
+		let graph = Graph::new(&traces)?;
+		// every span lasts 150µs
+		assert_eq!(graph.self_time("parent")?, 0.0);
+		assert_eq!(graph.self_time("child-2")?, 150.0);
+
+		let data = TEST_DATA.replacen(r#""duration": 150"#, r#""duration": 400"#, 1);
+		let traces: TraceObject = serde_json::from_str(&data)?;
+		assert_eq!(Graph::new(&traces)?.self_time("parent")?, 250.0);
+
+		// `child-2` overlaps `child-1` as a second child of `child-0`
+		let data = TEST_DATA.replace(
+			r#""spanID": "child-1"
+					}"#,
+			r#""spanID": "child-0"
+					}"#,
+		);
+		let traces: TraceObject = serde_json::from_str(&data)?;
+		assert_eq!(Graph::new(&traces)?.self_time("child-0")?, 0.0);
+		assert!(Graph::new(&traces)?.self_time("missing").is_err());
+		Ok(())
+	}
+
+	#[test]
+	fn should_export_folded_stacks() -> Result<(), Error> {
+		let data = TEST_DATA.replacen(r#""duration": 150"#, r#""duration": 400"#, 1).replacen("testop", "root;op", 1);
+		let traces: TraceObject = serde_json::from_str(&data)?;
+		let folded = Graph::new(&traces)?.to_folded()?;
+		assert_eq!(folded, "root_op 250\nroot_op;testop;testop;testop 150");
 		Ok(())
 	}
 }
@@ -16,11 +16,18 @@
 
 //! The HTTP Server that responds to Prometheus Requests
 
+use crate::daemon::CandidateCollector;
 use anyhow::{anyhow, Context as _, Error};
 use ascii::AsciiString;
-use prometheus::{Encoder as _, TextEncoder};
-use std::{net::SocketAddr, sync::Arc, time::Instant};
-use tiny_http::{Header, Request, Response, Server as TinyServer};
+use prometheus::{proto::MetricFamily, Encoder as _, Registry, TextEncoder};
+use std::{
+	cell::Cell,
+	io::{self, Read},
+	net::SocketAddr,
+	sync::{Arc, Mutex},
+	time::Instant,
+};
+use tiny_http::{Header, Request, Response, Server as TinyServer, StatusCode};
 
 pub struct Server {
 	handle: jod_thread::JoinHandle<()>,
@@ -28,13 +35,26 @@ pub struct Server {
 }
 
 impl Server {
-	pub fn start(addr: SocketAddr) -> Result<Self, Error> {
+	/// Start serving metrics at `addr`.
+	/// Parachain metrics from the default registry are served under `/metrics`,
+	/// while the daemon's own operational metrics from `internal` are served under `/debug/metrics`.
+	/// The candidates the daemon currently counts are served as JSON under `/debug/candidates`,
+	/// and the uptime of the server and the number of requests it served under `/health`.
+	/// If `buffer_metrics` is true, the entire metrics response is encoded in memory before it is sent,
+	/// otherwise metrics are encoded one family at a time as the response is written.
+	pub fn start(
+		addr: SocketAddr,
+		buffer_metrics: bool,
+		internal: Registry,
+		candidates: Arc<Mutex<CandidateCollector>>,
+	) -> Result<Self, Error> {
 		let server = Arc::new(TinyServer::http(addr).map_err(|e| anyhow!(e.to_string()))?);
 		let threaded_server = server.clone();
 		log::info!("exporting metrics to http://{}/metrics", addr);
+		log::info!("exporting internal metrics to http://{}/debug/metrics", addr);
 
 		let handle = jod_thread::spawn(move || {
-			let mut instance = ServerInstance::new(&threaded_server);
+			let mut instance = ServerInstance::new(&threaded_server, buffer_metrics, internal, candidates);
 			if let Err(e) = instance.request_handler() {
 				log::error!("{}", e);
 			}
@@ -51,20 +71,46 @@ impl Server {
 
 struct ServerInstance<'a> {
 	server: &'a TinyServer,
+	/// when the server started
+	started: Instant,
 	time: Instant,
 	requests_served: u32,
 	last_buffer_length: usize,
+	buffer_metrics: bool,
+	/// registry of the daemon's own operational metrics
+	internal: Registry,
+	candidates: Arc<Mutex<CandidateCollector>>,
 }
 
 impl<'a> ServerInstance<'a> {
-	fn new(server: &'a TinyServer) -> Self {
-		Self { server, time: Instant::now(), requests_served: 0, last_buffer_length: 0 }
+	fn new(
+		server: &'a TinyServer,
+		buffer_metrics: bool,
+		internal: Registry,
+		candidates: Arc<Mutex<CandidateCollector>>,
+	) -> Self {
+		Self {
+			server,
+			started: Instant::now(),
+			time: Instant::now(),
+			requests_served: 0,
+			last_buffer_length: 0,
+			buffer_metrics,
+			internal,
+			candidates,
+		}
 	}
 
 	fn request_handler(&mut self) -> Result<(), Error> {
 		for request in self.server.incoming_requests() {
 			match request.url() {
-				"/metrics" => self.handle_metrics(request)?,
+				"/metrics" => self.handle_metrics(request, prometheus::gather())?,
+				"/debug/metrics" => {
+					let metrics = self.internal.gather();
+					self.handle_metrics(request, metrics)?
+				}
+				"/debug/candidates" => self.handle_candidates(request)?,
+				"/health" => self.handle_health(request)?,
 				_ => self.handle_redirect(request)?,
 			};
 			self.log_stats();
@@ -82,14 +128,49 @@ impl<'a> ServerInstance<'a> {
 		}
 	}
 
-	fn handle_metrics(&mut self, request: Request) -> Result<(), Error> {
-		let encoder = TextEncoder::new();
-		let metrics = prometheus::gather();
-		let mut buffer = vec![];
-		encoder.encode(&metrics, &mut buffer)?;
-		self.last_buffer_length = buffer.len();
-		let response = Response::from_data(buffer);
-		request.respond(response).with_context(|| "Failed to respond to Prometheus request for metrics".to_string())?;
+	fn handle_metrics(&mut self, request: Request, metrics: Vec<MetricFamily>) -> Result<(), Error> {
+		if self.buffer_metrics {
+			let mut buffer = vec![];
+			TextEncoder::new().encode(&metrics, &mut buffer)?;
+			self.last_buffer_length = buffer.len();
+			let response = Response::from_data(buffer);
+			request
+				.respond(response)
+				.with_context(|| "Failed to respond to Prometheus request for metrics".to_string())?;
+		} else {
+			let written = Cell::new(0);
+			let response =
+				Response::new(StatusCode(200), Vec::new(), MetricsReader::new(metrics, &written), None, None);
+			request
+				.respond(response)
+				.with_context(|| "Failed to respond to Prometheus request for metrics".to_string())?;
+			self.last_buffer_length = written.get();
+		}
+		Ok(())
+	}
+
+	fn handle_candidates(&mut self, request: Request) -> Result<(), Error> {
+		let json = self.candidates.lock().map_err(|_| anyhow!("candidate collector lock poisoned"))?.to_json()?;
+		let response = Response::from_string(json).with_header(Header {
+			field: "Content-Type".parse().expect("Can not parse content type header. This should never fail"),
+			value: AsciiString::from_ascii("application/json")
+				.expect("Could not parse header value. This should never fail."),
+		});
+		request.respond(response).with_context(|| "Failed to respond to request for candidates".to_string())?;
+		Ok(())
+	}
+
+	fn handle_health(&mut self, request: Request) -> Result<(), Error> {
+		let json = serde_json::json!({
+			"uptime_secs": self.started.elapsed().as_secs(),
+			"requests_served": self.requests_served,
+		});
+		let response = Response::from_string(json.to_string()).with_header(Header {
+			field: "Content-Type".parse().expect("Can not parse content type header. This should never fail"),
+			value: AsciiString::from_ascii("application/json")
+				.expect("Could not parse header value. This should never fail."),
+		});
+		request.respond(response).with_context(|| "Failed to respond to health check".to_string())?;
 		Ok(())
 	}
 
@@ -105,3 +186,125 @@ impl<'a> ServerInstance<'a> {
 		Ok(())
 	}
 }
+
+/// Encodes metrics one family at a time as they are read,
+/// so the full text of the metrics response is never held in memory at once.
+struct MetricsReader<'a> {
+	encoder: TextEncoder,
+	families: std::vec::IntoIter<MetricFamily>,
+	buffer: Vec<u8>,
+	position: usize,
+	/// total number of bytes read so far
+	written: &'a Cell<usize>,
+}
+
+impl<'a> MetricsReader<'a> {
+	fn new(families: Vec<MetricFamily>, written: &'a Cell<usize>) -> Self {
+		Self { encoder: TextEncoder::new(), families: families.into_iter(), buffer: Vec::new(), position: 0, written }
+	}
+}
+
+impl<'a> Read for MetricsReader<'a> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		while self.position >= self.buffer.len() {
+			let family = match self.families.next() {
+				Some(f) => f,
+				None => return Ok(0),
+			};
+			self.buffer.clear();
+			self.position = 0;
+			self.encoder.encode(std::slice::from_ref(&family), &mut self.buffer).map_err(io::Error::other)?;
+		}
+		let len = std::cmp::min(buf.len(), self.buffer.len() - self.position);
+		buf[..len].copy_from_slice(&self.buffer[self.position..self.position + len]);
+		self.position += len;
+		self.written.set(self.written.get() + len);
+		Ok(len)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use prometheus::{Gauge, Histogram, HistogramOpts};
+	use std::collections::HashSet;
+
+	fn metric_names(addr: SocketAddr, path: &str) -> Result<HashSet<String>, Error> {
+		let body = ureq::get(&format!("http://{}{}", addr, path)).call()?.into_string()?;
+		Ok(body
+			.lines()
+			.filter(|l| !l.starts_with('#'))
+			.filter_map(|l| l.split([' ', '{']).next())
+			.map(String::from)
+			.collect())
+	}
+
+	#[test]
+	fn streamed_metrics_should_match_buffered() -> Result<(), Error> {
+		let registry = Registry::new();
+		let gauge = Gauge::new("test_gauge", "a test gauge")?;
+		let histogram = Histogram::with_opts(HistogramOpts::new("test_histogram", "a test histogram"))?;
+		registry.register(Box::new(gauge.clone()))?;
+		registry.register(Box::new(histogram.clone()))?;
+		gauge.set(42.0);
+		histogram.observe(1.5);
+
+		let mut buffered = Vec::new();
+		TextEncoder::new().encode(&registry.gather(), &mut buffered)?;
+
+		let written = Cell::new(0);
+		let mut streamed = Vec::new();
+		MetricsReader::new(registry.gather(), &written).read_to_end(&mut streamed)?;
+
+		assert_eq!(buffered, streamed);
+		assert_eq!(written.get(), buffered.len());
+		Ok(())
+	}
+
+	#[test]
+	fn internal_metrics_should_be_separate() -> Result<(), Error> {
+		let domain = Gauge::new("test_domain_gauge", "a parachain metric")?;
+		prometheus::register(Box::new(domain.clone()))?;
+		domain.set(1.0);
+		let internal = Registry::new_custom(Some("test_internal".into()), None)?;
+		let internal_gauge = Gauge::new("gauge", "an internal metric")?;
+		internal.register(Box::new(internal_gauge.clone()))?;
+		internal_gauge.set(1.0);
+
+		let collector = Arc::new(Mutex::new(CandidateCollector::new(false, false, false)));
+		let server = Server::start("127.0.0.1:0".parse()?, false, internal, collector)?;
+		let addr = server.server.server_addr();
+		let metrics = metric_names(addr, "/metrics")?;
+		let debug_metrics = metric_names(addr, "/debug/metrics")?;
+		server.stop();
+
+		assert!(metrics.contains("test_domain_gauge"));
+		assert!(debug_metrics.contains("test_internal_gauge"));
+		assert!(metrics.is_disjoint(&debug_metrics));
+		Ok(())
+	}
+
+	#[test]
+	fn should_report_health() -> Result<(), Error> {
+		let collector = Arc::new(Mutex::new(CandidateCollector::new(false, false, false)));
+		let server = Server::start("127.0.0.1:0".parse()?, false, Registry::new(), collector)?;
+		let addr = server.server.server_addr();
+		ureq::get(&format!("http://{}/debug/candidates", addr)).call()?;
+		let response = ureq::get(&format!("http://{}/health", addr)).call()?;
+		server.stop();
+
+		assert_eq!(response.status(), 200);
+		let health: serde_json::Value = serde_json::from_str(&response.into_string()?)?;
+		assert_eq!(health["requests_served"], 1);
+		assert!(health["uptime_secs"].is_u64());
+		Ok(())
+	}
+
+	#[test]
+	fn should_fail_to_start_on_used_port() -> Result<(), Error> {
+		let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+		let collector = Arc::new(Mutex::new(CandidateCollector::new(false, false, false)));
+		assert!(Server::start(listener.local_addr()?, false, Registry::new(), collector).is_err());
+		Ok(())
+	}
+}
@@ -0,0 +1,317 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of dot-jaeger.
+
+// dot-jaeger is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// dot-jaeger is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with dot-jaeger.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Query Jaeger for traces of Polkadot, and turn them into graphs and Prometheus metrics.
+//!
+//! [`JaegerApi`] queries the HTTP API of Jaeger, the traces it returns deserialize into
+//! [`TraceObject`](primitives::TraceObject)s, and [`Graph`](graph::Graph) builds the span tree of a trace.
+//! The `dot-jaeger` binary is a thin wrapper around [`cli::app`].
+
+pub mod api;
+#[cfg(feature = "async")]
+pub mod async_api;
+pub mod cli;
+mod daemon;
+mod explore;
+mod funnel;
+pub mod graph;
+mod http;
+mod otlp;
+mod output;
+pub mod primitives;
+mod rate_limit;
+mod tail;
+mod zipkin;
+
+pub use api::{JaegerApi, JaegerError};
+#[cfg(feature = "async")]
+pub use async_api::AsyncJaegerApi;
+
+#[cfg(test)]
+mod tests {
+	use std::{
+		io::{Read, Write},
+		net::{TcpListener, TcpStream},
+		thread::JoinHandle,
+	};
+
+	/// A `200 OK` response with `body`, after which the connection is closed.
+	pub fn ok(body: &str) -> String {
+		response("200 OK", body)
+	}
+
+	/// A response with `status` and `body`, after which the connection is closed.
+	pub fn response(status: &str, body: &str) -> String {
+		format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body)
+	}
+
+	/// Serve each of `responses` to one request, in order, on a local port.
+	/// Returns the URL of the server, and a handle that yields every request once all responses are sent.
+	pub fn serve(responses: Vec<String>) -> (String, JoinHandle<Vec<String>>) {
+		serve_bytes(responses.into_iter().map(String::into_bytes).collect())
+	}
+
+	/// Like [`serve`], for responses that are not text. Responses without `Connection: close` leave the connection
+	/// open for the next request, like a proxy opening a tunnel.
+	pub fn serve_bytes(responses: Vec<Vec<u8>>) -> (String, JoinHandle<Vec<String>>) {
+		let listener = TcpListener::bind("127.0.0.1:0").expect("can not bind mock server");
+		let url = format!("http://{}", listener.local_addr().expect("mock server has no address"));
+		let server = std::thread::spawn(move || {
+			let mut requests = Vec::new();
+			let mut stream: Option<TcpStream> = None;
+			for response in responses {
+				let mut connection = match stream.take() {
+					Some(stream) => stream,
+					None => listener.accept().expect("mock server can not accept").0,
+				};
+				requests.push(read_request(&mut connection));
+				connection.write_all(&response).expect("mock server can not respond");
+				let close = String::from_utf8_lossy(&response).to_lowercase().contains("connection: close");
+				if !close {
+					stream = Some(connection);
+				}
+			}
+			requests
+		});
+		(url, server)
+	}
+
+	/// Read the head and the body of a request, as long as its `Content-Length`.
+	fn read_request(stream: &mut TcpStream) -> String {
+		let mut request = Vec::new();
+		let mut buffer = [0; 4096];
+		loop {
+			let text = String::from_utf8_lossy(&request).to_string();
+			if let Some(head_end) = text.find("\r\n\r\n") {
+				let length = text[..head_end]
+					.lines()
+					.find_map(|l| {
+						l.to_lowercase().strip_prefix("content-length:").map(|n| n.trim().parse().unwrap_or(0))
+					})
+					.unwrap_or(0);
+				if request.len() >= head_end + 4 + length {
+					return text;
+				}
+			}
+			let read = stream.read(&mut buffer).expect("mock server can not read request");
+			if read == 0 {
+				return text;
+			}
+			request.extend_from_slice(&buffer[..read]);
+		}
+	}
+
+	/// A span of the trace `trace_id` that is CHILD_OF each of `parents`, with string tags given as `(key, value)`.
+	pub fn span_json(trace_id: &str, id: &str, parents: &[&str], tags: &[(&str, &str)]) -> String {
+		let references: Vec<String> = parents
+			.iter()
+			.map(|p| format!(r#"{{ "refType": "CHILD_OF", "traceID": "{}", "spanID": "{}" }}"#, trace_id, p))
+			.collect();
+		let tags: Vec<String> = tags
+			.iter()
+			.map(|(key, value)| format!(r#"{{ "key": "{}", "type": "string", "value": "{}" }}"#, key, value))
+			.collect();
+		format!(
+			r#"{{
+				"traceID": "{}", "spanID": "{}", "flags": null, "operationName": "testop",
+				"references": [{}], "startTime": 1616995411000000, "duration": 150,
+				"tags": [{}], "logs": [], "processID": "p1", "warnings": null
+			}}"#,
+			trace_id,
+			id,
+			references.join(","),
+			tags.join(",")
+		)
+	}
+
+	/// A trace `trace_id` of `spans`, each built by [`span_json`], without processes.
+	pub fn trace_json(trace_id: &str, spans: &[String]) -> String {
+		format!(
+			r#"{{ "traceID": "{}", "spans": [{}], "processes": {{}}, "warnings": null }}"#,
+			trace_id,
+			spans.join(",")
+		)
+	}
+
+	// test data for child-parent relationships
+	pub const TEST_DATA: &str = r#"
+	{
+	    "traceID": "6ga7nenJ21rhDy6Fwzjwz7KZQ5Jrii9",
+        "spans": [
+			{
+ 				"traceID": "6ga7nenJ21rhDy6Fwzjwz7KZQ5Jrii9",
+				"spanID": "parent",
+				"flags": null,
+				"operationName": "testop",
+				"references": [],
+				"startTime": 1616995411000000,
+				"duration": 150,
+				"tags": [
+					{
+						"key": "otel.library.name",
+						"type": "string",
+						"value": "mick-jaeger"
+					},
+					{
+						"key": "otel.library.version",
+						"type": "string",
+						"value": "0.1.4"
+					},
+					{
+						"key": "candidate-stage",
+						"type": "string",
+						"value": "4"
+					},
+					{
+						"key": "internal.span.format",
+						"type": "string",
+						"value": "proto"
+					}
+				],
+				"logs": [],
+				"processID": "p1",
+				"warnings": null
+			},
+			{
+				"traceID": "6ga7nenJ21rhDy6Fwzjwz7KZQ5Jrii9",
+				"spanID": "child-0",
+				"flags": null,
+				"operationName": "testop",
+				"references": [
+					{
+						"refType": "CHILD_OF",
+						"traceID": "6ga7nenJ21rhDy6Fwzjwz7KZQ5Jrii9",
+						"spanID": "parent"
+					}
+				],
+				"startTime": 1616995411000000,
+				"duration": 150,
+				"tags": [
+					{
+						"key": "otel.library.name",
+						"type": "string",
+						"value": "mick-jaeger"
+					},
+					{
+						"key": "otel.library.version",
+						"type": "string",
+						"value": "0.1.4"
+					},
+					{
+						"key": "candidate-stage",
+						"type": "string",
+						"value": "4"
+					},
+					{
+						"key": "internal.span.format",
+						"type": "string",
+						"value": "proto"
+					}
+				],
+				"logs": [],
+				"processID": "p1",
+				"warnings": null
+			},
+			{
+				"traceID": "6ga7nenJ21rhDy6Fwzjwz7KZQ5Jrii9",
+				"spanID": "child-1",
+				"flags": null,
+				"operationName": "testop",
+				"references": [
+					{
+						"refType": "CHILD_OF",
+						"traceID": "6ga7nenJ21rhDy6Fwzjwz7KZQ5Jrii9",
+						"spanID": "child-0"
+					}
+				],
+				"startTime": 1616995411000000,
+				"duration": 150,
+				"tags": [
+					{
+						"key": "otel.library.name",
+						"type": "string",
+						"value": "mick-jaeger"
+					},
+					{
+						"key": "otel.library.version",
+						"type": "string",
+						"value": "0.1.4"
+					},
+					{
+						"key": "candidate-stage",
+						"type": "string",
+						"value": "4"
+					},
+					{
+						"key": "internal.span.format",
+						"type": "string",
+						"value": "proto"
+					}
+				],
+				"logs": [],
+				"processID": "p1",
+				"warnings": null
+			},
+			{
+				"traceID": "6ga7nenJ21rhDy6Fwzjwz7KZQ5Jrii9",
+				"spanID": "child-2",
+				"flags": null,
+				"operationName": "testop",
+				"references": [
+					{
+						"refType": "CHILD_OF",
+						"traceID": "6ga7nenJ21rhDy6Fwzjwz7KZQ5Jrii9",
+						"spanID": "child-1"
+					}
+				],
+				"startTime": 1616995411000000,
+				"duration": 150,
+				"tags": [
+					{
+						"key": "otel.library.name",
+						"type": "string",
+						"value": "mick-jaeger"
+					},
+					{
+						"key": "otel.library.version",
+						"type": "string",
+						"value": "0.1.4"
+					},
+					{
+						"key": "candidate-stage",
+						"type": "string",
+						"value": "4"
+					},
+					{
+						"key": "internal.span.format",
+						"type": "string",
+						"value": "proto"
+					}
+				],
+				"logs": [],
+				"processID": "p1",
+				"warnings": null
+			}
+		],
+		"processes": {
+      		"p1": {
+        		"serviceName": "polkadot-insi-testing",
+        		"tags": []
+      		}
+    	}
+    }
+    "#;
+}
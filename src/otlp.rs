@@ -0,0 +1,242 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of dot-jaeger.
+
+// dot-jaeger is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// dot-jaeger is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with dot-jaeger.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Conversion of Jaeger traces to the OTLP/JSON encoding of OpenTelemetry, as accepted on `/v1/traces` by an
+//! OTLP/HTTP collector.
+
+use crate::primitives::{Log, Span, Tag, TagValue, TraceObject};
+use serde::Serialize;
+
+/// `SPAN_KIND_*` of OTLP, by the value of the `span.kind` tag of Jaeger.
+const SPAN_KINDS: &[(&str, u8)] = &[("internal", 1), ("server", 2), ("client", 3), ("producer", 4), ("consumer", 5)];
+/// `STATUS_CODE_ERROR` of OTLP.
+const STATUS_ERROR: u8 = 2;
+
+/// An `ExportTraceServiceRequest`.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportRequest<'a> {
+	pub resource_spans: Vec<ResourceSpans<'a>>,
+}
+
+/// Spans of one process of one trace.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceSpans<'a> {
+	pub resource: Resource<'a>,
+	pub scope_spans: Vec<ScopeSpans<'a>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Resource<'a> {
+	pub attributes: Vec<KeyValue<'a>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ScopeSpans<'a> {
+	pub spans: Vec<OtlpSpan<'a>>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct OtlpSpan<'a> {
+	pub trace_id: String,
+	pub span_id: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub parent_span_id: Option<String>,
+	pub name: &'a str,
+	pub kind: u8,
+	/// Nanoseconds since the Unix epoch. 64-bit integers are strings in OTLP/JSON.
+	pub start_time_unix_nano: String,
+	pub end_time_unix_nano: String,
+	pub attributes: Vec<KeyValue<'a>>,
+	pub events: Vec<Event<'a>>,
+	/// References of the span other than its parent, like `FOLLOWS_FROM` or parents in other traces.
+	pub links: Vec<Link>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub status: Option<Status>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Event<'a> {
+	pub time_unix_nano: String,
+	pub name: String,
+	pub attributes: Vec<KeyValue<'a>>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Link {
+	pub trace_id: String,
+	pub span_id: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Status {
+	pub code: u8,
+}
+
+#[derive(Serialize, Debug)]
+pub struct KeyValue<'a> {
+	pub key: &'a str,
+	pub value: AnyValue<'a>,
+}
+
+#[derive(Serialize, Debug)]
+pub enum AnyValue<'a> {
+	#[serde(rename = "stringValue")]
+	String(&'a str),
+	#[serde(rename = "boolValue")]
+	Bool(bool),
+	#[serde(rename = "intValue")]
+	Int(String),
+	#[serde(rename = "doubleValue")]
+	Double(f64),
+}
+
+impl<'a> From<&Tag<'a>> for KeyValue<'a> {
+	fn from(tag: &Tag<'a>) -> Self {
+		let value = match *tag.typed_value() {
+			TagValue::String(s) => AnyValue::String(s),
+			TagValue::Boolean(b) => AnyValue::Bool(b),
+			TagValue::Number(n) => AnyValue::Int(n.to_string()),
+			TagValue::Float(n) => AnyValue::Double(n),
+		};
+		KeyValue { key: tag.key(), value }
+	}
+}
+
+/// Convert `traces` to one export request, with a [`ResourceSpans`] per process of each trace.
+pub fn export<'a>(traces: &[TraceObject<'a>]) -> ExportRequest<'a> {
+	let mut resource_spans = Vec::new();
+	for trace in traces.iter() {
+		for (id, process) in trace.processes_sorted() {
+			let mut spans: Vec<&Span> = trace.spans.values().filter(|s| s.process_id == id).collect();
+			if spans.is_empty() {
+				continue;
+			}
+			spans.sort_unstable_by_key(|s| (s.start_time, s.span_id));
+
+			let mut attributes =
+				vec![KeyValue { key: "service.name", value: AnyValue::String(process.service_name()) }];
+			attributes.extend(process.tags().iter().map(KeyValue::from));
+			resource_spans.push(ResourceSpans {
+				resource: Resource { attributes },
+				scope_spans: vec![ScopeSpans { spans: spans.into_iter().map(to_otlp_span).collect() }],
+			});
+		}
+	}
+	ExportRequest { resource_spans }
+}
+
+fn to_otlp_span<'a>(span: &Span<'a>) -> OtlpSpan<'a> {
+	let parent = span.parent_references().find(|r| r.trace_id() == span.trace_id);
+	let links = span
+		.references
+		.iter()
+		.filter(|r| !parent.is_some_and(|p| std::ptr::eq(p, *r)))
+		.map(|r| Link { trace_id: trace_id(r.trace_id()), span_id: span_id(r.span_id()) })
+		.collect();
+	let kind = span
+		.get_tag("span.kind")
+		.and_then(|t| SPAN_KINDS.iter().find(|(name, _)| *name == t.value()))
+		.map_or(0, |(_, kind)| *kind);
+	let failed = span.get_tag("error").is_some_and(|t| t.value() == "true");
+
+	OtlpSpan {
+		trace_id: trace_id(span.trace_id),
+		span_id: span_id(span.span_id),
+		parent_span_id: parent.map(|r| span_id(r.span_id())),
+		name: span.operation_name,
+		kind,
+		start_time_unix_nano: nanos(span.start_time, 0.0),
+		end_time_unix_nano: nanos(span.start_time, span.duration),
+		attributes: span.tags.iter().map(KeyValue::from).collect(),
+		events: span.logs.iter().map(to_event).collect(),
+		links,
+		status: if failed { Some(Status { code: STATUS_ERROR }) } else { None },
+	}
+}
+
+/// A log becomes an event named after its `event` field, as the OpenTracing shim of OpenTelemetry does.
+fn to_event<'a>(log: &Log<'a>) -> Event<'a> {
+	let name = log.fields.iter().find(|f| f.key() == "event").map_or_else(|| "log".to_string(), |f| f.value());
+	Event {
+		time_unix_nano: nanos(log.timestamp, 0.0),
+		name,
+		attributes: log.fields.iter().map(KeyValue::from).collect(),
+	}
+}
+
+/// `micros` plus an `offset` in microseconds, in nanoseconds. Only the offset goes through a float, since
+/// timestamps in nanoseconds exceed the precision of `f64`.
+fn nanos(micros: usize, offset: f64) -> String {
+	(micros as u64 * 1000 + (offset * 1000.0).round() as u64).to_string()
+}
+
+/// Jaeger drops the leading zeros of hex IDs, OTLP expects 16 bytes for trace IDs and 8 bytes for span IDs.
+fn trace_id(id: &str) -> String {
+	format!("{:0>32}", id.to_lowercase())
+}
+
+fn span_id(id: &str) -> String {
+	format!("{:0>16}", id.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::tests::*;
+	use anyhow::Error;
+	use serde_json::Value;
+
+	#[test]
+	fn should_export_spans_to_otlp() -> Result<(), Error> {
+		let data = TEST_DATA.replace(
+			r#""spanID": "child-0",
+				"flags": null,
+				"operationName": "testop","#,
+			r#""spanID": "child-0",
+				"flags": null,
+				"operationName": "import-block","#,
+		);
+		let traces: Vec<TraceObject> = vec![serde_json::from_str(&data)?];
+		let request = serde_json::to_value(export(&traces))?;
+
+		let resource = &request["resourceSpans"][0];
+		assert_eq!(request["resourceSpans"].as_array().map(Vec::len), Some(1));
+		assert_eq!(resource["resource"]["attributes"][0]["key"], "service.name");
+		assert_eq!(resource["resource"]["attributes"][0]["value"]["stringValue"], "polkadot-insi-testing");
+
+		let spans = resource["scopeSpans"][0]["spans"].as_array().unwrap();
+		assert_eq!(spans.len(), 4);
+		let child = spans.iter().find(|s| s["name"] == "import-block").unwrap();
+		assert_eq!(child["traceId"], "06ga7nenj21rhdy6fwzjwz7kzq5jrii9");
+		assert_eq!(child["spanId"], "000000000child-0");
+		assert_eq!(child["parentSpanId"], "0000000000parent");
+		assert_eq!(child["startTimeUnixNano"], "1616995411000000000");
+		assert_eq!(child["endTimeUnixNano"], "1616995411000150000");
+		assert_eq!(
+			child["attributes"][0],
+			serde_json::json!({"key": "otel.library.name", "value": {"stringValue": "mick-jaeger"}})
+		);
+
+		let root = spans.iter().find(|s| s["spanId"] == "0000000000parent").unwrap();
+		assert_eq!(root.get("parentSpanId"), None::<&Value>);
+		Ok(())
+	}
+}
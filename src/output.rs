@@ -0,0 +1,250 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of dot-jaeger.
+
+// dot-jaeger is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// dot-jaeger is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with dot-jaeger.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Formats in which traces are printed.
+
+use crate::primitives::{DurationUnit, Span, TraceObject};
+use anyhow::{bail, Error};
+use std::{
+	collections::BTreeSet,
+	fmt::{self, Write},
+	str::FromStr,
+};
+
+/// Columns of the CSV output, one row per span. Spans with several parents list them separated by spaces.
+pub const CSV_HEADER: &str = "traceID,spanID,operationName,startTime,duration,processID,parentSpanID,tags";
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OutputFormat {
+	#[default]
+	Json,
+	PrettyJson,
+	/// One row per span, with the tags of the span as a JSON-encoded column.
+	Csv,
+	/// One compact JSON object per line: a trace, or a span if `spans` is set.
+	JsonLines {
+		spans: bool,
+	},
+	/// An OTLP/JSON export request of OpenTelemetry, for replaying traces into an OTLP backend.
+	Otlp,
+	/// An array of spans in the JSON of the v2 API of Zipkin.
+	Zipkin,
+}
+
+impl OutputFormat {
+	/// Serialize `traces` in this format.
+	pub fn render(&self, traces: &[TraceObject<'_>]) -> Result<String, Error> {
+		match self {
+			OutputFormat::Json => Ok(serde_json::to_string(traces)?),
+			OutputFormat::PrettyJson => Ok(serde_json::to_string_pretty(traces)?),
+			OutputFormat::Csv => to_csv(traces),
+			OutputFormat::JsonLines { spans: false } => {
+				Ok(traces.iter().map(serde_json::to_string).collect::<Result<Vec<_>, _>>()?.join("\n"))
+			}
+			OutputFormat::JsonLines { spans: true } => to_span_lines(traces),
+			OutputFormat::Otlp => Ok(serde_json::to_string(&crate::otlp::export(traces))?),
+			OutputFormat::Zipkin => Ok(serde_json::to_string(&crate::zipkin::export(traces))?),
+		}
+	}
+}
+
+impl FromStr for OutputFormat {
+	type Err = Error;
+	fn from_str(s: &str) -> Result<Self, Error> {
+		match s {
+			"json" => Ok(OutputFormat::Json),
+			"pretty-json" => Ok(OutputFormat::PrettyJson),
+			"csv" => Ok(OutputFormat::Csv),
+			"jsonl" => Ok(OutputFormat::JsonLines { spans: false }),
+			"otlp" => Ok(OutputFormat::Otlp),
+			"zipkin" => Ok(OutputFormat::Zipkin),
+			_ => bail!(
+				"unknown output format `{}`. Expected one of `json`, `pretty-json`, `csv`, `jsonl`, `otlp`, `zipkin`",
+				s
+			),
+		}
+	}
+}
+
+fn to_csv(traces: &[TraceObject<'_>]) -> Result<String, Error> {
+	let mut out = String::new();
+	writeln!(out, "{}", CSV_HEADER)?;
+	for trace in traces.iter() {
+		let mut spans: Vec<&Span> = trace.spans.values().collect();
+		spans.sort_unstable_by_key(|s| (s.start_time, s.span_id));
+		for span in spans {
+			let row = [
+				span.trace_id.to_string(),
+				span.span_id.to_string(),
+				span.operation_name.to_string(),
+				span.start_time.to_string(),
+				span.duration.to_string(),
+				span.process_id.to_string(),
+				span.parent_span_ids().join(" "),
+				serde_json::to_string(&span.tags)?,
+			];
+			writeln!(out, "{}", row.iter().map(|f| escape_csv(f)).collect::<Vec<_>>().join(","))?;
+		}
+	}
+	Ok(out.trim_end().to_string())
+}
+
+/// One line of JSON per span, in the order of the CSV output.
+fn to_span_lines(traces: &[TraceObject<'_>]) -> Result<String, Error> {
+	let mut lines = Vec::new();
+	for trace in traces.iter() {
+		let mut spans: Vec<&Span> = trace.spans.values().collect();
+		spans.sort_unstable_by_key(|s| (s.start_time, s.span_id));
+		for span in spans {
+			lines.push(serde_json::to_string(span)?);
+		}
+	}
+	Ok(lines.join("\n"))
+}
+
+/// Counts over many traces, printed by `--summary` instead of the traces themselves.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+	traces: usize,
+	spans: usize,
+	services: usize,
+	/// shortest, average and longest duration of a trace, in milliseconds
+	durations: Option<(f64, f64, f64)>,
+}
+
+impl Summary {
+	/// Summarize `traces`, whose spans report their durations in microseconds, like Jaeger does.
+	pub fn new(traces: &[TraceObject<'_>]) -> Self {
+		let services: BTreeSet<&str> =
+			traces.iter().flat_map(|t| t.processes_sorted()).map(|(_, p)| p.service_name()).collect();
+		let durations: Vec<f64> =
+			traces.iter().filter_map(TraceObject::duration).map(|d| DurationUnit::Micros.to_millis(d)).collect();
+		let durations = (!durations.is_empty()).then(|| {
+			let min = durations.iter().copied().fold(f64::INFINITY, f64::min);
+			let max = durations.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+			(min, durations.iter().sum::<f64>() / durations.len() as f64, max)
+		});
+		Self {
+			traces: traces.len(),
+			spans: traces.iter().map(|t| t.spans.len()).sum(),
+			services: services.len(),
+			durations,
+		}
+	}
+}
+
+impl fmt::Display for Summary {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(f, "Traces: {}", self.traces)?;
+		writeln!(f, "Spans: {}", self.spans)?;
+		write!(f, "Services: {}", self.services)?;
+		if let Some((min, avg, max)) = self.durations {
+			write!(f, "\nTrace duration: min {:.3}ms, avg {:.3}ms, max {:.3}ms", min, avg, max)?;
+		}
+		Ok(())
+	}
+}
+
+/// Table of the traces found by `search`: one row of the trace ID, root operation, duration and span count
+/// of each trace. Traces without their root span show `-` as operation.
+pub fn search_table(traces: &[TraceObject<'_>]) -> String {
+	let mut out = format!("{:<32} {:<40} {:>14} {:>6}", "TRACE ID", "ROOT OPERATION", "DURATION (ms)", "SPANS");
+	for trace in traces.iter() {
+		let operation = trace.root().map_or("-", |s| s.operation_name);
+		let duration = trace.duration().map(|d| DurationUnit::Micros.to_millis(d)).unwrap_or_default();
+		out.push_str(&format!(
+			"\n{:<32} {:<40} {:>14.3} {:>6}",
+			trace.trace_id,
+			operation,
+			duration,
+			trace.spans.len()
+		));
+	}
+	out
+}
+
+/// Quote a field if it contains a separator, quote or line break, doubling any quotes inside of it.
+fn escape_csv(field: &str) -> String {
+	if field.contains([',', '"', '\n', '\r']) {
+		format!("\"{}\"", field.replace('"', "\"\""))
+	} else {
+		field.to_string()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::tests::*;
+
+	#[test]
+	fn should_render_csv_row_per_span() -> Result<(), Error> {
+		let traces: Vec<TraceObject> = vec![serde_json::from_str(TEST_DATA)?];
+		let csv = OutputFormat::Csv.render(&traces)?;
+		let mut lines = csv.lines();
+		assert_eq!(lines.next(), Some(CSV_HEADER));
+
+		let rows: Vec<&str> = lines.collect();
+		assert_eq!(rows.len(), 4);
+		let child = rows.iter().find(|r| r.contains(",child-0,")).unwrap();
+		assert!(child.starts_with("6ga7nenJ21rhDy6Fwzjwz7KZQ5Jrii9,child-0,testop,1616995411000000,150,p1,parent,"));
+		assert!(child.contains(r#""[{""key"":""otel.library.name"""#));
+		Ok(())
+	}
+
+	#[test]
+	fn should_render_json_lines() -> Result<(), Error> {
+		let traces: Vec<TraceObject> = vec![serde_json::from_str(TEST_DATA)?, serde_json::from_str(TEST_DATA)?];
+		let lines = OutputFormat::JsonLines { spans: false }.render(&traces)?;
+		assert_eq!(lines.lines().count(), 2);
+		for line in lines.lines() {
+			let trace: serde_json::Value = serde_json::from_str(line)?;
+			assert_eq!(trace["spans"].as_object().map(|s| s.len()), Some(4));
+		}
+
+		let lines = OutputFormat::JsonLines { spans: true }.render(&traces)?;
+		assert_eq!(lines.lines().count(), 8);
+		for line in lines.lines() {
+			let span: serde_json::Value = serde_json::from_str(line)?;
+			assert_eq!(span["traceID"], "6ga7nenJ21rhDy6Fwzjwz7KZQ5Jrii9");
+		}
+		assert_eq!("jsonl".parse::<OutputFormat>()?, OutputFormat::JsonLines { spans: false });
+		Ok(())
+	}
+
+	#[test]
+	fn should_summarize_traces() -> Result<(), Error> {
+		let slow = TEST_DATA.replace(r#""duration": 150"#, r#""duration": 450"#);
+		let traces: Vec<TraceObject> = vec![serde_json::from_str(TEST_DATA)?, serde_json::from_str(&slow)?];
+		let summary = Summary::new(&traces);
+		assert_eq!(summary, Summary { traces: 2, spans: 8, services: 1, durations: Some((0.15, 0.3, 0.45)) });
+		assert!(summary.to_string().ends_with("Trace duration: min 0.150ms, avg 0.300ms, max 0.450ms"));
+
+		assert_eq!(Summary::new(&[]).to_string(), "Traces: 0\nSpans: 0\nServices: 0");
+		Ok(())
+	}
+
+	#[test]
+	fn should_print_search_table() -> Result<(), Error> {
+		let traces: Vec<TraceObject> = vec![serde_json::from_str(TEST_DATA)?];
+		let table = search_table(&traces);
+		let rows: Vec<Vec<&str>> = table.lines().map(|l| l.split_whitespace().collect()).collect();
+		assert_eq!(rows[0], vec!["TRACE", "ID", "ROOT", "OPERATION", "DURATION", "(ms)", "SPANS"]);
+		assert_eq!(rows[1], vec!["6ga7nenJ21rhDy6Fwzjwz7KZQ5Jrii9", "testop", "0.150", "4"]);
+		assert_eq!(rows.len(), 2);
+		Ok(())
+	}
+}
@@ -14,8 +14,23 @@
 // You should have received a copy of the GNU General Public License
 // along with dot-jaeger.  If not, see <http://www.gnu.org/licenses/>.
 
-use serde::{de::Deserializer, Deserialize, Serialize};
-use std::collections::HashMap;
+use crate::api::JaegerError;
+use anyhow::{bail, Error};
+use regex::Regex;
+use serde::{de::Deserializer, Deserialize, Serialize, Serializer};
+use serde_json::Value;
+use std::{
+	cmp::Ordering,
+	collections::{BTreeSet, HashMap},
+	fmt,
+	str::FromStr,
+};
+
+/// Value that replaces the value of a redacted tag.
+pub const REDACTED: &str = "<redacted>";
+
+/// Bit of the Jaeger span flags signifying that the span was sampled.
+pub const SAMPLED_FLAG: usize = 1;
 
 /// RPC Primitives
 #[derive(Serialize, Deserialize, Debug)]
@@ -28,18 +43,49 @@ pub struct RpcResponse<T> {
 }
 
 impl<T> RpcResponse<T> {
-	pub fn consume(self) -> Vec<T> {
-		self.data
+	/// Number of results Jaeger has, which may be more than it returned.
+	pub fn total(&self) -> usize {
+		self.total
+	}
+
+	/// Most results Jaeger returns at once, or 0 if it does not say.
+	pub fn limit(&self) -> usize {
+		self.limit
+	}
+
+	/// Number of results Jaeger skipped before the first one it returned.
+	pub fn offset(&self) -> usize {
+		self.offset
+	}
+
+	/// Whether Jaeger returned as many results as its limit allows, but has more.
+	pub fn is_truncated(&self) -> bool {
+		self.limit > 0 && self.data.len() >= self.limit && self.total > self.data.len()
+	}
+
+	/// The data of the response, or the errors Jaeger responded with, even if it also responded with data.
+	pub fn try_consume(self) -> Result<Vec<T>, JaegerError> {
+		check_errors(self.errors)?;
+		Ok(self.data)
+	}
+}
+
+/// Fail with the `errors` of a response, unless there are none.
+pub(crate) fn check_errors(errors: Option<Value>) -> Result<(), JaegerError> {
+	match errors {
+		None | Some(Value::Null) => Ok(()),
+		Some(Value::Array(errors)) if errors.is_empty() => Ok(()),
+		Some(errors) => Err(JaegerError::Errors(errors)),
 	}
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TraceObject<'a> {
 	#[serde(rename = "traceID")]
-	trace_id: &'a str,
+	pub trace_id: &'a str,
 	#[serde(deserialize_with = "deserialize_vec_as_hashmap")]
 	pub spans: HashMap<&'a str, Span<'a>>,
-	#[serde(borrow)]
+	#[serde(borrow, serialize_with = "serialize_hashmap_sorted")]
 	processes: HashMap<&'a str, Process<'a>>,
 	warnings: Option<Vec<&'a str>>,
 }
@@ -55,16 +101,192 @@ where
 	Ok(map)
 }
 
+/// Paths of the fields of a trace returned by Jaeger that are lost when it is deserialized into a [`TraceObject`]
+/// and serialized again, like `spans[].tags[].unit`. Reveals fields of the Jaeger in use that dot-jaeger does not model.
+pub fn dropped_fields(raw: &Value) -> Result<BTreeSet<String>, Error> {
+	let trace = TraceObject::deserialize(raw)?;
+	let typed = serde_json::to_value(&trace)?;
+	let mut dropped = BTreeSet::new();
+	missing_keys(raw, &typed, "", &mut dropped);
+	Ok(dropped)
+}
+
+/// Collect the paths of the keys of `raw` that are missing from `typed` into `missing`.
+fn missing_keys(raw: &Value, typed: &Value, path: &str, missing: &mut BTreeSet<String>) {
+	match (raw, typed) {
+		(Value::Object(raw), Value::Object(typed)) => {
+			for (key, value) in raw.iter() {
+				let path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+				match typed.get(key) {
+					Some(typed) => missing_keys(value, typed, &path, missing),
+					None => {
+						missing.insert(path);
+					}
+				}
+			}
+		}
+		(Value::Array(raw), Value::Array(typed)) => {
+			for (raw, typed) in raw.iter().zip(typed) {
+				missing_keys(raw, typed, &format!("{}[]", path), missing);
+			}
+		}
+		// the list of spans is kept as a map by span ID
+		(Value::Array(raw), Value::Object(typed)) => {
+			for raw in raw.iter() {
+				if let Some(typed) = raw.get("spanID").and_then(Value::as_str).and_then(|id| typed.get(id)) {
+					missing_keys(raw, typed, &format!("{}[]", path), missing);
+				}
+			}
+		}
+		_ => (),
+	}
+}
+
+/// Entries of a map in ascending key order, since the iteration order of a `HashMap` is different every run.
+fn sorted_by_key<'a, 'b, V>(map: &'b HashMap<&'a str, V>) -> Vec<(&'a str, &'b V)> {
+	let mut entries: Vec<_> = map.iter().map(|(k, v)| (*k, v)).collect();
+	entries.sort_unstable_by_key(|(k, _)| *k);
+	entries
+}
+
+fn serialize_hashmap_sorted<S, V>(map: &HashMap<&str, V>, serializer: S) -> Result<S::Ok, S::Error>
+where
+	S: Serializer,
+	V: Serialize,
+{
+	serializer.collect_map(sorted_by_key(map))
+}
+
 impl<'a> TraceObject<'a> {
 	/// Gets a span that corresponds to the parent of the given id.
-	pub fn get_parent(&self, id: &'a str) -> Option<&'a Span> {
+	pub fn get_parent(&self, id: &str) -> Option<&Span<'a>> {
+		self.spans.get(id).and_then(|s| {
+			let parent_span = self.parent_in_trace(s)?;
+			self.spans.get(parent_span)
+		})
+	}
+
+	/// Gets all parents of the span with the given id that belong to this trace.
+	pub fn get_parents(&self, id: &str) -> Vec<&Span<'a>> {
+		let span = match self.spans.get(id) {
+			Some(span) => span,
+			None => return Vec::new(),
+		};
+		span.parent_references()
+			.filter(|r| r.trace_id == self.trace_id)
+			.filter_map(|r| self.spans.get(r.span_id))
+			.collect()
+	}
+
+	/// Get the ID of the parent of `span`, unless the parent belongs to a different trace.
+	pub fn parent_in_trace(&self, span: &Span<'a>) -> Option<&'a str> {
+		span.parent_reference().filter(|r| r.trace_id == self.trace_id).map(|r| r.span_id)
+	}
+
+	/// References from spans of this trace to spans of other traces, alongside the ID of the referencing span.
+	pub fn cross_trace_references(&self) -> Vec<(&'a str, &Reference<'a>)> {
+		let mut references: Vec<_> = self
+			.spans
+			.values()
+			.flat_map(|s| s.references.iter().map(move |r| (s.span_id, r)))
+			.filter(|(_, r)| r.trace_id != self.trace_id)
+			.collect();
+		references.sort_unstable_by_key(|(id, r)| (*id, r.span_id));
+		references
+	}
+
+	/// Name of the service that reported `span`.
+	pub fn service_name(&self, span: &Span<'_>) -> Option<&'a str> {
+		self.processes.get(span.process_id).map(|p| p.service_name)
+	}
+
+	/// Processes of this trace, ordered by process ID.
+	/// Use this rather than iterating over `processes` wherever the order may affect the output.
+	pub fn processes_sorted(&self) -> Vec<(&'a str, &Process<'a>)> {
+		sorted_by_key(&self.processes)
+	}
+
+	/// Warnings Jaeger reported for this trace and for each of its spans.
+	pub fn warnings(&self) -> impl Iterator<Item = &'a str> + '_ {
+		let spans = self.spans.values().flat_map(|s| s.warnings.iter().flatten());
+		self.warnings.iter().flatten().chain(spans).copied()
+	}
+
+	/// The earliest start time of any span in this trace, in microseconds.
+	pub fn start_time(&self) -> Option<usize> {
+		self.spans.values().map(|s| s.start_time).min()
+	}
+
+	/// Time from the start of the earliest span to the end of the latest span in this trace,
+	/// in the unit of the span durations.
+	pub fn duration(&self) -> Option<f64> {
+		let end = self.spans.values().map(|s| s.start_time as f64 + s.duration).reduce(f64::max)?;
+		Some(end - self.start_time()? as f64)
+	}
+
+	/// Whether this trace contains its root span.
+	/// A root span is one that does not reference any parent in the same trace.
+	/// Jaeger may return a trace without its root if the root fell outside of the queried window.
+	pub fn has_root(&self) -> bool {
+		self.root().is_some()
+	}
+
+	/// The earliest span of this trace that does not reference any parent in the same trace, if any.
+	pub fn root(&self) -> Option<&Span<'a>> {
+		self.spans.values().filter(|s| self.parent_in_trace(s).is_none()).min_by_key(|s| (s.start_time, s.span_id))
+	}
+
+	/// Whether any span in this trace has a tag with the key and value of `filter`.
+	pub fn has_tag(&self, filter: &TagFilter) -> bool {
+		self.spans.values().any(|s| s.get_tag(&filter.key).map(|t| t.value() == filter.value).unwrap_or(false))
+	}
+
+	/// Whether the operation name or a tag value of any span in this trace matches `filter`.
+	pub fn matches(&self, filter: &Regex) -> bool {
 		self.spans
-			.get(id)
-			.map(|s| {
-				let parent_span = s.parent_span_id()?;
-				self.spans.get(parent_span)
-			})
-			.flatten()
+			.values()
+			.any(|s| filter.is_match(s.operation_name) || s.tags.iter().any(|t| filter.is_match(&t.value())))
+	}
+
+	/// Keys of the tags present on every span of this trace.
+	/// A trace without spans has no common tags.
+	pub fn common_tags(&self) -> BTreeSet<&'a str> {
+		let mut spans = self.spans.values();
+		let first = match spans.next() {
+			Some(s) => s.tags.iter().map(|t| t.key).collect(),
+			None => return BTreeSet::new(),
+		};
+		spans
+			.fold(first, |common: BTreeSet<&'a str>, s| common.into_iter().filter(|k| s.get_tag(k).is_some()).collect())
+	}
+
+	/// Keys of the tags present on any span of this trace.
+	pub fn any_tags(&self) -> BTreeSet<&'a str> {
+		self.spans.values().flat_map(|s| s.tags.iter().map(|t| t.key)).collect()
+	}
+
+	/// Add the service name of the process of each span to the span, so it is output alongside the `processID`.
+	pub fn resolve_processes(&mut self) {
+		for span in self.spans.values_mut() {
+			span.service_name = self.processes.get(span.process_id).map(|p| p.service_name);
+		}
+	}
+
+	/// Add the duration of each span in a readable unit, like `1.5s`, next to the raw duration in microseconds.
+	pub fn humanize_durations(&mut self) {
+		for span in self.spans.values_mut() {
+			span.duration_human = Some(format_duration(span.duration));
+		}
+	}
+
+	/// Replace the values of all span and process tags under any of `keys` with [`REDACTED`].
+	pub fn redact(&mut self, keys: &[&str]) {
+		for span in self.spans.values_mut() {
+			span.tags.iter_mut().filter(|t| keys.contains(&t.key)).for_each(Tag::redact);
+		}
+		for process in self.processes.values_mut() {
+			process.tags.iter_mut().filter(|t| keys.contains(&t.key)).for_each(Tag::redact);
+		}
 	}
 }
 
@@ -82,25 +304,217 @@ pub struct Span<'a> {
 	#[serde(rename = "startTime")]
 	pub start_time: usize,
 	pub duration: f64,
+	/// `duration` in a readable unit, only set by [`TraceObject::humanize_durations`]
+	#[serde(rename = "durationHuman", skip_deserializing, skip_serializing_if = "Option::is_none")]
+	pub duration_human: Option<String>,
 	#[serde(borrow)]
 	pub tags: Vec<Tag<'a>>,
-	pub logs: Vec<serde_json::Value>, // FIXME: not sure what an actual 'log' looks like
+	#[serde(borrow)]
+	pub logs: Vec<Log<'a>>,
 	#[serde(rename = "processID")]
 	pub process_id: &'a str,
+	/// service of the process of this span, only set by [`TraceObject::resolve_processes`]
+	#[serde(rename = "serviceName", skip_deserializing, skip_serializing_if = "Option::is_none")]
+	pub service_name: Option<&'a str>,
 	#[serde(borrow)]
 	pub warnings: Option<Vec<&'a str>>,
 }
 
 impl<'a> Span<'a> {
 	/// get a tag under `key`
-	pub fn get_tag(&self, key: &str) -> Option<&'a Tag> {
+	pub fn get_tag(&self, key: &str) -> Option<&'a Tag<'_>> {
 		self.tags.iter().find(|t| t.key == key)
 	}
 
+	/// Whether the sampled bit is set in the flags of this span.
+	pub fn is_sampled(&self) -> bool {
+		self.flags.map(|f| f & SAMPLED_FLAG != 0).unwrap_or(false)
+	}
+
+	/// Timestamps of the logs attached to this span, in microseconds.
+	pub fn log_timestamps(&self) -> Vec<usize> {
+		self.logs.iter().map(|l| l.timestamp).collect()
+	}
+
+	/// Logs of this span logged from `start` up to and including `end`, as Unix timestamps in microseconds.
+	pub fn logs_between(&self, start: usize, end: usize) -> Vec<&Log<'a>> {
+		self.logs.iter().filter(|l| (start..=end).contains(&l.timestamp)).collect()
+	}
+
+	/// The duration of this span in microseconds, as measured by `source`.
+	/// Sources that rely on logs fall back to the full span duration if the span has no logs.
+	pub fn duration_from(&self, source: DurationSource) -> f64 {
+		let timestamps = self.log_timestamps();
+		let (first, last) = match (timestamps.iter().min(), timestamps.iter().max()) {
+			(Some(first), Some(last)) => (*first as f64, *last as f64),
+			_ => return self.duration,
+		};
+		match source {
+			DurationSource::Span => self.duration,
+			DurationSource::FirstLogToEnd => self.start_time as f64 + self.duration - first,
+			DurationSource::LogSpan => last - first,
+		}
+	}
+
+	/// Get the ID of the span in the same trace this span follows from:
+	/// a span that caused this span without being its parent.
+	pub fn follows_from(&self) -> Option<&'a str> {
+		let reference = self.references.iter().find(|r| r.ref_type == "FOLLOWS_FROM" && r.trace_id == self.trace_id);
+		reference.map(|r| r.span_id)
+	}
+
 	/// Get the ID to the parent of this span.
 	pub fn parent_span_id(&self) -> Option<&'a str> {
-		let child = self.references.iter().find(|r| r.ref_type == "CHILD_OF");
-		child.map(|c| c.span_id)
+		self.parent_reference().map(|r| r.span_id)
+	}
+
+	/// Get the reference to the parent of this span. If the span has several parents, this is the first one.
+	/// The parent may belong to a different trace, see [`TraceObject::parent_in_trace`].
+	pub fn parent_reference(&self) -> Option<&Reference<'a>> {
+		self.parent_references().next()
+	}
+
+	/// Get the references to all parents of this span.
+	pub fn parent_references(&self) -> impl Iterator<Item = &Reference<'a>> {
+		self.references.iter().filter(|r| r.ref_type == "CHILD_OF")
+	}
+
+	/// Get the IDs of all parents of this span.
+	pub fn parent_span_ids(&self) -> Vec<&'a str> {
+		self.parent_references().map(|r| r.span_id).collect()
+	}
+}
+
+/// Where the duration of a span is measured from.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DurationSource {
+	/// The `duration` field of the span: from span start to span end.
+	#[default]
+	Span,
+	/// From the first log event of the span to the span end.
+	FirstLogToEnd,
+	/// From the first log event of the span to the last log event of the span.
+	LogSpan,
+}
+
+impl FromStr for DurationSource {
+	type Err = Error;
+	fn from_str(s: &str) -> Result<Self, Error> {
+		match s {
+			"span" => Ok(DurationSource::Span),
+			"first-log-to-end" => Ok(DurationSource::FirstLogToEnd),
+			"log-span" => Ok(DurationSource::LogSpan),
+			_ => bail!("unknown duration source `{}`. Expected one of `span`, `first-log-to-end`, `log-span`", s),
+		}
+	}
+}
+
+/// Order of the traces in the output.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TraceOrder {
+	/// By the start time of the earliest span.
+	#[default]
+	StartTime,
+	/// By the time from the start of the earliest span to the end of the latest span.
+	Duration,
+	/// By the number of spans.
+	SpanCount,
+}
+
+impl TraceOrder {
+	/// Sort `traces` in this order, ascending unless `descending`.
+	pub fn sort(self, traces: &mut [TraceObject<'_>], descending: bool) {
+		traces.sort_by(|a, b| {
+			let ordering = match self {
+				TraceOrder::StartTime => a.start_time().cmp(&b.start_time()),
+				TraceOrder::Duration => a.duration().partial_cmp(&b.duration()).unwrap_or(Ordering::Equal),
+				TraceOrder::SpanCount => a.spans.len().cmp(&b.spans.len()),
+			};
+			if descending {
+				ordering.reverse()
+			} else {
+				ordering
+			}
+		});
+	}
+}
+
+impl FromStr for TraceOrder {
+	type Err = Error;
+	fn from_str(s: &str) -> Result<Self, Error> {
+		match s {
+			"start-time" => Ok(TraceOrder::StartTime),
+			"duration" => Ok(TraceOrder::Duration),
+			"span-count" => Ok(TraceOrder::SpanCount),
+			_ => bail!("unknown sort order `{}`. Expected one of `start-time`, `duration`, `span-count`", s),
+		}
+	}
+}
+
+/// Unit of the `duration` field of the spans reported by the source.
+/// Jaeger reports microseconds, but some custom agents report other units.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DurationUnit {
+	#[default]
+	Micros,
+	Millis,
+	Nanos,
+}
+
+impl DurationUnit {
+	/// Convert a duration in this unit to milliseconds.
+	pub fn to_millis(self, duration: f64) -> f64 {
+		match self {
+			DurationUnit::Micros => duration / 1000f64,
+			DurationUnit::Millis => duration,
+			DurationUnit::Nanos => duration / 1_000_000f64,
+		}
+	}
+}
+
+impl FromStr for DurationUnit {
+	type Err = Error;
+	fn from_str(s: &str) -> Result<Self, Error> {
+		match s {
+			"us" => Ok(DurationUnit::Micros),
+			"ms" => Ok(DurationUnit::Millis),
+			"ns" => Ok(DurationUnit::Nanos),
+			_ => bail!("unknown duration unit `{}`. Expected one of `us`, `ms`, `ns`", s),
+		}
+	}
+}
+
+/// A duration in microseconds, like Jaeger reports them, in the largest unit it is at least one of:
+/// `1.5s`, `250ms` or `800µs`.
+pub fn format_duration(micros: f64) -> String {
+	let (value, unit) = match micros.abs() {
+		m if m >= 1_000_000f64 => (micros / 1_000_000f64, "s"),
+		m if m >= 1000f64 => (micros / 1000f64, "ms"),
+		_ => (micros, "µs"),
+	};
+	let value = format!("{:.3}", value);
+	format!("{}{}", value.trim_end_matches('0').trim_end_matches('.'), unit)
+}
+
+/// Point in time parsed from an RFC3339 timestamp like `2021-03-19T10:30:00Z`, in Unix microseconds as Jaeger expects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timestamp(usize);
+
+impl Timestamp {
+	pub fn micros(self) -> usize {
+		self.0
+	}
+}
+
+impl FromStr for Timestamp {
+	type Err = Error;
+	fn from_str(s: &str) -> Result<Self, Error> {
+		let time = chrono::DateTime::parse_from_rfc3339(s)
+			.map_err(|e| anyhow::anyhow!("invalid RFC3339 timestamp `{}`: {}. Example: 2021-03-19T10:30:00Z", s, e))?;
+		if time.timestamp() < 0 {
+			bail!("timestamp `{}` is before 1970", s);
+		}
+		Ok(Timestamp(time.timestamp() as usize * 1_000_000 + time.timestamp_subsec_micros() as usize))
 	}
 }
 
@@ -114,9 +528,51 @@ pub struct Tag<'a> {
 }
 
 impl<'a> Tag<'a> {
+	pub fn key(&self) -> &'a str {
+		self.key
+	}
+
 	pub fn value(&self) -> String {
 		self.value.to_string()
 	}
+
+	/// The value of this tag, with the type reported by Jaeger.
+	pub fn typed_value(&self) -> &TagValue<'a> {
+		&self.value
+	}
+
+	/// Mask the value of this tag.
+	pub fn redact(&mut self) {
+		self.ty = "string";
+		self.value = TagValue::String(REDACTED);
+	}
+}
+
+/// A tag a span must have, parsed from `key=value`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagFilter {
+	key: String,
+	value: String,
+}
+
+impl TagFilter {
+	pub fn key(&self) -> &str {
+		&self.key
+	}
+
+	pub fn value(&self) -> &str {
+		&self.value
+	}
+}
+
+impl FromStr for TagFilter {
+	type Err = Error;
+	fn from_str(s: &str) -> Result<Self, Error> {
+		match s.split_once('=') {
+			Some((key, value)) if !key.is_empty() => Ok(Self { key: key.to_string(), value: value.to_string() }),
+			_ => bail!("expected `key=value`, found `{}`", s),
+		}
+	}
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -125,14 +581,17 @@ pub enum TagValue<'a> {
 	String(&'a str),
 	Boolean(bool),
 	Number(usize),
+	/// Must come after `Number` so that integers are not parsed as floats.
+	Float(f64),
 }
 
-impl<'a> ToString for TagValue<'a> {
-	fn to_string(&self) -> String {
+impl<'a> fmt::Display for TagValue<'a> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
 		match self {
-			TagValue::String(s) => s.to_string(),
-			TagValue::Boolean(b) => b.to_string(),
-			TagValue::Number(n) => n.to_string(),
+			TagValue::String(s) => write!(f, "{}", s),
+			TagValue::Boolean(b) => write!(f, "{}", b),
+			TagValue::Number(n) => write!(f, "{}", n),
+			TagValue::Float(n) => write!(f, "{}", n),
 		}
 	}
 }
@@ -145,6 +604,40 @@ pub struct Process<'a> {
 	tags: Vec<Tag<'a>>,
 }
 
+impl<'a> Process<'a> {
+	pub fn service_name(&self) -> &'a str {
+		self.service_name
+	}
+
+	pub fn tags(&self) -> &[Tag<'a>] {
+		&self.tags
+	}
+}
+
+/// An event logged during a span.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Log<'a> {
+	/// Unix timestamp in microseconds.
+	pub timestamp: usize,
+	#[serde(borrow)]
+	pub fields: Vec<Tag<'a>>,
+}
+
+/// Calls from one service to another, as reported by the Jaeger dependencies API.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Dependency<'a> {
+	pub parent: &'a str,
+	pub child: &'a str,
+	#[serde(rename = "callCount")]
+	pub call_count: usize,
+}
+
+impl fmt::Display for Dependency<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{} -> {}: {}", self.parent, self.child, self.call_count)
+	}
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Reference<'a> {
 	#[serde(rename = "refType")]
@@ -155,12 +648,62 @@ pub struct Reference<'a> {
 	span_id: &'a str,
 }
 
+impl<'a> Reference<'a> {
+	pub fn trace_id(&self) -> &'a str {
+		self.trace_id
+	}
+
+	pub fn span_id(&self) -> &'a str {
+		self.span_id
+	}
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
 	use crate::tests::*;
 	use anyhow::Error;
 
+	#[test]
+	fn should_surface_response_errors() -> Result<(), Error> {
+		let ok = r#"{"data": ["polkadot"], "total": 1, "limit": 0, "offset": 0, "errors": null}"#;
+		assert_eq!(serde_json::from_str::<RpcResponse<String>>(ok)?.try_consume()?, vec!["polkadot"]);
+
+		let failed =
+			r#"{"data": [], "total": 0, "limit": 0, "offset": 0, "errors": [{"code": 500, "msg": "storage"}]}"#;
+		let error = serde_json::from_str::<RpcResponse<String>>(failed)?.try_consume().unwrap_err();
+		assert!(error.to_string().contains("storage"), "{}", error);
+		Ok(())
+	}
+
+	#[test]
+	fn should_tell_truncated_responses() -> Result<(), Error> {
+		let truncated = r#"{"data": ["a", "b"], "total": 5, "limit": 2, "offset": 1, "errors": null}"#;
+		let response = serde_json::from_str::<RpcResponse<String>>(truncated)?;
+		assert_eq!((response.total(), response.limit(), response.offset()), (5, 2, 1));
+		assert!(response.is_truncated());
+
+		for complete in [
+			r#"{"data": ["a", "b"], "total": 2, "limit": 2, "offset": 0, "errors": null}"#,
+			r#"{"data": ["a"], "total": 5, "limit": 2, "offset": 0, "errors": null}"#,
+			r#"{"data": ["a", "b"], "total": 0, "limit": 0, "offset": 0, "errors": null}"#,
+		] {
+			assert!(!serde_json::from_str::<RpcResponse<String>>(complete)?.is_truncated(), "{}", complete);
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn should_find_dropped_fields() -> Result<(), Error> {
+		let raw: Value = serde_json::from_str(TEST_DATA)?;
+		assert_eq!(dropped_fields(&raw)?, BTreeSet::new());
+
+		let data = TEST_DATA.replacen(r#""spanID": "parent","#, r#""spanID": "parent", "childSpanIds": [],"#, 1);
+		let raw: Value = serde_json::from_str(&data)?;
+		assert_eq!(dropped_fields(&raw)?.into_iter().collect::<Vec<_>>(), vec!["spans[].childSpanIds"]);
+		Ok(())
+	}
+
 	#[test]
 	fn should_find_parents() -> Result<(), Error> {
 		let traces: TraceObject = serde_json::from_str(TEST_DATA)?;
@@ -168,4 +711,255 @@ mod tests {
 		assert_eq!(traces.get_parent("child-1").unwrap().span_id, "child-0");
 		Ok(())
 	}
+
+	#[test]
+	fn should_find_all_parents() -> Result<(), Error> {
+		let data = TEST_DATA.replacen(
+			r#""spanID": "child-0"
+					}"#,
+			r#""spanID": "child-0"
+					},
+					{
+						"refType": "CHILD_OF",
+						"traceID": "6ga7nenJ21rhDy6Fwzjwz7KZQ5Jrii9",
+						"spanID": "parent"
+					}"#,
+			1,
+		);
+		let traces: TraceObject = serde_json::from_str(&data)?;
+		assert_eq!(traces.spans["child-1"].parent_span_ids(), vec!["child-0", "parent"]);
+		assert_eq!(traces.spans["child-1"].parent_span_id(), Some("child-0"));
+		let parents: Vec<&str> = traces.get_parents("child-1").iter().map(|s| s.span_id).collect();
+		assert_eq!(parents, vec!["child-0", "parent"]);
+		assert_eq!(traces.get_parent("child-1").unwrap().span_id, "child-0");
+		Ok(())
+	}
+
+	#[test]
+	fn should_detect_missing_root() -> Result<(), Error> {
+		let mut traces: TraceObject = serde_json::from_str(TEST_DATA)?;
+		assert!(traces.has_root());
+		assert_eq!(traces.root().map(|s| s.span_id), Some("parent"));
+		traces.spans.remove("parent");
+		assert!(!traces.has_root());
+		Ok(())
+	}
+
+	#[test]
+	fn should_detect_cross_trace_references() -> Result<(), Error> {
+		let data = TEST_DATA.replacen(
+			r#""traceID": "6ga7nenJ21rhDy6Fwzjwz7KZQ5Jrii9",
+						"spanID": "parent""#,
+			r#""traceID": "another-trace",
+						"spanID": "parent""#,
+			1,
+		);
+		let traces: TraceObject = serde_json::from_str(&data)?;
+		let references = traces.cross_trace_references();
+		assert_eq!(references.len(), 1);
+		assert_eq!(references[0].0, "child-0");
+		assert_eq!(references[0].1.trace_id(), "another-trace");
+		assert_eq!(references[0].1.span_id(), "parent");
+
+		// the parent in the other trace is not mistaken for the span with the same ID in this trace
+		assert!(traces.get_parent("child-0").is_none());
+		assert!(traces.get_parent("child-1").is_some());
+
+		let traces: TraceObject = serde_json::from_str(TEST_DATA)?;
+		assert!(traces.cross_trace_references().is_empty());
+		Ok(())
+	}
+
+	#[test]
+	fn should_match_operations_and_tag_values() -> Result<(), Error> {
+		let traces: TraceObject = serde_json::from_str(TEST_DATA)?;
+		assert!(traces.matches(&Regex::new("^test")?));
+		assert!(traces.matches(&Regex::new("mick-.*")?));
+		assert!(!traces.matches(&Regex::new("otel")?));
+		Ok(())
+	}
+
+	#[test]
+	fn should_resolve_processes() -> Result<(), Error> {
+		let mut traces: TraceObject = serde_json::from_str(TEST_DATA)?;
+		assert!(serde_json::to_value(&traces)?["spans"]["parent"].get("serviceName").is_none());
+
+		traces.resolve_processes();
+		let json = serde_json::to_value(&traces)?;
+		assert_eq!(json["spans"]["parent"]["serviceName"], "polkadot-insi-testing");
+		assert_eq!(json["spans"]["parent"]["processID"], "p1");
+		Ok(())
+	}
+
+	#[test]
+	fn should_filter_by_tag() -> Result<(), Error> {
+		let traces: TraceObject = serde_json::from_str(TEST_DATA)?;
+		assert!(traces.has_tag(&"candidate-stage=4".parse()?));
+		assert!(traces.has_tag(&"otel.library.name=mick-jaeger".parse()?));
+		assert!(!traces.has_tag(&"candidate-stage=5".parse()?));
+		assert!(!traces.has_tag(&"candidate-hash=4".parse()?));
+		assert!("candidate-stage".parse::<TagFilter>().is_err());
+		assert!("=4".parse::<TagFilter>().is_err());
+		Ok(())
+	}
+
+	#[test]
+	fn should_print_dependencies() -> Result<(), Error> {
+		let data = r#"{"parent": "polkadot-validator", "child": "polkadot-collator", "callCount": 12}"#;
+		let dependency: Dependency = serde_json::from_str(data)?;
+		assert_eq!(dependency.to_string(), "polkadot-validator -> polkadot-collator: 12");
+		Ok(())
+	}
+
+	#[test]
+	fn should_sort_traces() -> Result<(), Error> {
+		let slow = TEST_DATA.replace(r#""duration": 150"#, r#""duration": 300"#);
+		let late = TEST_DATA.replace("1616995411000000", "1616995412000000");
+		let mut traces: Vec<TraceObject> =
+			vec![serde_json::from_str(&late)?, serde_json::from_str(&slow)?, serde_json::from_str(TEST_DATA)?];
+		assert_eq!(traces[0].duration(), Some(150.0));
+		assert_eq!(traces[1].duration(), Some(300.0));
+
+		TraceOrder::Duration.sort(&mut traces, true);
+		assert_eq!(traces[0].duration(), Some(300.0));
+		TraceOrder::StartTime.sort(&mut traces, false);
+		assert_eq!(traces[2].start_time(), Some(1616995412000000));
+		TraceOrder::StartTime.sort(&mut traces, true);
+		assert_eq!(traces[0].start_time(), Some(1616995412000000));
+		assert!("size".parse::<TraceOrder>().is_err());
+		Ok(())
+	}
+
+	#[test]
+	fn should_format_durations() -> Result<(), Error> {
+		assert_eq!(format_duration(1_500_000f64), "1.5s");
+		assert_eq!(format_duration(250_000f64), "250ms");
+		assert_eq!(format_duration(800f64), "800µs");
+		assert_eq!(format_duration(1234.5678), "1.235ms");
+		assert_eq!(format_duration(0f64), "0µs");
+
+		let mut trace: TraceObject = serde_json::from_str(TEST_DATA)?;
+		assert!(!serde_json::to_string(&trace)?.contains("durationHuman"));
+		trace.humanize_durations();
+		let json = serde_json::to_value(&trace)?;
+		let span = &json["spans"]["parent"];
+		assert_eq!((span["duration"].as_f64(), span["durationHuman"].as_str()), (Some(150f64), Some("150µs")));
+		Ok(())
+	}
+
+	#[test]
+	fn should_parse_rfc3339_timestamps() -> Result<(), Error> {
+		assert_eq!("2021-03-19T10:30:00Z".parse::<Timestamp>()?.micros(), 1_616_149_800_000_000);
+		assert_eq!("2021-03-19T11:30:00.000250+01:00".parse::<Timestamp>()?.micros(), 1_616_149_800_000_250);
+		assert!("2021-03-19".parse::<Timestamp>().is_err());
+		assert!("1969-12-31T23:59:59Z".parse::<Timestamp>().is_err());
+		Ok(())
+	}
+
+	#[test]
+	fn should_compute_duration_from_source() -> Result<(), Error> {
+		let traces: TraceObject = serde_json::from_str(TEST_DATA)?;
+		let mut span = traces.spans.get("parent").unwrap().clone();
+		assert_eq!(span.duration_from(DurationSource::FirstLogToEnd), 150.0);
+
+		span.logs = vec![
+			Log { timestamp: 1616995411000040, fields: Vec::new() },
+			Log { timestamp: 1616995411000100, fields: Vec::new() },
+		];
+		assert_eq!(span.duration_from(DurationSource::Span), 150.0);
+		assert_eq!(span.duration_from(DurationSource::FirstLogToEnd), 110.0);
+		assert_eq!(span.duration_from(DurationSource::LogSpan), 60.0);
+		Ok(())
+	}
+
+	#[test]
+	fn should_find_logs_between() -> Result<(), Error> {
+		let data = TEST_DATA.replacen(
+			r#""logs": [],"#,
+			r#""logs": [
+				{ "timestamp": 1616995411000040, "fields": [{ "key": "event", "type": "string", "value": "seconded" }] },
+				{ "timestamp": 1616995411000100, "fields": [{ "key": "event", "type": "string", "value": "backed" }] }
+			],"#,
+			1,
+		);
+		let traces: TraceObject = serde_json::from_str(&data)?;
+		let span = &traces.spans["parent"];
+		let logs = span.logs_between(1616995411000000, 1616995411000050);
+		assert_eq!(logs.len(), 1);
+		assert_eq!(logs[0].fields[0].value(), "seconded");
+		assert_eq!(span.logs_between(1616995411000040, 1616995411000100).len(), 2);
+		assert!(span.logs_between(1616995411000101, 1616995411000200).is_empty());
+		Ok(())
+	}
+
+	#[test]
+	fn should_decode_sampled_flag() -> Result<(), Error> {
+		let traces: TraceObject = serde_json::from_str(TEST_DATA)?;
+		let mut span = traces.spans.get("parent").unwrap().clone();
+		assert!(!span.is_sampled());
+		span.flags = Some(1);
+		assert!(span.is_sampled());
+		span.flags = Some(2);
+		assert!(!span.is_sampled());
+		span.flags = Some(3);
+		assert!(span.is_sampled());
+		Ok(())
+	}
+
+	#[test]
+	fn should_parse_float_tags() -> Result<(), Error> {
+		let tag: Tag = serde_json::from_str(r#"{"key": "sampler.param", "type": "float64", "value": 0.25}"#)?;
+		assert!(matches!(tag.value, TagValue::Float(v) if v == 0.25));
+		assert_eq!(tag.value(), "0.25");
+		assert_eq!(serde_json::to_string(&tag)?, r#"{"key":"sampler.param","type":"float64","value":0.25}"#);
+
+		let tag: Tag = serde_json::from_str(r#"{"key": "count", "type": "int64", "value": 4}"#)?;
+		assert!(matches!(tag.value, TagValue::Number(4)));
+		Ok(())
+	}
+
+	#[test]
+	fn should_summarize_tags() -> Result<(), Error> {
+		let mut traces: TraceObject = serde_json::from_str(TEST_DATA)?;
+		traces.spans.get_mut("child-2").unwrap().tags.retain(|t| t.key != "candidate-stage");
+
+		let common = traces.common_tags();
+		assert!(common.contains("otel.library.name"));
+		assert!(!common.contains("candidate-stage"));
+		assert!(traces.any_tags().contains("candidate-stage"));
+
+		traces.spans.clear();
+		assert!(traces.common_tags().is_empty());
+		assert!(traces.any_tags().is_empty());
+		Ok(())
+	}
+
+	#[test]
+	fn should_redact_tags() -> Result<(), Error> {
+		let mut traces: TraceObject = serde_json::from_str(TEST_DATA)?;
+		traces.redact(&["otel.library.name"]);
+		let span = traces.spans.get("child-0").unwrap();
+		assert_eq!(span.get_tag("otel.library.name").unwrap().value(), REDACTED);
+		assert_eq!(span.get_tag("otel.library.version").unwrap().value(), "0.1.4");
+		Ok(())
+	}
+
+	#[test]
+	fn should_order_processes_by_id() -> Result<(), Error> {
+		let data = TEST_DATA.replace(
+			r#""processes": {"#,
+			r#""processes": { "p3": { "serviceName": "c", "tags": [] }, "p2": { "serviceName": "b", "tags": [] },"#,
+		);
+		let ids = || -> Result<Vec<String>, Error> {
+			let traces: TraceObject = serde_json::from_str(&data)?;
+			Ok(traces.processes_sorted().into_iter().map(|(id, _)| id.to_string()).collect())
+		};
+		assert_eq!(ids()?, vec!["p1", "p2", "p3"]);
+		assert_eq!(ids()?, ids()?);
+
+		let traces: TraceObject = serde_json::from_str(&data)?;
+		let json = serde_json::to_string(&traces)?;
+		assert!(json.find(r#""p1""#) < json.find(r#""p2""#) && json.find(r#""p2""#) < json.find(r#""p3""#));
+		Ok(())
+	}
 }
@@ -0,0 +1,98 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of dot-jaeger.
+
+// dot-jaeger is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// dot-jaeger is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with dot-jaeger.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Rate-limited logging, so that an error repeating every cycle does not flood the logs.
+
+use std::{
+	fmt::Display,
+	time::{Duration, Instant},
+};
+
+/// Logs a repeating message at most once per `interval`,
+/// keeping count of how many times it was suppressed in the meantime.
+pub struct RateLimitedLog {
+	interval: Duration,
+	/// the last message logged, and when it was logged
+	last: Option<(String, Instant)>,
+	suppressed: usize,
+}
+
+impl RateLimitedLog {
+	pub fn new(interval: Duration) -> Self {
+		Self { interval, last: None, suppressed: 0 }
+	}
+
+	/// Log `msg` as an error, unless the same message was already logged within the interval.
+	pub fn error(&mut self, msg: impl Display) {
+		if let Some(line) = self.check(msg.to_string(), Instant::now()) {
+			log::error!("{}", line);
+		}
+	}
+
+	/// Returns the line that should be logged for `msg` at time `now`, if any.
+	fn check(&mut self, msg: String, now: Instant) -> Option<String> {
+		match self.last.as_ref() {
+			Some((last, at)) if *last == msg && now.duration_since(*at) < self.interval => {
+				self.suppressed += 1;
+				None
+			}
+			Some((last, at)) if *last == msg && self.suppressed > 0 => {
+				let line = format!(
+					"{} (repeated {} more times in the last {:?})",
+					msg,
+					self.suppressed,
+					now.duration_since(*at)
+				);
+				self.suppressed = 0;
+				self.last = Some((msg, now));
+				Some(line)
+			}
+			_ => {
+				let line = if self.suppressed > 0 {
+					format!("{} (previous error was repeated {} more times)", msg, self.suppressed)
+				} else {
+					msg.clone()
+				};
+				self.suppressed = 0;
+				self.last = Some((msg, now));
+				Some(line)
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn should_log_repeated_errors_once_per_interval() {
+		let mut log = RateLimitedLog::new(Duration::from_secs(60));
+		let start = Instant::now();
+		let error = || "Jaeger is unreachable".to_string();
+
+		assert_eq!(log.check(error(), start), Some(error()));
+		assert_eq!(log.check(error(), start + Duration::from_secs(1)), None);
+		assert_eq!(log.check(error(), start + Duration::from_secs(30)), None);
+
+		let line = log.check(error(), start + Duration::from_secs(61)).unwrap();
+		assert!(line.starts_with("Jaeger is unreachable (repeated 2 more times"));
+		assert_eq!(log.check(error(), start + Duration::from_secs(62)), None);
+
+		// a different error is logged immediately
+		assert!(log.check("another error".to_string(), start + Duration::from_secs(63)).is_some());
+	}
+}
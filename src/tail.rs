@@ -0,0 +1,51 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of dot-jaeger.
+
+// dot-jaeger is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// dot-jaeger is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with dot-jaeger.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Keeps track of traces already seen while continuously polling Jaeger.
+
+use crate::primitives::TraceObject;
+use std::collections::HashSet;
+
+/// Trace IDs seen across polls.
+#[derive(Default)]
+pub struct SeenTraces {
+	seen: HashSet<String>,
+}
+
+impl SeenTraces {
+	/// Keep only the traces that were not seen in any previous poll, and remember them.
+	pub fn unseen<'a>(&mut self, traces: Vec<TraceObject<'a>>) -> Vec<TraceObject<'a>> {
+		traces.into_iter().filter(|t| self.seen.insert(t.trace_id.to_string())).collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::tests::*;
+	use anyhow::Error;
+
+	#[test]
+	fn should_not_repeat_seen_traces() -> Result<(), Error> {
+		let mut seen = SeenTraces::default();
+		let first_poll = vec![serde_json::from_str(TEST_DATA)?];
+		assert_eq!(seen.unseen(first_poll).len(), 1);
+
+		let second_poll = vec![serde_json::from_str(TEST_DATA)?];
+		assert!(seen.unseen(second_poll).is_empty());
+		Ok(())
+	}
+}
@@ -0,0 +1,137 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of dot-jaeger.
+
+// dot-jaeger is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// dot-jaeger is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with dot-jaeger.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Conversion of Jaeger traces to the span JSON of the v2 API of Zipkin.
+
+use crate::primitives::{Log, Span, TraceObject};
+use itertools::Itertools;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ZipkinSpan<'a> {
+	pub trace_id: String,
+	pub id: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub parent_id: Option<String>,
+	pub name: &'a str,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub kind: Option<&'static str>,
+	/// Unix timestamp in microseconds.
+	pub timestamp: usize,
+	/// In microseconds.
+	pub duration: u64,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub local_endpoint: Option<Endpoint<'a>>,
+	pub annotations: Vec<Annotation>,
+	/// Zipkin only has string tags.
+	pub tags: BTreeMap<&'a str, String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Endpoint<'a> {
+	pub service_name: &'a str,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Annotation {
+	pub timestamp: usize,
+	pub value: String,
+}
+
+/// Convert the spans of `traces` to Zipkin spans, in the order of the CSV output.
+pub fn export<'a>(traces: &[TraceObject<'a>]) -> Vec<ZipkinSpan<'a>> {
+	let mut spans = Vec::new();
+	for trace in traces.iter() {
+		let mut sorted: Vec<&Span> = trace.spans.values().collect();
+		sorted.sort_unstable_by_key(|s| (s.start_time, s.span_id));
+		spans.extend(sorted.into_iter().map(|s| to_zipkin_span(trace, s)));
+	}
+	spans
+}
+
+fn to_zipkin_span<'a>(trace: &TraceObject<'a>, span: &Span<'a>) -> ZipkinSpan<'a> {
+	let kind = span.get_tag("span.kind").and_then(|t| match t.value().as_str() {
+		"client" => Some("CLIENT"),
+		"server" => Some("SERVER"),
+		"producer" => Some("PRODUCER"),
+		"consumer" => Some("CONSUMER"),
+		_ => None,
+	});
+	ZipkinSpan {
+		trace_id: trace_id(span.trace_id),
+		id: hex_id(span.span_id, 16),
+		// Zipkin has no references across traces.
+		parent_id: trace.parent_in_trace(span).map(|id| hex_id(id, 16)),
+		name: span.operation_name,
+		kind,
+		timestamp: span.start_time,
+		duration: span.duration.round() as u64,
+		local_endpoint: trace.service_name(span).map(|service_name| Endpoint { service_name }),
+		annotations: span.logs.iter().map(to_annotation).collect(),
+		tags: span.tags.iter().map(|t| (t.key(), t.value())).collect(),
+	}
+}
+
+/// Annotations of Zipkin are plain strings: the `event` field of the log, or else all fields as `key=value`.
+fn to_annotation(log: &Log<'_>) -> Annotation {
+	let value = match log.fields.iter().find(|f| f.key() == "event") {
+		Some(event) => event.value(),
+		None => log.fields.iter().map(|f| format!("{}={}", f.key(), f.value())).join(" "),
+	};
+	Annotation { timestamp: log.timestamp, value }
+}
+
+/// Zipkin accepts 64-bit and 128-bit trace IDs.
+fn trace_id(id: &str) -> String {
+	hex_id(id, if id.len() <= 16 { 16 } else { 32 })
+}
+
+/// Jaeger drops the leading zeros of hex IDs, Zipkin expects them padded to `width` and in lowercase.
+fn hex_id(id: &str, width: usize) -> String {
+	format!("{:0>width$}", id.to_lowercase(), width = width)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::tests::*;
+	use anyhow::Error;
+
+	#[test]
+	fn should_export_spans_to_zipkin() -> Result<(), Error> {
+		let traces: Vec<TraceObject> = vec![serde_json::from_str(TEST_DATA)?];
+		let spans = serde_json::to_value(export(&traces))?;
+		let spans = spans.as_array().unwrap();
+		assert_eq!(spans.len(), 4);
+
+		let child = spans.iter().find(|s| s["id"] == "000000000child-0").unwrap();
+		assert_eq!(child["traceId"], "06ga7nenj21rhdy6fwzjwz7kzq5jrii9");
+		assert_eq!(child["parentId"], "0000000000parent");
+		assert_eq!(child["name"], "testop");
+		assert_eq!(child["timestamp"], 1616995411000000u64);
+		assert_eq!(child["duration"], 150);
+		assert_eq!(child["localEndpoint"]["serviceName"], "polkadot-insi-testing");
+		assert_eq!(child["tags"]["otel.library.name"], "mick-jaeger");
+
+		let root = spans.iter().find(|s| s["id"] == "0000000000parent").unwrap();
+		assert!(root.get("parentId").is_none());
+		assert_eq!(trace_id("a1b2"), "000000000000a1b2");
+		Ok(())
+	}
+}